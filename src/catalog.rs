@@ -0,0 +1,530 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::BirdError;
+
+/// How many similar birds to suggest when a chosen one isn't in the taxonomy
+const SUGGESTIONS: usize = 3;
+
+/// One entry of the eBird taxonomy
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Bird {
+    #[serde(rename(deserialize = "sciName"))]
+    pub scientific_name: String,
+    #[serde(rename(deserialize = "comName"))]
+    pub common_name: String,
+    #[serde(rename(deserialize = "speciesCode"))]
+    pub species_code: String,
+    #[serde(rename(deserialize = "category"))]
+    pub category: String,
+    #[serde(rename(deserialize = "taxonOrder"))]
+    pub taxon_order: f32,
+    #[serde(rename(deserialize = "bandingCodes"))]
+    pub banding_codes: Option<Vec<String>>,
+    #[serde(rename(deserialize = "comNameCodes"))]
+    pub com_name_codes: Option<Vec<String>>,
+    #[serde(rename(deserialize = "sciNameCodes"))]
+    pub sci_name_codes: Option<Vec<String>>,
+    #[serde(rename(deserialize = "order"))]
+    pub order: Option<String>,
+    #[serde(rename(deserialize = "familyComName"))]
+    pub family_com_name: Option<String>,
+    #[serde(rename(deserialize = "familySciName"))]
+    pub family_sci_name: Option<String>,
+    #[serde(rename(deserialize = "reportAs"))]
+    pub report_as: Option<String>,
+    #[serde(rename(deserialize = "extinct"))]
+    pub extinct: Option<bool>,
+    #[serde(rename(deserialize = "extinctYear"))]
+    pub extinct_year: Option<i32>,
+    #[serde(rename(deserialize = "familyCode"))]
+    pub family_code: Option<String>,
+}
+
+/// A particular bird to post, as given on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum BirdChoice {
+    /// eBird species code, e.g. `norcar`
+    SpeciesCode(String),
+    /// Common name, e.g. `Northern Cardinal`, in any case
+    CommonName(String),
+}
+
+/// The eBird taxonomy, parsed once and indexed for lookups
+#[derive(Debug, Clone, Default)]
+pub struct BirdCatalog {
+    birds: Vec<Bird>,
+    /// Species code to position in `birds`
+    species: HashMap<String, usize>,
+    /// Lowercased family code, common name, and scientific name to positions in `birds`
+    families: HashMap<String, Vec<usize>>,
+}
+
+impl BirdCatalog {
+    /// Read and parse a local copy of the taxonomy
+    pub fn load(path: impl AsRef<Path>) -> Result<BirdCatalog, BirdError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        BirdCatalog::parse(&contents)
+            .map_err(|e| BirdError::Parse(format!("'{}' is not valid taxonomy JSON: {}", path.display(), e)))
+    }
+
+    /// Parse the taxonomy as served by eBird's `ref/taxonomy/ebird` endpoint
+    pub fn parse(json: &str) -> Result<BirdCatalog, serde_json::Error> {
+        serde_json::from_str(json).map(BirdCatalog::new)
+    }
+
+    /// Index an already parsed taxonomy
+    pub fn new(birds: Vec<Bird>) -> BirdCatalog {
+        let mut species = HashMap::new();
+        let mut families: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, b) in birds.iter().enumerate() {
+            species.insert(b.species_code.clone(), i);
+            let names = [&b.family_code, &b.family_com_name, &b.family_sci_name];
+            for name in names.into_iter().flatten() {
+                let positions = families.entry(name.to_lowercase()).or_default();
+                // A family whose code and name are the same shouldn't list its birds twice
+                if positions.last() != Some(&i) {
+                    positions.push(i);
+                }
+            }
+        }
+        BirdCatalog { birds, species, families }
+    }
+
+    /// Every bird, in taxonomic order
+    pub fn birds(&self) -> &[Bird] {
+        &self.birds
+    }
+
+    pub fn len(&self) -> usize {
+        self.birds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.birds.is_empty()
+    }
+
+    /// Any bird at all, or `None` if the catalog is empty
+    pub fn random<R: Rng>(&self, rng: &mut R) -> Option<&Bird> {
+        if self.birds.is_empty() {
+            return None;
+        }
+        // Sample a u64 rather than a usize so 32 and 64-bit targets pick the same bird
+        Some(&self.birds[rng.gen_range(0..self.birds.len() as u64) as usize])
+    }
+
+    /// The bird with eBird species code `code`, e.g. `norcar`
+    pub fn by_species_code(&self, code: &str) -> Option<&Bird> {
+        self.species.get(code).map(|&i| &self.birds[i])
+    }
+
+    /// The species `bird` is reported as, e.g. Red-tailed Hawk for Red-tailed Hawk (borealis):
+    /// the entry its `report_as` names, or `bird` itself if it has none or it names no bird here
+    pub fn reported_as<'a>(&'a self, bird: &'a Bird) -> &'a Bird {
+        let Some(code) = bird.report_as.as_deref().filter(|c| *c != bird.species_code) else {
+            return bird;
+        };
+        self.by_species_code(code).unwrap_or_else(|| {
+            warn!("{} ({}) is reported as '{}', which isn't in the taxonomy", bird.common_name, bird.species_code, code);
+            bird
+        })
+    }
+
+    /// The bird whose common name is `name`, ignoring case
+    pub fn by_common_name(&self, name: &str) -> Option<&Bird> {
+        let name = name.to_lowercase();
+        self.birds.iter().find(|b| b.common_name.to_lowercase() == name)
+    }
+
+    /// The bird `choice` refers to, which has to be a species rather than e.g. a hybrid or spuh
+    pub fn choose(&self, choice: &BirdChoice) -> Result<&Bird, BirdError> {
+        let b = match choice {
+            BirdChoice::SpeciesCode(code) => self.by_species_code(code).ok_or_else(|| {
+                BirdError::Config(format!("no bird in the taxonomy has species code '{}'{}", code, self.suggest(code)))
+            })?,
+            BirdChoice::CommonName(name) => self.by_common_name(name).ok_or_else(|| {
+                BirdError::Config(format!("no bird in the taxonomy is called '{}'{}", name, self.suggest(name)))
+            })?,
+        };
+        if b.category != "species" {
+            return Err(BirdError::Config(format!("{} ({}) is a {}, not a species", b.common_name, b.species_code, b.category)));
+        }
+        Ok(b)
+    }
+
+    /// The species whose common name or species code is most like `query`, best first. A common
+    /// name containing `query` counts as an exact match.
+    pub fn closest(&self, query: &str, n: usize) -> Vec<&Bird> {
+        let query = query.trim().to_lowercase();
+        let mut scored: Vec<(usize, &Bird)> = self.birds.iter()
+            .filter(|b| b.category == "species")
+            .map(|b| {
+                let name = b.common_name.to_lowercase();
+                let distance = if name.contains(&query) {
+                    0
+                } else {
+                    edit_distance(&query, &name).min(edit_distance(&query, &b.species_code))
+                };
+                (distance, b)
+            })
+            .collect();
+        // Stable, so equally close birds stay in taxonomic order
+        scored.sort_by_key(|&(distance, _)| distance);
+        scored.into_iter().take(n).map(|(_, b)| b).collect()
+    }
+
+    /// `; did you mean ...?` listing the birds closest to `query`, or nothing if there are none
+    pub(crate) fn suggest(&self, query: &str) -> String {
+        let closest: Vec<String> = self.closest(query, SUGGESTIONS).iter()
+            .map(|b| format!("{} ({})", b.common_name, b.species_code))
+            .collect();
+        if closest.is_empty() {
+            String::new()
+        } else {
+            format!("; did you mean {}?", closest.join(", "))
+        }
+    }
+
+    /// Every bird whose common or scientific name contains `query`, ignoring case: those whose
+    /// name is `query` first, then those whose name starts with it, then the rest, each in
+    /// taxonomic order. With `family`, only birds in that family, as for [`BirdCatalog::by_family`].
+    pub fn search(&self, query: &str, family: Option<&str>) -> Vec<&Bird> {
+        let query = query.trim().to_lowercase();
+        let birds = match family {
+            Some(family) => self.by_family(family),
+            None => self.birds.iter().collect(),
+        };
+        let mut found: Vec<(u8, &Bird)> = birds.into_iter()
+            .filter_map(|b| {
+                let names = [b.common_name.to_lowercase(), b.scientific_name.to_lowercase()];
+                let rank = if names.contains(&query) {
+                    0
+                } else if names.iter().any(|n| n.starts_with(&query)) {
+                    1
+                } else if names.iter().any(|n| n.contains(&query)) {
+                    2
+                } else {
+                    return None;
+                };
+                Some((rank, b))
+            })
+            .collect();
+        // Stable, so each rank stays in taxonomic order
+        found.sort_by_key(|&(rank, _)| rank);
+        found.into_iter().map(|(_, b)| b).collect()
+    }
+
+    /// Every bird in a family, given its code (`cardin1`), common name (`Cardinals and Allies`),
+    /// or scientific name (`Cardinalidae`), ignoring case
+    pub fn by_family(&self, family: &str) -> Vec<&Bird> {
+        self.families.get(&family.to_lowercase())
+            .map(|positions| positions.iter().map(|&i| &self.birds[i]).collect())
+            .unwrap_or_default()
+    }
+
+    /// The common name of every family in the taxonomy, e.g. `Cardinals and Allies`, sorted and
+    /// without duplicates; any of them can be given to [`BirdCatalog::by_family`]
+    pub fn families(&self) -> Vec<String> {
+        sorted_names(self.birds.iter().filter_map(|b| b.family_com_name.as_deref()))
+    }
+
+    /// Every order in the taxonomy, e.g. `Passeriformes`, sorted and without duplicates
+    pub fn orders(&self) -> Vec<String> {
+        sorted_names(self.birds.iter().filter_map(|b| b.order.as_deref()))
+    }
+
+    /// The families in `list`, lowercased: a comma separated list of codes, common names, or
+    /// scientific names as for [`BirdCatalog::by_family`]. A name with commas in it, e.g. `Hawks,
+    /// Eagles, and Kites`, can be given as it is. Fails with `BirdError::Config`, suggesting the
+    /// closest families, if one isn't in the taxonomy.
+    pub fn parse_families(&self, list: &str) -> Result<HashSet<String>, BirdError> {
+        parse_names(list, |name| self.families.contains_key(name)).map_err(|unknown| {
+            let names = self.birds.iter().flat_map(|b| [&b.family_com_name, &b.family_sci_name]).flatten();
+            let closest = closest_names(&unknown, names.map(String::as_str));
+            BirdError::Config(format!("no family in the taxonomy is called '{}'{}", unknown, closest))
+        })
+    }
+
+    /// The orders in the comma separated `list`, lowercased, as for [`BirdCatalog::parse_families`]
+    pub fn parse_orders(&self, list: &str) -> Result<HashSet<String>, BirdError> {
+        let orders = self.orders();
+        let known: HashSet<String> = orders.iter().map(|o| o.to_lowercase()).collect();
+        parse_names(list, |name| known.contains(name)).map_err(|unknown| {
+            let closest = closest_names(&unknown, orders.iter().map(String::as_str));
+            BirdError::Config(format!("no order in the taxonomy is called '{}'{}", unknown, closest))
+        })
+    }
+}
+
+/// The names in the comma separated `list`, lowercased, each made of as many pieces as it takes
+/// for `known` to accept it, or the first piece that is no part of a known name
+fn parse_names(list: &str, known: impl Fn(&str) -> bool) -> Result<HashSet<String>, String> {
+    let pieces: Vec<&str> = list.split(',').collect();
+    let mut names = HashSet::new();
+    let mut start = 0;
+    while start < pieces.len() {
+        if pieces[start].trim().is_empty() {
+            start += 1;
+            continue;
+        }
+        // The longest name first, so `Hawks, Eagles, and Kites` isn't taken for a family `Hawks`
+        let found = (start + 1..=pieces.len()).rev()
+            .map(|end| (end, pieces[start..end].join(",").trim().to_lowercase()))
+            .find(|(_, name)| known(name));
+        let Some((end, name)) = found else { return Err(pieces[start].trim().to_string()) };
+        names.insert(name);
+        start = end;
+    }
+    Ok(names)
+}
+
+/// `; did you mean ...?` listing the few `names` closest to `query`, or nothing if there are none
+fn closest_names<'a>(query: &str, names: impl Iterator<Item = &'a str>) -> String {
+    let query = query.to_lowercase();
+    let mut scored: Vec<(usize, &str)> = names
+        .map(|name| {
+            let lower = name.to_lowercase();
+            (if lower.contains(&query) { 0 } else { edit_distance(&query, &lower) }, name)
+        })
+        .collect();
+    scored.sort();
+    scored.dedup_by_key(|(_, name)| *name);
+    let closest: Vec<&str> = scored.into_iter().take(SUGGESTIONS).map(|(_, name)| name).collect();
+    if closest.is_empty() {
+        String::new()
+    } else {
+        format!("; did you mean {}?", closest.join(", "))
+    }
+}
+
+/// `names` in order, each one once
+fn sorted_names<'a>(names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut names: Vec<String> = names.map(str::to_string).collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// What was asked for when a taxonomy was downloaded, kept next to it in e.g. `birds.json.meta`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct TaxonomyInfo {
+    /// Locale of the common names, or `None` for eBird's default, English
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// RFC 3339 timestamp of the download
+    pub downloaded_at: String,
+}
+
+impl TaxonomyInfo {
+    /// Where the info about the taxonomy at `taxonomy` is kept
+    pub fn path(taxonomy: &Path) -> PathBuf {
+        let mut name = taxonomy.file_name().map(OsString::from).unwrap_or_default();
+        name.push(".meta");
+        taxonomy.with_file_name(name)
+    }
+
+    /// The info about the taxonomy at `taxonomy`, or `None` if it was downloaded before any was kept
+    pub fn load(taxonomy: &Path) -> Result<Option<TaxonomyInfo>, BirdError> {
+        let path = TaxonomyInfo::path(taxonomy);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_str(&contents).map(Some)
+            .map_err(|e| BirdError::Parse(format!("'{}' is not valid JSON: {}", path.display(), e)))
+    }
+
+    pub fn save(&self, taxonomy: &Path) -> Result<(), BirdError> {
+        let json = serde_json::to_string(self)
+            .map_err(|e| BirdError::Parse(format!("Error converting taxonomy info to JSON: {}", e)))?;
+        fs::write(TaxonomyInfo::path(taxonomy), json)?;
+        Ok(())
+    }
+}
+
+/// Levenshtein distance between `a` and `b`, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+
+    use super::*;
+
+    fn catalog() -> BirdCatalog {
+        let birds = json!([
+            { "sciName": "Cardinalis cardinalis", "comName": "Northern Cardinal", "speciesCode": "norcar",
+              "category": "species", "taxonOrder": 1.0, "familyCode": "cardin1",
+              "familyComName": "Cardinals and Allies", "familySciName": "Cardinalidae" },
+            { "sciName": "Piranga olivacea", "comName": "Scarlet Tanager", "speciesCode": "scatan",
+              "category": "species", "taxonOrder": 2.0, "familyCode": "cardin1",
+              "familyComName": "Cardinals and Allies", "familySciName": "Cardinalidae" },
+            { "sciName": "Cyanocitta cristata", "comName": "Blue Jay", "speciesCode": "blujay",
+              "category": "species", "taxonOrder": 3.0, "familyCode": "corvid1",
+              "familyComName": "Crows, Jays, and Magpies", "familySciName": "Corvidae" },
+        ]);
+        BirdCatalog::new(serde_json::from_value(birds).unwrap())
+    }
+
+    #[test]
+    fn looks_up_species_codes() {
+        let catalog = catalog();
+        assert_eq!(catalog.by_species_code("blujay").unwrap().common_name, "Blue Jay");
+        assert!(catalog.by_species_code("dodo").is_none());
+    }
+
+    #[test]
+    fn looks_up_families_by_code_or_name() {
+        let catalog = catalog();
+        let codes = |family| catalog.by_family(family).iter().map(|b| b.species_code.as_str()).collect::<Vec<_>>();
+        assert_eq!(codes("cardin1"), ["norcar", "scatan"]);
+        assert_eq!(codes("cardinals and allies"), ["norcar", "scatan"]);
+        assert_eq!(codes("Corvidae"), ["blujay"]);
+        assert!(codes("Dodos").is_empty());
+    }
+
+    #[test]
+    fn lists_families_and_orders_once_each() {
+        let mut birds = catalog().birds().to_vec();
+        birds[0].order = Some("Passeriformes".to_string());
+        birds[2].order = Some("Passeriformes".to_string());
+        birds[1].family_com_name = None;
+        let catalog = BirdCatalog::new(birds);
+        assert_eq!(catalog.families(), ["Cardinals and Allies", "Crows, Jays, and Magpies"]);
+        assert_eq!(catalog.orders(), ["Passeriformes"]);
+    }
+
+    #[test]
+    fn family_lists_take_names_with_commas_in_them() {
+        let catalog = catalog();
+        let families = catalog.parse_families("Crows, Jays, and Magpies,cardin1, ").unwrap();
+        assert_eq!(families, HashSet::from(["crows, jays, and magpies".to_string(), "cardin1".to_string()]));
+        match catalog.parse_families("Corvidae, Crows") {
+            Err(BirdError::Config(m)) => assert_eq!(m, "no family in the taxonomy is called 'Crows'; did you mean \
+                Crows, Jays, and Magpies, Corvidae, Cardinalidae?"),
+            other => panic!("expected Config, got {:?}", other),
+        }
+        assert!(catalog.parse_orders("Passeriformes").is_err());
+    }
+
+    #[test]
+    fn chosen_bird_has_to_be_a_species() {
+        let mut birds = catalog().birds().to_vec();
+        birds[2].category = "slash".to_string();
+        let catalog = BirdCatalog::new(birds);
+
+        let name = |choice| catalog.choose(&choice).map(|b| b.species_code.clone());
+        assert_eq!(name(BirdChoice::SpeciesCode("norcar".to_string())).unwrap(), "norcar");
+        assert_eq!(name(BirdChoice::CommonName("scarlet TANAGER".to_string())).unwrap(), "scatan");
+        assert!(matches!(name(BirdChoice::SpeciesCode("dodo".to_string())), Err(BirdError::Config(_))));
+        assert!(matches!(name(BirdChoice::CommonName("Scarlet".to_string())), Err(BirdError::Config(_))));
+        match name(BirdChoice::SpeciesCode("blujay".to_string())) {
+            Err(BirdError::Config(m)) => assert_eq!(m, "Blue Jay (blujay) is a slash, not a species"),
+            other => panic!("expected Config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_bird_suggests_the_closest() {
+        let catalog = catalog();
+        let codes = |query| catalog.closest(query, 2).iter().map(|b| b.species_code.as_str()).collect::<Vec<_>>();
+        assert_eq!(codes("norcrd"), ["norcar", "scatan"]);
+        assert_eq!(codes("jay"), ["blujay", "norcar"]);
+        assert_eq!(codes("Scarlett Tanager"), ["scatan", "norcar"]);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+
+        match catalog.choose(&BirdChoice::SpeciesCode("blujya".to_string())) {
+            Err(BirdError::Config(m)) => assert_eq!(
+                m,
+                "no bird in the taxonomy has species code 'blujya'; did you mean Blue Jay (blujay), Northern Cardinal (norcar), Scarlet Tanager (scatan)?"
+            ),
+            other => panic!("expected Config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn search_ranks_exact_then_prefix_then_substring() {
+        let mut birds = catalog().birds().to_vec();
+        birds[0].common_name = "Cardinal".to_string();
+        birds[1].common_name = "Cardinal Tanager".to_string();
+        birds[2].common_name = "Blue Cardinal".to_string();
+        birds.reverse();
+        let catalog = BirdCatalog::new(birds);
+
+        let codes = |query, family| catalog.search(query, family).iter().map(|b| b.species_code.as_str()).collect::<Vec<_>>();
+        assert_eq!(codes("CARDINAL", None), ["norcar", "scatan", "blujay"]);
+        assert_eq!(codes("cardinal", Some("Corvidae")), ["blujay"]);
+        assert_eq!(codes("piranga", None), ["scatan"]);
+        assert!(codes("dodo", None).is_empty());
+    }
+
+    #[test]
+    fn random_is_none_only_when_empty() {
+        let mut rng = StdRng::seed_from_u64(1);
+        assert!(catalog().random(&mut rng).is_some());
+        assert!(BirdCatalog::default().random(&mut rng).is_none());
+    }
+
+    #[test]
+    fn parses_a_saved_taxonomy() {
+        let catalog = BirdCatalog::parse(include_str!("../tests/fixtures/birds.json")).unwrap();
+        assert_eq!(catalog.len(), 8);
+        let hawk = catalog.by_species_code("rethaw").unwrap();
+        assert_eq!(hawk.scientific_name, "Buteo jamaicensis");
+        assert_eq!(hawk.banding_codes.as_deref(), Some(&["RTHA".to_string()][..]));
+        assert_eq!(catalog.by_species_code("rethaw1").unwrap().report_as.as_deref(), Some("rethaw"));
+        let dodo = catalog.by_common_name("dodo").unwrap();
+        assert_eq!((dodo.extinct, dodo.extinct_year), (Some(true), Some(1681)));
+        assert_eq!(catalog.by_family("Anatidae").len(), 3);
+        assert!(matches!(catalog.choose(&BirdChoice::SpeciesCode("buteo".to_string())), Err(BirdError::Config(_))));
+    }
+
+    #[test]
+    fn forms_are_reported_as_their_species() {
+        let catalog = BirdCatalog::parse(include_str!("../tests/fixtures/birds.json")).unwrap();
+        let hawk = catalog.by_species_code("rethaw1").unwrap();
+        assert_eq!(catalog.reported_as(hawk).common_name, "Red-tailed Hawk");
+        // Species, and entries without a `reportAs` at all, are their own
+        let cardinal = catalog.by_species_code("norcar").unwrap();
+        assert_eq!(catalog.reported_as(cardinal).species_code, "norcar");
+        let scaup = catalog.by_species_code("y00011").unwrap();
+        assert_eq!(catalog.reported_as(scaup).species_code, "y00011");
+
+        let dangling = Bird { report_as: Some("nosuch".to_string()), ..hawk.clone() };
+        assert_eq!(catalog.reported_as(&dangling).species_code, "rethaw1");
+    }
+
+    #[test]
+    fn load_rejects_invalid_json() {
+        let path = std::env::temp_dir().join(format!("botd-catalog-{}.json", std::process::id()));
+        fs::write(&path, "[{").unwrap();
+        let result = BirdCatalog::load(&path);
+        let _ = fs::remove_file(&path);
+        assert!(matches!(result, Err(BirdError::Parse(_))));
+    }
+}
@@ -0,0 +1,878 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use time::{Date, OffsetDateTime, UtcOffset};
+
+use crate::{
+    register_secret, BirdChoice, BirdError, BlueskyPublisher, Credentials, DateOverrides, FamilyRepeat, Lookback, MastodonPublisher, MAX_BLOB_BYTES, MAX_PHOTOS, Proxies, Publisher, RateLimiter,
+    SeasonalWeights, SelectionStrategy, Template,
+};
+
+/// Everything the bot needs to know that isn't baked into the code
+#[derive(Clone)]
+pub struct Config {
+    /// eBird API key, used to download the taxonomy
+    pub ebird_api_key: String,
+    /// Contact address sent in the `User-Agent` header
+    pub email: String,
+    /// Bluesky handle to post as
+    pub handle: String,
+    /// Bluesky (app) password
+    pub password: String,
+    /// How far back a posted species won't be picked again: so many posts, so many days, or ever
+    pub history_window: Lookback,
+    /// Local copy of the eBird taxonomy, with common names in English. Other locales are kept
+    /// next to it, see [`Config::taxonomy_path`].
+    pub birds_path: PathBuf,
+    /// eBird locale to download the common names in, e.g. `es` or `pt_BR`; English if `None`
+    pub locale: Option<String>,
+    /// Download the taxonomy before picking a bird if the local copy is missing or stale
+    pub auto_update_taxonomy: bool,
+    /// Age after which the local copy of the taxonomy is stale, in days
+    pub taxonomy_max_age: u64,
+    /// Record of recently posted birds
+    pub history_path: PathBuf,
+    /// Saved Bluesky session, reused between runs
+    pub session_path: PathBuf,
+    /// Log of every post made, one JSON object per line
+    pub post_log_path: PathBuf,
+    /// Size at which the post log is rotated, in bytes; 0 never rotates it
+    pub post_log_max_size: u64,
+    /// Lock file held while the bot runs, so runs can't overlap
+    pub lock_path: PathBuf,
+    /// Age after which a lock is assumed to be left over from a crash and taken over, in seconds
+    pub stale_lock_age: u64,
+    /// Base URL of the eBird API
+    pub ebird_api_url: String,
+    /// Base URL of the eBird website, where species pages are scraped from
+    pub ebird_url: String,
+    /// Base URL of the Bluesky PDS to post to
+    pub pds_url: String,
+    /// Timeout for eBird API downloads (the taxonomy and regional species lists), in seconds
+    pub taxonomy_timeout: u64,
+    /// Timeout for fetching a species page to scrape, in seconds
+    pub page_timeout: u64,
+    /// Keep species pages in the data directory and use them instead of fetching them again,
+    /// e.g. to spare eBird while working on the bot
+    pub cache_pages: bool,
+    /// Age after which a kept species page is fetched again, in seconds
+    pub page_cache_ttl: u64,
+    /// Timeout for downloading a photo, in seconds
+    pub image_timeout: u64,
+    /// Timeout for each Bluesky API call, in seconds
+    pub bluesky_timeout: u64,
+    /// Time budget for a whole run, an `update-taxonomy`, or a `preview`, including retries, in seconds
+    pub max_run_duration: u64,
+    /// Longest rate limit to sit out within a request, in seconds; longer ones fail the attempt
+    pub max_rate_limit_wait: u64,
+    /// Paces requests to eBird and the Macaulay Library; clones of the config share it
+    pub ebird_rate_limit: RateLimiter,
+    /// Most birds to try before giving up when species pages have no usable photo
+    pub photo_attempts: u32,
+    /// Most photos to put in a post, from 1 to `MAX_PHOTOS`, when the species page has more than one
+    pub max_photos: usize,
+    /// Photos bigger than this, in bytes, are shrunk to fit before uploading them to Bluesky, with
+    /// the `resize-photos` feature; at most `MAX_BLOB_BYTES`
+    pub max_photo_bytes: usize,
+    /// Go through everything except uploading the photo and creating the post
+    pub dry_run: bool,
+    /// Link a recording of the bird's song, when there is one, with the photo as its thumbnail
+    pub include_audio: bool,
+    /// eBird taxonomy categories that may be picked, e.g. `species` and `issf` (subspecies groups).
+    /// The others are `spuh`, `slash`, `hybrid`, `intergrade`, `domestic`, and `form`.
+    pub categories: Vec<String>,
+    /// Categories that may never be picked, e.g. `domestic`, taken out after `categories`
+    pub excluded_categories: Vec<String>,
+    /// Add the species' range map from its eBird page to the photos, when there is one
+    pub include_range_map: bool,
+    /// What the post says about the bird, with the photo credits in place of `{credit}`
+    pub template: Template,
+    /// Pick extinct birds too, e.g. for an account about them
+    pub include_extinct: bool,
+    /// How the bird is picked at random
+    pub selection: SelectionStrategy,
+    /// How many of the last posts the pick stays away from the families of; 0 for none
+    pub family_window: usize,
+    /// Whether a bird of one of those families is only less likely or left out
+    pub family_repeat: FamilyRepeat,
+    /// How much more likely birds of some families are to be picked in each month
+    pub seasonal_weights: SeasonalWeights,
+    /// Birds to post on particular days instead of picking one
+    pub date_overrides: DateOverrides,
+    /// Post this bird instead of picking one at random
+    pub only_bird: Option<BirdChoice>,
+    /// Pick the bird for this day instead of today, e.g. to see what a past run picked
+    pub date: Option<Date>,
+    /// Seed the pick with this instead of the date, e.g. to try out the filters
+    pub seed: Option<u64>,
+    /// Time zone, as an offset from UTC, whose calendar date the bird of the day is picked for
+    pub utc_offset: UtcOffset,
+    /// Only pick birds on the species list of this eBird region (e.g. `US-NY`)
+    pub region: Option<String>,
+    /// Age after which the local copy of a region's species list is stale, in days
+    pub region_max_age: u64,
+    /// Only pick birds of these families, comma separated, by code (`accipi1`), common (`Hawks,
+    /// Eagles, and Kites`), or scientific (`Accipitridae`) name
+    pub only_family: Option<String>,
+    /// Only pick birds of these orders, comma separated, e.g. `Strigiformes,Accipitriformes`
+    pub only_order: Option<String>,
+    /// Mastodon instance to cross-post to, e.g. `https://mastodon.social`
+    pub mastodon_url: Option<String>,
+    /// Access token for the Mastodon account, with the `write:media` and `write:statuses` scopes
+    pub mastodon_token: Option<String>,
+    /// File the credentials the environment doesn't set were read from, see [`Credentials`]
+    pub credentials_file: Option<PathBuf>,
+    /// Proxies every request goes through, from `HTTP_PROXY`, `HTTPS_PROXY`, and `NO_PROXY`
+    pub proxies: Proxies,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ebird_api_key: String::new(),
+            email: String::new(),
+            handle: String::new(),
+            password: String::new(),
+            history_window: Lookback::default(),
+            birds_path: PathBuf::from("birds.json"),
+            locale: None,
+            auto_update_taxonomy: true,
+            taxonomy_max_age: 90,
+            history_path: PathBuf::from("posted_history.json"),
+            session_path: PathBuf::from("session.json"),
+            post_log_path: PathBuf::from("posts.jsonl"),
+            post_log_max_size: 1_000_000,
+            lock_path: PathBuf::from("botd.lock"),
+            stale_lock_age: 3600,
+            ebird_api_url: "https://api.ebird.org".to_string(),
+            ebird_url: "https://ebird.org".to_string(),
+            pds_url: "https://bsky.social".to_string(),
+            taxonomy_timeout: 120,
+            page_timeout: 30,
+            cache_pages: false,
+            page_cache_ttl: 3 * 60 * 60,
+            image_timeout: 30,
+            bluesky_timeout: 30,
+            max_rate_limit_wait: 60,
+            max_run_duration: 120,
+            ebird_rate_limit: RateLimiter::new(2.0),
+            photo_attempts: 5,
+            max_photos: 1,
+            max_photo_bytes: MAX_BLOB_BYTES,
+            dry_run: false,
+            include_audio: false,
+            categories: vec!["species".to_string()],
+            excluded_categories: Vec::new(),
+            include_range_map: false,
+            template: Template::default(),
+            include_extinct: false,
+            selection: SelectionStrategy::Uniform,
+            family_window: 0,
+            family_repeat: FamilyRepeat::default(),
+            seasonal_weights: SeasonalWeights::default(),
+            date_overrides: DateOverrides::default(),
+            only_bird: None,
+            date: None,
+            seed: None,
+            utc_offset: UtcOffset::UTC,
+            region: None,
+            region_max_age: 30,
+            only_family: None,
+            only_order: None,
+            mastodon_url: None,
+            mastodon_token: None,
+            credentials_file: None,
+            proxies: Proxies::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Read `EBIRD_API_KEY`, `BOTD_EMAIL`, `BOTD_HANDLE`, and `BOTD_PASS`, or those of them not
+    /// set from the file named by `BOTD_CREDENTIALS_FILE` (see [`Credentials`]), reporting every
+    /// one that is missing rather than just the first.
+    /// `BOTD_HISTORY_WINDOW` (see [`Lookback`], default 30 posts), `BOTD_REGION` with
+    /// `BOTD_REGION_MAX_AGE` (days, default 30), `BOTD_FAMILY`, `BOTD_ORDER`, `BOTD_MAX_RATE_LIMIT_WAIT` (seconds, default 60),
+    /// `BOTD_MAX_RUN_DURATION` (seconds, default 120), and the timeouts
+    /// `BOTD_TAXONOMY_TIMEOUT` (default 120), `BOTD_PAGE_TIMEOUT`, `BOTD_IMAGE_TIMEOUT`, and
+    /// `BOTD_BLUESKY_TIMEOUT` (seconds, default 30), `BOTD_EBIRD_RATE` (requests per second, default 2),
+    /// `BOTD_PHOTO_ATTEMPTS` (default 5), `BOTD_MAX_PHOTOS` (1 to 4, default 1),
+    /// `BOTD_MAX_PHOTO_BYTES` (default and most 1000000),
+    /// `BOTD_STALE_LOCK_AGE` (seconds, default 3600), `BOTD_POST_LOG_MAX_SIZE` (bytes, default
+    /// 1000000, 0 for no limit), `BOTD_SELECTION` (`uniform`, the default,
+    /// `weighted-by-taxon`, `family-balanced`, `order-balanced`, or `shuffle`),
+    /// `BOTD_FAMILY_WINDOW` (posts, default 0) with `BOTD_FAMILY_REPEAT` (`deprioritize`, the
+    /// default, or `exclude`), `BOTD_SEASONAL_WEIGHTS` (see [`SeasonalWeights`]), `BOTD_OVERRIDES` (see
+    /// [`DateOverrides`]), `BOTD_CATEGORIES` (comma separated, default
+    /// `species`) and `BOTD_EXCLUDE_CATEGORIES`, `BOTD_UTC_OFFSET` (e.g. `-05:00`, default UTC), `BOTD_CACHE_PAGES` with
+    /// `BOTD_PAGE_CACHE_TTL` (seconds, default 10800), `BOTD_INCLUDE_AUDIO`, `BOTD_INCLUDE_RANGE_MAP`,
+    /// `BOTD_INCLUDE_EXTINCT`, `BOTD_TEMPLATE` (see [`Template`]), and `BOTD_LOCALE` (see
+    /// [`Config::taxonomy_path`]) are optional.
+    /// Files are kept in `BOTD_DATA_DIR`, or without it the platform's data directory (e.g.
+    /// `~/.local/share/birdoftheday`), and `BOTD_BIRDS_PATH` keeps the taxonomy somewhere else
+    /// again. The taxonomy is downloaded again when it is older than `BOTD_TAXONOMY_MAX_AGE` days
+    /// (default 90) unless `BOTD_AUTO_UPDATE_TAXONOMY` is false. Setting both `BOTD_MASTODON_URL`
+    /// and `BOTD_MASTODON_TOKEN` cross-posts to Mastodon. Requests go through the proxies in
+    /// `HTTP_PROXY` and `HTTPS_PROXY`, as [`Proxies::from_env`] reads them. The API key, password,
+    /// Mastodon token, and proxy credentials are masked from then on in error messages.
+    pub fn from_env() -> Result<Config, BirdError> {
+        let (config, missing, problems) = Config::read_env();
+        if !problems.is_empty() {
+            Err(BirdError::Config(problems.join("; ")))
+        } else if !missing.is_empty() {
+            Err(BirdError::MissingEnv(missing))
+        } else {
+            Ok(config)
+        }
+    }
+
+    /// Like [`Config::from_env`], but the credentials are left blank if they aren't set, for
+    /// commands that neither download the taxonomy nor post anything
+    pub fn from_env_without_credentials() -> Result<Config, BirdError> {
+        let (config, _, problems) = Config::read_env();
+        match problems.is_empty() {
+            true => Ok(config),
+            false => Err(BirdError::Config(problems.join("; "))),
+        }
+    }
+
+    /// The configuration in the environment as far as it could be read, with every problem
+    /// [`Config::from_env`] would fail on rather than just the first, missing variables included
+    pub fn check_env() -> (Config, Vec<String>) {
+        let (config, missing, mut problems) = Config::read_env();
+        problems.extend(missing.iter().map(|name| format!("{} is not set", name)));
+        (config, problems)
+    }
+
+    /// The configuration in the environment, which required variables are missing from it, and
+    /// what is wrong with the rest
+    fn read_env() -> (Config, Vec<String>, Vec<String>) {
+        Config::read_vars(|name| env::var(name).ok())
+    }
+
+    fn read_vars(var: impl Fn(&str) -> Option<String>) -> (Config, Vec<String>, Vec<String>) {
+        let mut env = EnvReader::new(&var);
+        let credentials_file = var("BOTD_CREDENTIALS_FILE").filter(|f| !f.is_empty()).map(PathBuf::from);
+        if let Some(path) = &credentials_file {
+            match Credentials::load(path) {
+                Ok(credentials) => env.credentials = credentials,
+                Err(problem) => env.problems.push(format!("BOTD_CREDENTIALS_FILE: {}", problem)),
+            }
+        }
+        let mut config = Config {
+            ebird_api_key: env.required("EBIRD_API_KEY"),
+            auto_update_taxonomy: env.parse("BOTD_AUTO_UPDATE_TAXONOMY", true),
+            taxonomy_max_age: env.parse("BOTD_TAXONOMY_MAX_AGE", 90),
+            email: env.required("BOTD_EMAIL"),
+            handle: env.required("BOTD_HANDLE"),
+            password: env.required("BOTD_PASS"),
+            history_window: env.parse("BOTD_HISTORY_WINDOW", Lookback::default()),
+            region: var("BOTD_REGION").filter(|r| !r.is_empty()),
+            region_max_age: env.parse("BOTD_REGION_MAX_AGE", 30),
+            locale: var("BOTD_LOCALE").filter(|l| !l.trim().is_empty()).map(|l| l.trim().to_string()),
+            selection: env.parse("BOTD_SELECTION", SelectionStrategy::default()),
+            family_window: env.parse("BOTD_FAMILY_WINDOW", 0),
+            family_repeat: env.parse("BOTD_FAMILY_REPEAT", FamilyRepeat::default()),
+            seasonal_weights: SeasonalWeights::default(),
+            date_overrides: DateOverrides::default(),
+            only_family: var("BOTD_FAMILY").filter(|f| !f.trim().is_empty()),
+            only_order: var("BOTD_ORDER").filter(|o| !o.trim().is_empty()),
+            max_rate_limit_wait: env.parse("BOTD_MAX_RATE_LIMIT_WAIT", 60),
+            max_run_duration: env.parse("BOTD_MAX_RUN_DURATION", 120),
+            taxonomy_timeout: env.parse("BOTD_TAXONOMY_TIMEOUT", 120),
+            page_timeout: env.parse("BOTD_PAGE_TIMEOUT", 30),
+            cache_pages: env.parse("BOTD_CACHE_PAGES", false),
+            page_cache_ttl: env.parse("BOTD_PAGE_CACHE_TTL", 3 * 60 * 60),
+            image_timeout: env.parse("BOTD_IMAGE_TIMEOUT", 30),
+            bluesky_timeout: env.parse("BOTD_BLUESKY_TIMEOUT", 30),
+            ebird_rate_limit: RateLimiter::new(env.parse("BOTD_EBIRD_RATE", 2.0)),
+            photo_attempts: env.parse("BOTD_PHOTO_ATTEMPTS", 5),
+            max_photos: env.parse("BOTD_MAX_PHOTOS", 1),
+            max_photo_bytes: env.parse("BOTD_MAX_PHOTO_BYTES", MAX_BLOB_BYTES),
+            stale_lock_age: env.parse("BOTD_STALE_LOCK_AGE", 3600),
+            post_log_max_size: env.parse("BOTD_POST_LOG_MAX_SIZE", 1_000_000),
+            include_audio: env.parse("BOTD_INCLUDE_AUDIO", false),
+            include_extinct: env.parse("BOTD_INCLUDE_EXTINCT", false),
+            include_range_map: env.parse("BOTD_INCLUDE_RANGE_MAP", false),
+            mastodon_url: var("BOTD_MASTODON_URL").filter(|u| !u.trim().is_empty()),
+            mastodon_token: env.optional("BOTD_MASTODON_TOKEN"),
+            credentials_file,
+            ..Config::default()
+        };
+        let EnvReader { missing, mut problems, .. } = env;
+        if let Some(offset) = var("BOTD_UTC_OFFSET") {
+            match parse_utc_offset(&offset) {
+                Some(offset) => config.utc_offset = offset,
+                None => problems.push(format!("BOTD_UTC_OFFSET '{}' is not an offset from UTC, e.g. -05:00 or +05:30", offset)),
+            }
+        }
+        match Proxies::from_vars(&var) {
+            Ok(proxies) => config.proxies = proxies,
+            Err(e) => problems.push(config_message(e)),
+        }
+        if let Some(dir) = var("BOTD_DATA_DIR").filter(|d| !d.is_empty()).map(PathBuf::from).or_else(default_data_dir) {
+            config.set_data_dir(&dir);
+        }
+        let allowed = var("BOTD_CATEGORIES");
+        let denied = var("BOTD_EXCLUDE_CATEGORIES").filter(|d| !d.trim().is_empty());
+        match category_rules(allowed.as_deref(), denied.as_deref()) {
+            Ok((allowed, denied)) => (config.categories, config.excluded_categories) = (allowed, denied),
+            Err(rule_problems) => problems.extend(rule_problems),
+        }
+        if let Some(weights) = var("BOTD_SEASONAL_WEIGHTS") {
+            match weights.parse() {
+                Ok(weights) => config.seasonal_weights = weights,
+                Err(problem) => problems.push(format!("BOTD_SEASONAL_WEIGHTS: {}", problem)),
+            }
+        }
+        if let Some(overrides) = var("BOTD_OVERRIDES") {
+            match overrides.parse() {
+                Ok(overrides) => config.date_overrides = overrides,
+                Err(problem) => problems.push(format!("BOTD_OVERRIDES: {}", problem)),
+            }
+        }
+        if let Some(template) = var("BOTD_TEMPLATE") {
+            match template.parse() {
+                Ok(template) => config.template = template,
+                Err(problem) => problems.push(format!("BOTD_TEMPLATE: {}", problem)),
+            }
+        }
+        if let Some(path) = var("BOTD_BIRDS_PATH").filter(|p| !p.is_empty()) {
+            config.birds_path = PathBuf::from(path);
+        }
+        if let Some(locale) = config.locale.as_deref().filter(|l| !is_locale(l)) {
+            problems.push(format!("BOTD_LOCALE '{}' is not an eBird locale code, e.g. es or pt_BR", locale));
+            config.locale = None;
+        }
+        let rate = config.ebird_rate_limit.per_second();
+        if !rate.is_finite() || rate <= 0.0 {
+            problems.push("BOTD_EBIRD_RATE must be more than 0".to_string());
+        }
+        if !(1..=MAX_PHOTOS).contains(&config.max_photos) {
+            problems.push(format!("BOTD_MAX_PHOTOS must be from 1 to {}", MAX_PHOTOS));
+        }
+        if !(1..=MAX_BLOB_BYTES).contains(&config.max_photo_bytes) {
+            problems.push(format!("BOTD_MAX_PHOTO_BYTES must be from 1 to {}, the most Bluesky accepts", MAX_BLOB_BYTES));
+        }
+        for (name, timeout) in [
+            ("BOTD_TAXONOMY_TIMEOUT", config.taxonomy_timeout),
+            ("BOTD_PAGE_TIMEOUT", config.page_timeout),
+            ("BOTD_IMAGE_TIMEOUT", config.image_timeout),
+            ("BOTD_BLUESKY_TIMEOUT", config.bluesky_timeout),
+            ("BOTD_MAX_RUN_DURATION", config.max_run_duration),
+        ] {
+            if timeout == 0 {
+                problems.push(format!("{} must be more than 0", name));
+            }
+        }
+        if config.mastodon_url.is_some() != config.mastodon_token.is_some() {
+            problems.push("BOTD_MASTODON_URL and BOTD_MASTODON_TOKEN have to be set together".to_string());
+        }
+
+        for secret in [&config.ebird_api_key, &config.password].into_iter().chain(&config.mastodon_token) {
+            register_secret(secret);
+        }
+        (config, missing, problems)
+    }
+
+    /// Every setting read from the environment, by variable, with the value in effect and the
+    /// credentials masked, e.g. to show where a value came from
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        let secret = |s: &str| if s.is_empty() { String::new() } else { "***".to_string() };
+        let optional = |s: &Option<String>| s.clone().unwrap_or_default();
+        let data_dir = self.history_path.parent().map(|d| d.display().to_string()).unwrap_or_default();
+        let mut settings = vec![
+            ("EBIRD_API_KEY", secret(&self.ebird_api_key)),
+            ("BOTD_EMAIL", self.email.clone()),
+            ("BOTD_HANDLE", self.handle.clone()),
+            ("BOTD_PASS", secret(&self.password)),
+            ("BOTD_CREDENTIALS_FILE", self.credentials_file.as_ref().map(|f| f.display().to_string()).unwrap_or_default()),
+            ("BOTD_DATA_DIR", data_dir),
+            ("BOTD_BIRDS_PATH", self.birds_path.display().to_string()),
+            ("BOTD_LOCALE", optional(&self.locale)),
+            ("BOTD_AUTO_UPDATE_TAXONOMY", self.auto_update_taxonomy.to_string()),
+            ("BOTD_TAXONOMY_MAX_AGE", self.taxonomy_max_age.to_string()),
+            ("BOTD_HISTORY_WINDOW", self.history_window.to_string()),
+            ("BOTD_CATEGORIES", self.categories.join(",")),
+            ("BOTD_EXCLUDE_CATEGORIES", self.excluded_categories.join(",")),
+            ("BOTD_INCLUDE_EXTINCT", self.include_extinct.to_string()),
+            ("BOTD_SELECTION", self.selection.to_string()),
+            ("BOTD_FAMILY_WINDOW", self.family_window.to_string()),
+            ("BOTD_FAMILY_REPEAT", self.family_repeat.to_string()),
+            ("BOTD_SEASONAL_WEIGHTS", self.seasonal_weights.to_string()),
+            ("BOTD_OVERRIDES", self.date_overrides.to_string()),
+            ("BOTD_REGION", optional(&self.region)),
+            ("BOTD_REGION_MAX_AGE", self.region_max_age.to_string()),
+            ("BOTD_UTC_OFFSET", format_utc_offset(self.utc_offset)),
+            ("BOTD_FAMILY", optional(&self.only_family)),
+            ("BOTD_ORDER", optional(&self.only_order)),
+            ("BOTD_MAX_RATE_LIMIT_WAIT", self.max_rate_limit_wait.to_string()),
+            ("BOTD_MAX_RUN_DURATION", self.max_run_duration.to_string()),
+            ("BOTD_TAXONOMY_TIMEOUT", self.taxonomy_timeout.to_string()),
+            ("BOTD_PAGE_TIMEOUT", self.page_timeout.to_string()),
+            ("BOTD_CACHE_PAGES", self.cache_pages.to_string()),
+            ("BOTD_PAGE_CACHE_TTL", self.page_cache_ttl.to_string()),
+            ("BOTD_IMAGE_TIMEOUT", self.image_timeout.to_string()),
+            ("BOTD_BLUESKY_TIMEOUT", self.bluesky_timeout.to_string()),
+            ("BOTD_EBIRD_RATE", self.ebird_rate_limit.per_second().to_string()),
+            ("BOTD_PHOTO_ATTEMPTS", self.photo_attempts.to_string()),
+            ("BOTD_MAX_PHOTOS", self.max_photos.to_string()),
+            ("BOTD_MAX_PHOTO_BYTES", self.max_photo_bytes.to_string()),
+            ("BOTD_STALE_LOCK_AGE", self.stale_lock_age.to_string()),
+            ("BOTD_POST_LOG_MAX_SIZE", self.post_log_max_size.to_string()),
+            ("BOTD_INCLUDE_AUDIO", self.include_audio.to_string()),
+            ("BOTD_INCLUDE_RANGE_MAP", self.include_range_map.to_string()),
+            ("BOTD_TEMPLATE", self.template.to_string()),
+            ("BOTD_MASTODON_URL", optional(&self.mastodon_url)),
+            ("BOTD_MASTODON_TOKEN", secret(self.mastodon_token.as_deref().unwrap_or_default())),
+        ];
+        settings.extend(self.proxies.settings());
+        settings
+    }
+
+    /// Keep the taxonomy, history, saved session, post log, and lock file in `dir`
+    pub fn set_data_dir(&mut self, dir: &Path) {
+        self.birds_path = dir.join("birds.json");
+        self.history_path = dir.join("posted_history.json");
+        self.session_path = dir.join("session.json");
+        self.post_log_path = dir.join("posts.jsonl");
+        self.lock_path = dir.join("botd.lock");
+    }
+
+    /// Create the directories the taxonomy, history, saved session, post log, and lock file go in
+    pub fn create_data_dirs(&self) -> Result<(), BirdError> {
+        for path in [&self.birds_path, &self.history_path, &self.session_path, &self.post_log_path, &self.lock_path] {
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// How far back the history has to reach: to keep recently posted birds from being picked
+    /// again, and to the families of the last `family_window` posts
+    pub fn history_lookbacks(&self) -> [Lookback; 2] {
+        [self.history_window, Lookback::Posts(self.family_window)]
+    }
+
+    /// The day to pick the bird for: `date` if it is set, otherwise today at `utc_offset`
+    pub fn today(&self) -> Date {
+        self.date.unwrap_or_else(|| OffsetDateTime::now_utc().to_offset(self.utc_offset).date())
+    }
+
+    /// Local copy of the taxonomy in `locale`: `birds_path` itself without one, and e.g.
+    /// `birds.es.json` next to it for `es`, so switching locales doesn't replace the other copies
+    pub fn taxonomy_path(&self) -> PathBuf {
+        let Some(locale) = &self.locale else { return self.birds_path.clone() };
+        let mut name = self.birds_path.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!(".{}", locale));
+        if let Some(extension) = self.birds_path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        self.birds_path.with_file_name(name)
+    }
+
+    /// Local copy of the species list for `region`, kept next to the taxonomy
+    pub fn region_path(&self, region: &str) -> PathBuf {
+        self.birds_path.with_file_name(format!("region.{}.json", region))
+    }
+
+    /// Where the `shuffle` selection keeps the order it walks the taxonomy in, in the data directory
+    pub fn shuffle_queue_path(&self) -> PathBuf {
+        self.history_path.with_file_name("shuffle_queue.json")
+    }
+
+    /// Species codes or common names never to pick, in the data directory
+    pub fn blocklist_path(&self) -> PathBuf {
+        self.history_path.with_file_name("blocklist.txt")
+    }
+
+    /// Species codes or common names to pick only from, if the file exists, in the data directory
+    pub fn allowlist_path(&self) -> PathBuf {
+        self.history_path.with_file_name("allowlist.txt")
+    }
+
+    /// Where the species page for `species_code` is kept with `cache_pages`, in the data directory
+    pub fn page_cache_path(&self, species_code: &str) -> PathBuf {
+        self.history_path.with_file_name("pages").join(format!("{}.html", species_code))
+    }
+
+    /// Where `preview` saves the `n`th photo of a species, counting from 0, in the data directory
+    pub fn photo_path(&self, species_code: &str, n: usize, extension: &str) -> PathBuf {
+        let name = match n {
+            0 => format!("{}.{}", species_code, extension),
+            n => format!("{}-{}.{}", species_code, n + 1, extension),
+        };
+        self.history_path.with_file_name(name)
+    }
+
+    /// Every platform to post to: Bluesky, and Mastodon if it is configured
+    pub fn publishers(&self) -> Vec<Box<dyn Publisher>> {
+        let mut publishers: Vec<Box<dyn Publisher>> = vec![Box::new(BlueskyPublisher::new())];
+        if let (Some(url), Some(token)) = (&self.mastodon_url, &self.mastodon_token) {
+            publishers.push(Box::new(MastodonPublisher::new(url, token)));
+        }
+        publishers
+    }
+
+    /// `User-Agent` header value identifying the bot and who runs it
+    pub fn user_agent(&self) -> String {
+        format!("BirdOfTheDayBot ({})", self.email)
+    }
+}
+
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Config")
+            .field("ebird_api_key", &"***")
+            .field("email", &self.email)
+            .field("handle", &self.handle)
+            .field("password", &"***")
+            .field("history_window", &self.history_window)
+            .field("birds_path", &self.birds_path)
+            .field("locale", &self.locale)
+            .field("auto_update_taxonomy", &self.auto_update_taxonomy)
+            .field("taxonomy_max_age", &self.taxonomy_max_age)
+            .field("history_path", &self.history_path)
+            .field("session_path", &self.session_path)
+            .field("post_log_path", &self.post_log_path)
+            .field("post_log_max_size", &self.post_log_max_size)
+            .field("lock_path", &self.lock_path)
+            .field("stale_lock_age", &self.stale_lock_age)
+            .field("ebird_api_url", &self.ebird_api_url)
+            .field("ebird_url", &self.ebird_url)
+            .field("pds_url", &self.pds_url)
+            .field("taxonomy_timeout", &self.taxonomy_timeout)
+            .field("page_timeout", &self.page_timeout)
+            .field("cache_pages", &self.cache_pages)
+            .field("page_cache_ttl", &self.page_cache_ttl)
+            .field("image_timeout", &self.image_timeout)
+            .field("bluesky_timeout", &self.bluesky_timeout)
+            .field("max_rate_limit_wait", &self.max_rate_limit_wait)
+            .field("max_run_duration", &self.max_run_duration)
+            .field("ebird_rate_limit", &self.ebird_rate_limit)
+            .field("photo_attempts", &self.photo_attempts)
+            .field("max_photos", &self.max_photos)
+            .field("max_photo_bytes", &self.max_photo_bytes)
+            .field("dry_run", &self.dry_run)
+            .field("include_audio", &self.include_audio)
+            .field("categories", &self.categories)
+            .field("excluded_categories", &self.excluded_categories)
+            .field("include_range_map", &self.include_range_map)
+            .field("template", &self.template.to_string())
+            .field("include_extinct", &self.include_extinct)
+            .field("selection", &self.selection)
+            .field("family_window", &self.family_window)
+            .field("family_repeat", &self.family_repeat)
+            .field("seasonal_weights", &self.seasonal_weights.to_string())
+            .field("date_overrides", &self.date_overrides.to_string())
+            .field("only_bird", &self.only_bird)
+            .field("date", &self.date)
+            .field("seed", &self.seed)
+            .field("utc_offset", &self.utc_offset)
+            .field("region", &self.region)
+            .field("region_max_age", &self.region_max_age)
+            .field("only_family", &self.only_family)
+            .field("only_order", &self.only_order)
+            .field("mastodon_url", &self.mastodon_url)
+            .field("mastodon_token", &self.mastodon_token.as_ref().map(|_| "***"))
+            .field("credentials_file", &self.credentials_file)
+            .field("proxies", &self.proxies)
+            .finish()
+    }
+}
+
+/// Set variables from `.env` files, leaving any already in the environment alone: from `path` if
+/// it is given, otherwise from `.env` in the working directory and then from `.env` in `data_dir`
+/// (or `BOTD_DATA_DIR`, or the platform's data directory), so the first file to set a variable
+/// wins. Returns the files that were read.
+pub fn load_env_files(path: Option<&Path>, data_dir: Option<&Path>) -> Result<Vec<PathBuf>, BirdError> {
+    let load = |path: &Path| dotenvy::from_path(path)
+        .map_err(|e| BirdError::Config(format!("unable to read '{}': {}", path.display(), e)));
+    if let Some(path) = path {
+        load(path)?;
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut loaded = Vec::new();
+    let mut try_load = |path: PathBuf| -> Result<(), BirdError> {
+        if path.is_file() {
+            load(&path)?;
+            loaded.push(path);
+        }
+        Ok(())
+    };
+    try_load(PathBuf::from(".env"))?;
+    let data_dir = data_dir.map(Path::to_path_buf)
+        .or_else(|| env::var_os("BOTD_DATA_DIR").filter(|d| !d.is_empty()).map(PathBuf::from))
+        .or_else(default_data_dir);
+    if let Some(dir) = data_dir {
+        try_load(dir.join(".env"))?;
+    }
+    Ok(loaded)
+}
+
+/// Where files go without `BOTD_DATA_DIR`, or `None` to leave them in the working directory
+fn default_data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("birdoftheday"))
+}
+
+/// Every category of entry in the eBird taxonomy
+const CATEGORIES: [&str; 8] = ["species", "issf", "spuh", "slash", "hybrid", "intergrade", "domestic", "form"];
+
+/// The comma separated categories in the variable `name`, lowercased
+fn parse_categories(name: &str, value: &str) -> Result<Vec<String>, String> {
+    let categories: Vec<String> = value.split(',').map(|c| c.trim().to_lowercase()).filter(|c| !c.is_empty()).collect();
+    if categories.is_empty() {
+        return Err(format!("{} must name at least one category", name));
+    }
+    match categories.iter().find(|c| !CATEGORIES.contains(&c.as_str())) {
+        Some(c) => Err(format!("{} has '{}', which isn't one of {}", name, c, CATEGORIES.join(", "))),
+        None => Ok(categories),
+    }
+}
+
+/// The categories that may be picked and those that may not, from `BOTD_CATEGORIES` and
+/// `BOTD_EXCLUDE_CATEGORIES`, only species if neither is set.
+/// With only categories to leave out, every other one may be picked. A category can't be both.
+fn category_rules(allowed: Option<&str>, denied: Option<&str>) -> Result<(Vec<String>, Vec<String>), Vec<String>> {
+    if allowed.is_none() && denied.is_none() {
+        return Ok((vec!["species".to_string()], Vec::new()));
+    }
+    let allowed = allowed.map(|a| parse_categories("BOTD_CATEGORIES", a)).transpose();
+    let denied = denied.map(|d| parse_categories("BOTD_EXCLUDE_CATEGORIES", d)).transpose();
+    let (allowed, denied) = match (allowed, denied) {
+        (Ok(allowed), Ok(denied)) => (allowed, denied.unwrap_or_default()),
+        (allowed, denied) => return Err(allowed.err().into_iter().chain(denied.err()).collect()),
+    };
+    let Some(allowed) = allowed else {
+        return Ok((CATEGORIES.map(str::to_string).to_vec(), denied));
+    };
+    let both: Vec<&str> = allowed.iter().filter(|c| denied.contains(c)).map(String::as_str).collect();
+    match both.is_empty() {
+        true => Ok((allowed, denied)),
+        false => Err(vec![format!("BOTD_CATEGORIES and BOTD_EXCLUDE_CATEGORIES both have {}", both.join(", "))]),
+    }
+}
+
+/// An offset from UTC given as `+05:30`, `-05:00`, `-5`, or `Z`
+fn parse_utc_offset(s: &str) -> Option<UtcOffset> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("z") || s.eq_ignore_ascii_case("utc") {
+        return Some(UtcOffset::UTC);
+    }
+    let (sign, rest) = match s.split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let digits = |n: &str| (1..=2).contains(&n.len()) && n.bytes().all(|b| b.is_ascii_digit());
+    if !digits(hours) || !digits(minutes) {
+        return None;
+    }
+    let (hours, minutes): (i8, i8) = (hours.parse().ok()?, minutes.parse().ok()?);
+    // Every time zone in use is within 14 hours of UTC
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+/// `offset` as `+05:30`, the way `BOTD_UTC_OFFSET` takes it
+fn format_utc_offset(offset: UtcOffset) -> String {
+    let sign = if offset.is_negative() { '-' } else { '+' };
+    format!("{}{:02}:{:02}", sign, offset.whole_hours().abs(), offset.minutes_past_hour().abs())
+}
+
+/// Whether `s` is shaped like an eBird locale code, e.g. `es`, `pt_BR`, or `zh_SIM`, which
+/// also keeps it safe to put in a file name
+pub fn is_locale(s: &str) -> bool {
+    let mut parts = s.split(['_', '-']);
+    let language = parts.next().unwrap_or_default();
+    (2..=3).contains(&language.len()) && language.bytes().all(|b| b.is_ascii_alphabetic())
+        && parts.all(|p| (2..=4).contains(&p.len()) && p.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+/// Reads variables for [`Config::check_env`], noting what is missing or wrong instead of stopping
+pub(crate) struct EnvReader<'a> {
+    /// Looks a variable up, in the environment but for tests
+    var: &'a dyn Fn(&str) -> Option<String>,
+    pub missing: Vec<String>,
+    pub problems: Vec<String>,
+    /// Where credentials not set in the environment are looked for
+    pub credentials: Credentials,
+}
+
+impl<'a> EnvReader<'a> {
+    pub fn new(var: &'a dyn Fn(&str) -> Option<String>) -> EnvReader<'a> {
+        EnvReader { var, missing: Vec::new(), problems: Vec::new(), credentials: Credentials::default() }
+    }
+
+    /// A variable that has to be set, here or in the credentials file, or an empty string if it isn't
+    fn required(&mut self, name: &str) -> String {
+        match self.optional(name) {
+            Some(v) => v,
+            None => {
+                self.missing.push(name.to_string());
+                String::new()
+            }
+        }
+    }
+
+    /// A variable that may be set, here or in the credentials file, with the environment first
+    fn optional(&self, name: &str) -> Option<String> {
+        (self.var)(name).filter(|v| !v.trim().is_empty())
+            .or_else(|| self.credentials.get(name).map(str::to_string))
+    }
+
+    /// An optional variable, or `default` if it isn't set or can't be parsed
+    pub fn parse<T: std::str::FromStr>(&mut self, name: &str, default: T) -> T {
+        match (self.var)(name) {
+            None => default,
+            Some(v) => v.trim().parse().unwrap_or_else(|_| {
+                self.problems.push(format!("'{}' is not a valid value for {}", v, name));
+                default
+            }),
+        }
+    }
+}
+
+/// What is wrong, without the `invalid configuration: ` a `BirdError::Config` is shown with
+fn config_message(e: BirdError) -> String {
+    match e {
+        BirdError::Config(m) => m,
+        e => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_missing_variable_is_reported() {
+        let (_, missing, problems) = Config::read_vars(|name| match name {
+            "BOTD_EMAIL" => Some("birder@example.com".to_string()),
+            "BOTD_PASS" => Some("  ".to_string()),
+            _ => None,
+        });
+        assert_eq!(missing, ["EBIRD_API_KEY", "BOTD_HANDLE", "BOTD_PASS"]);
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn credentials_not_in_the_environment_come_from_the_file() {
+        let mut env = EnvReader::new(&|_| None);
+        env.credentials = serde_json::from_value(serde_json::json!({"handle": "bird.bsky.social"})).unwrap();
+        assert_eq!(env.required("BOTD_HANDLE"), "bird.bsky.social");
+        assert_eq!(env.required("EBIRD_API_KEY"), "");
+        assert_eq!(env.missing, ["EBIRD_API_KEY"]);
+    }
+
+    #[test]
+    fn file_paths_come_from_the_environment() {
+        let (config, _, _) = Config::read_vars(|_| None);
+        assert_eq!(config.history_path, default_data_dir().unwrap().join("posted_history.json"));
+
+        let (config, _, problems) = Config::read_vars(|name| match name {
+            "BOTD_DATA_DIR" => Some("/var/lib/botd".to_string()),
+            "BOTD_BIRDS_PATH" => Some("/usr/share/botd/taxonomy.json".to_string()),
+            _ => None,
+        });
+        assert!(problems.is_empty());
+        assert_eq!(config.birds_path, Path::new("/usr/share/botd/taxonomy.json"));
+        assert_eq!(config.session_path, Path::new("/var/lib/botd/session.json"));
+        assert_eq!(config.lock_path, Path::new("/var/lib/botd/botd.lock"));
+    }
+
+    #[test]
+    fn environment_overrides_env_file() {
+        let path = env::temp_dir().join(format!("botd-env-{}", std::process::id()));
+        fs::write(&path, "BOTD_TEST_FROM_FILE=file\nBOTD_TEST_IN_BOTH=file\n").unwrap();
+        env::set_var("BOTD_TEST_IN_BOTH", "process");
+
+        assert_eq!(load_env_files(Some(&path), None).unwrap(), vec![path.clone()]);
+        assert_eq!(env::var("BOTD_TEST_FROM_FILE").as_deref(), Ok("file"));
+        assert_eq!(env::var("BOTD_TEST_IN_BOTH").as_deref(), Ok("process"));
+        assert!(matches!(load_env_files(Some(&path.with_extension("missing")), None), Err(BirdError::Config(_))));
+    }
+
+    #[test]
+    fn missing_data_dir_is_created() {
+        let dir = env::temp_dir().join(format!("botd-data-{}", std::process::id())).join("nested");
+        let _ = fs::remove_dir_all(&dir);
+        let mut config = Config::default();
+        config.set_data_dir(&dir);
+        config.create_data_dirs().unwrap();
+        assert!(dir.is_dir());
+        assert!(Config::default().create_data_dirs().is_ok());
+    }
+
+    #[test]
+    fn data_dir_moves_every_file() {
+        let mut config = Config::default();
+        config.set_data_dir(Path::new("/var/lib/botd"));
+        assert_eq!(config.birds_path, Path::new("/var/lib/botd/birds.json"));
+        assert_eq!(config.history_path, Path::new("/var/lib/botd/posted_history.json"));
+        assert_eq!(config.session_path, Path::new("/var/lib/botd/session.json"));
+        assert_eq!(config.post_log_path, Path::new("/var/lib/botd/posts.jsonl"));
+        assert_eq!(config.lock_path, Path::new("/var/lib/botd/botd.lock"));
+        assert_eq!(config.region_path("US-NY"), Path::new("/var/lib/botd/region.US-NY.json"));
+        assert_eq!(config.page_cache_path("norcar"), Path::new("/var/lib/botd/pages/norcar.html"));
+        assert_eq!(config.shuffle_queue_path(), Path::new("/var/lib/botd/shuffle_queue.json"));
+        assert_eq!(config.blocklist_path(), Path::new("/var/lib/botd/blocklist.txt"));
+        assert_eq!(config.allowlist_path(), Path::new("/var/lib/botd/allowlist.txt"));
+        assert_eq!(config.photo_path("norcar", 0, "jpg"), Path::new("/var/lib/botd/norcar.jpg"));
+        assert_eq!(config.photo_path("norcar", 1, "png"), Path::new("/var/lib/botd/norcar-2.png"));
+    }
+
+    #[test]
+    fn each_locale_has_its_own_taxonomy() {
+        let mut config = Config::default();
+        config.set_data_dir(Path::new("/var/lib/botd"));
+        assert_eq!(config.taxonomy_path(), Path::new("/var/lib/botd/birds.json"));
+        config.locale = Some("pt_BR".to_string());
+        assert_eq!(config.taxonomy_path(), Path::new("/var/lib/botd/birds.pt_BR.json"));
+        config.birds_path = PathBuf::from("taxonomy");
+        assert_eq!(config.taxonomy_path(), Path::new("taxonomy.pt_BR"));
+
+        assert!(["es", "pt_BR", "zh_SIM", "es-MX"].iter().all(|l| is_locale(l)));
+        assert!(!["", "e", "../es", "es/x", "es_", "spanish"].iter().any(|l| is_locale(l)));
+    }
+
+    #[test]
+    fn categories_have_to_be_ebird_ones() {
+        assert_eq!(parse_categories("BOTD_CATEGORIES", " Species, issf,").unwrap(), ["species", "issf"]);
+        assert!(parse_categories("BOTD_CATEGORIES", " , ").unwrap_err().contains("at least one"));
+        assert!(parse_categories("BOTD_CATEGORIES", "species,subspecies").unwrap_err().contains("'subspecies'"));
+    }
+
+    #[test]
+    fn categories_are_allowed_and_denied() {
+        let strings = |s: &[&str]| s.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(category_rules(None, None), Ok((strings(&["species"]), vec![])));
+        assert_eq!(category_rules(Some("species,hybrid"), None), Ok((strings(&["species", "hybrid"]), vec![])));
+        assert_eq!(category_rules(None, Some("domestic,form")), Ok((strings(&CATEGORIES), strings(&["domestic", "form"]))));
+        assert_eq!(category_rules(Some("species"), Some("domestic")), Ok((strings(&["species"]), strings(&["domestic"]))));
+
+        let problems = category_rules(Some("species,Hybrid"), Some("hybrid,domestic")).unwrap_err();
+        assert_eq!(problems, ["BOTD_CATEGORIES and BOTD_EXCLUDE_CATEGORIES both have hybrid"]);
+        let problems = category_rules(Some("bogus"), Some("domestic,junk")).unwrap_err();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[1].starts_with("BOTD_EXCLUDE_CATEGORIES has 'junk'"), "{:?}", problems);
+    }
+
+    #[test]
+    fn utc_offsets_round_trip() {
+        for (given, shown) in [("+05:30", "+05:30"), ("-5", "-05:00"), ("-00:30", "-00:30"), ("Z", "+00:00")] {
+            assert_eq!(parse_utc_offset(given).map(format_utc_offset).as_deref(), Some(shown), "{}", given);
+        }
+        for invalid in ["", "5", "+5:60", "+25", "+05:30:00", "EST"] {
+            assert_eq!(parse_utc_offset(invalid), None, "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::{fs, path::Path};
+
+use log::warn;
+use serde::Deserialize;
+
+/// Credentials kept in a file rather than the environment, e.g. a Docker or systemd secret,
+/// named by `BOTD_CREDENTIALS_FILE`: a JSON object with any of `handle`, `password`, `email`,
+/// `ebird_api_key`, and `mastodon_token`. A variable that is set takes precedence over the file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    handle: Option<String>,
+    password: Option<String>,
+    email: Option<String>,
+    ebird_api_key: Option<String>,
+    mastodon_token: Option<String>,
+}
+
+impl Credentials {
+    /// Read the file at `path`, warning if anyone at all may read it
+    pub fn load(path: impl AsRef<Path>) -> Result<Credentials, String> {
+        let path = path.as_ref();
+        let credentials = Credentials::read(path)?;
+        if world_readable(path) {
+            warn!("'{}' can be read by every user on this machine; chmod 600 it to keep the credentials in it to yourself",
+                path.display());
+        }
+        Ok(credentials)
+    }
+
+    /// Like [`Credentials::load`], without the warning, for reading the file again
+    pub fn read(path: impl AsRef<Path>) -> Result<Credentials, String> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| format!("unable to read '{}': {}", path.display(), e))?;
+        serde_json::from_str(&contents).map_err(|e| format!("'{}' is not a valid credentials file: {}", path.display(), e))
+    }
+
+    /// The value the file has for the variable `name`, e.g. `BOTD_PASS`, if it has one
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let value = match name {
+            "BOTD_HANDLE" => &self.handle,
+            "BOTD_PASS" => &self.password,
+            "BOTD_EMAIL" => &self.email,
+            "EBIRD_API_KEY" => &self.ebird_api_key,
+            "BOTD_MASTODON_TOKEN" => &self.mastodon_token,
+            _ => &None,
+        };
+        value.as_deref().filter(|v| !v.trim().is_empty())
+    }
+}
+
+#[cfg(unix)]
+fn world_readable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o004 != 0)
+}
+
+/// Windows has no such permission bits to check
+#[cfg(not(unix))]
+fn world_readable(_: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("botd-{}-{}.json", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn credentials_are_looked_up_by_variable() {
+        let path = temp_file("credentials", r#"{"handle": "bird.bsky.social", "password": "hunter2", "email": " "}"#);
+        let credentials = Credentials::load(&path).unwrap();
+        assert_eq!(credentials.get("BOTD_HANDLE"), Some("bird.bsky.social"));
+        assert_eq!(credentials.get("BOTD_PASS"), Some("hunter2"));
+        assert_eq!(credentials.get("BOTD_EMAIL"), None);
+        assert_eq!(credentials.get("EBIRD_API_KEY"), None);
+        assert_eq!(credentials.get("BOTD_REGION"), None);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn bad_files_are_rejected() {
+        let path = temp_file("credentials-typo", r#"{"pasword": "hunter2"}"#);
+        assert!(Credentials::load(&path).unwrap_err().contains("unknown field `pasword`"));
+        let _ = fs::remove_file(&path);
+        assert!(Credentials::load(&path).unwrap_err().starts_with("unable to read"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn world_readable_files_are_noticed() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_file("credentials-mode", "{}");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(world_readable(&path));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(!world_readable(&path));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,323 @@
+use std::{fmt, io, time::Duration};
+
+use crate::redact;
+
+/// Every way a bird of the day run can fail
+#[derive(Debug)]
+pub enum BirdError {
+    /// A request could not be completed (connection, timeout, TLS, ...)
+    Network(minreq::Error),
+    /// eBird or the Macaulay Library answered with a non-200 status code
+    Ebird { status: i32 },
+    /// The local taxonomy file could not be read or written
+    Io(io::Error),
+    /// Something we downloaded or read from disk was not in the expected shape
+    Parse(String),
+    /// The eBird species page for the chosen bird had no usable photo
+    Scrape(String),
+    /// The eBird species page had none of the tags photos are scraped from, so eBird has most
+    /// likely changed its layout; holds the start of the page's `<head>`
+    LayoutChanged(String),
+    /// Bluesky session creation failed, for `reason` if Bluesky said why in a way we know
+    Auth { status: Option<i32>, reason: Option<AuthFailure>, message: String },
+    /// Uploading the photo or creating the post record failed
+    Post { status: Option<i32>, message: String },
+    /// Uploading the photo or posting the status to Mastodon failed
+    Mastodon { status: Option<i32>, message: String },
+    /// One or more required environment variables are not set
+    MissingEnv(Vec<String>),
+    /// A configuration value is invalid
+    Config(String),
+    /// eBird doesn't recognize the configured region code
+    InvalidRegion(String),
+    /// Filtering left no birds to choose from
+    NoCandidates(String),
+    /// A server asked us to back off for longer than we're willing to wait
+    RateLimited { retry_after: Duration },
+    /// The run took longer than `Config::max_run_duration`
+    DeadlineExceeded,
+    /// Another run of the bot holds the lock, e.g. `process 1234`
+    AlreadyRunning(String),
+    /// Whoever was asked to approve the post turned it down
+    Declined,
+}
+
+/// Why Bluesky turned down logging in, from the `error` code in its response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailure {
+    /// The handle or password is wrong (`AuthenticationRequired`)
+    InvalidCredentials,
+    /// The account asks for a code sent by email to log in (`AuthFactorTokenRequired`)
+    TwoFactorRequired,
+    /// Bluesky moderation has taken the account down (`AccountTakedown`)
+    AccountTakedown,
+    /// The account has been deactivated by its owner (`AccountDeactivated`)
+    AccountDeactivated,
+}
+
+impl AuthFailure {
+    /// The failure for an `error` code from `com.atproto.server.createSession`, if it's one we know
+    pub fn from_code(code: &str) -> Option<AuthFailure> {
+        match code {
+            "AuthenticationRequired" => Some(AuthFailure::InvalidCredentials),
+            "AuthFactorTokenRequired" => Some(AuthFailure::TwoFactorRequired),
+            "AccountTakedown" => Some(AuthFailure::AccountTakedown),
+            "AccountDeactivated" => Some(AuthFailure::AccountDeactivated),
+            _ => None,
+        }
+    }
+
+    /// What to do about it
+    pub fn hint(self) -> &'static str {
+        match self {
+            AuthFailure::InvalidCredentials =>
+                "check BOTD_HANDLE and BOTD_PASS, and use an app password from Settings > Privacy and security > App passwords rather than the account password",
+            AuthFailure::TwoFactorRequired =>
+                "the account has email two-factor authentication on, which a bot can't answer; use an app password from Settings > Privacy and security > App passwords, which skips it",
+            AuthFailure::AccountTakedown =>
+                "the account has been taken down by Bluesky moderation; see the email Bluesky sent about it, or appeal at https://bsky.app",
+            AuthFailure::AccountDeactivated => "the account has been deactivated; log in at https://bsky.app to reactivate it",
+        }
+    }
+}
+
+impl BirdError {
+    /// Whether a fresh attempt could reasonably succeed: transport errors (including timeouts),
+    /// 5xx/408/429 responses, and species pages that came back without the expected photo tags.
+    /// Other 4xx responses (e.g. a wrong password), local file problems, bad configuration, and a
+    /// change to eBird's page layout are fatal.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BirdError::Network(_) | BirdError::Scrape(_) | BirdError::RateLimited { .. } => true,
+            BirdError::Ebird { status } => retryable_status(*status),
+            BirdError::Auth { status, .. } | BirdError::Post { status, .. } | BirdError::Mastodon { status, .. } =>
+                status.is_some_and(retryable_status),
+            BirdError::Io(_)
+            | BirdError::Parse(_)
+            | BirdError::LayoutChanged(_)
+            | BirdError::MissingEnv(_)
+            | BirdError::Config(_)
+            | BirdError::InvalidRegion(_)
+            | BirdError::NoCandidates(_)
+            | BirdError::DeadlineExceeded
+            | BirdError::AlreadyRunning(_)
+            | BirdError::Declined => false,
+        }
+    }
+
+    /// Process exit code for a run that failed with this error, so schedulers can tell failures apart:
+    ///
+    /// | code | failure |
+    /// |------|---------|
+    /// | 1 | anything else: network errors, running out of time |
+    /// | 2 | configuration |
+    /// | 3 | local files: the taxonomy, history, or filtering it down to nothing |
+    /// | 4 | eBird: bad responses, a species page without a photo, or a change to its layout |
+    /// | 5 | Bluesky authentication |
+    /// | 6 | creating the Bluesky or Mastodon post |
+    /// | 7 | another instance is running |
+    /// | 8 | the post was turned down when asked for approval |
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            BirdError::Network(_) | BirdError::DeadlineExceeded => 1,
+            BirdError::MissingEnv(_) | BirdError::Config(_) | BirdError::InvalidRegion(_) => 2,
+            BirdError::Io(_) | BirdError::Parse(_) | BirdError::NoCandidates(_) => 3,
+            BirdError::Ebird { .. } | BirdError::Scrape(_) | BirdError::LayoutChanged(_) => 4,
+            BirdError::Auth { .. } => 5,
+            BirdError::Post { .. } | BirdError::Mastodon { .. } | BirdError::RateLimited { .. } => 6,
+            BirdError::AlreadyRunning(_) => 7,
+            BirdError::Declined => 8,
+        }
+    }
+
+    /// Short name of the variant, e.g. `scrape`, for tools reading `--output json` to tell failures apart
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BirdError::Network(_) => "network",
+            BirdError::Ebird { .. } => "ebird",
+            BirdError::Io(_) => "io",
+            BirdError::Parse(_) => "parse",
+            BirdError::Scrape(_) => "scrape",
+            BirdError::LayoutChanged(_) => "layout_changed",
+            BirdError::Auth { .. } => "auth",
+            BirdError::Post { .. } => "post",
+            BirdError::Mastodon { .. } => "mastodon",
+            BirdError::MissingEnv(_) => "missing_env",
+            BirdError::Config(_) => "config",
+            BirdError::InvalidRegion(_) => "invalid_region",
+            BirdError::NoCandidates(_) => "no_candidates",
+            BirdError::RateLimited { .. } => "rate_limited",
+            BirdError::DeadlineExceeded => "deadline_exceeded",
+            BirdError::AlreadyRunning(_) => "already_running",
+            BirdError::Declined => "declined",
+        }
+    }
+
+    /// The minimum time to wait before trying again, if the server told us
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            BirdError::RateLimited { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BirdError {
+    /// Response bodies can echo credentials back, so they are masked before anyone sees them
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&redact(&Unredacted(self).to_string()))
+    }
+}
+
+/// The message of an error as is, secrets and all
+struct Unredacted<'a>(&'a BirdError);
+
+impl fmt::Display for Unredacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            BirdError::Network(e) => write!(f, "network error: {}", e),
+            BirdError::Ebird { status } => write!(f, "bad response code from eBird: {}", status),
+            BirdError::Io(e) => write!(f, "I/O error: {}", e),
+            BirdError::Parse(m) => write!(f, "parse error: {}", m),
+            BirdError::Scrape(m) => write!(f, "no usable photo: {}", m),
+            BirdError::LayoutChanged(head) =>
+                write!(f, "eBird species page has none of the expected tags, its layout may have changed: {}", head),
+            BirdError::Auth { status, reason, message } => {
+                write_stage(f, "Bluesky authentication", status, message)?;
+                match reason {
+                    Some(r) => write!(f, " ({})", r.hint()),
+                    None => Ok(()),
+                }
+            }
+            BirdError::Post { status, message } => write_stage(f, "Bluesky post", status, message),
+            BirdError::Mastodon { status, message } => write_stage(f, "Mastodon post", status, message),
+            BirdError::MissingEnv(names) => write!(f, "missing required environment variables: {}", names.join(", ")),
+            BirdError::Config(m) => write!(f, "invalid configuration: {}", m),
+            BirdError::InvalidRegion(r) => write!(f, "'{}' is not a valid eBird region code", r),
+            BirdError::NoCandidates(m) => write!(f, "no birds to choose from: {}", m),
+            BirdError::RateLimited { retry_after } => write!(f, "rate limited for another {:?}", retry_after),
+            BirdError::DeadlineExceeded => write!(f, "ran out of time for this run"),
+            BirdError::AlreadyRunning(holder) => write!(f, "another instance is running ({})", holder),
+            BirdError::Declined => write!(f, "the post was not approved"),
+        }
+    }
+}
+
+fn retryable_status(status: i32) -> bool {
+    status >= 500 || status == 408 || status == 429
+}
+
+fn write_stage(f: &mut fmt::Formatter<'_>, stage: &str, status: &Option<i32>, message: &str) -> fmt::Result {
+    match status {
+        Some(s) => write!(f, "{} failed (response code {}): {}", stage, s, message),
+        None => write!(f, "{} failed: {}", stage, message),
+    }
+}
+
+impl std::error::Error for BirdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BirdError::Network(e) => Some(e),
+            BirdError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<minreq::Error> for BirdError {
+    fn from(e: minreq::Error) -> Self {
+        BirdError::Network(e)
+    }
+}
+
+impl From<io::Error> for BirdError {
+    fn from(e: io::Error) -> Self {
+        BirdError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_failures_are_fatal() {
+        for status in [400, 401, 403] {
+            assert!(!BirdError::Auth { status: Some(status), reason: None, message: String::new() }.is_retryable());
+        }
+    }
+
+    #[test]
+    fn timeouts_are_retryable() {
+        let timeout = minreq::Error::IoError(io::Error::from(io::ErrorKind::TimedOut));
+        assert!(BirdError::Network(timeout).is_retryable());
+        assert!(BirdError::Ebird { status: 504 }.is_retryable());
+        assert!(BirdError::Post { status: Some(408), message: String::new() }.is_retryable());
+    }
+
+    #[test]
+    fn server_errors_are_retryable() {
+        assert!(BirdError::Ebird { status: 503 }.is_retryable());
+        assert!(BirdError::Auth { status: Some(502), reason: None, message: String::new() }.is_retryable());
+        assert!(!BirdError::Ebird { status: 404 }.is_retryable());
+    }
+
+    #[test]
+    fn local_errors_are_fatal() {
+        assert!(!BirdError::Io(io::Error::from(io::ErrorKind::NotFound)).is_retryable());
+        assert!(!BirdError::MissingEnv(vec!["BOTD_PASS".to_string()]).is_retryable());
+        assert!(!BirdError::Parse(String::new()).is_retryable());
+    }
+
+    #[test]
+    fn exit_codes_tell_failures_apart() {
+        let cases = [
+            (BirdError::Network(minreq::Error::AddressNotFound), 1),
+            (BirdError::DeadlineExceeded, 1),
+            (BirdError::MissingEnv(vec!["BOTD_PASS".to_string()]), 2),
+            (BirdError::Config(String::new()), 2),
+            (BirdError::InvalidRegion("XX".to_string()), 2),
+            (BirdError::Io(io::Error::from(io::ErrorKind::NotFound)), 3),
+            (BirdError::Parse(String::new()), 3),
+            (BirdError::NoCandidates(String::new()), 3),
+            (BirdError::Ebird { status: 503 }, 4),
+            (BirdError::Scrape(String::new()), 4),
+            (BirdError::LayoutChanged(String::new()), 4),
+            (BirdError::Auth { status: Some(401), reason: None, message: String::new() }, 5),
+            (BirdError::Post { status: Some(400), message: String::new() }, 6),
+            (BirdError::Mastodon { status: Some(422), message: String::new() }, 6),
+            (BirdError::RateLimited { retry_after: Duration::from_secs(1) }, 6),
+            (BirdError::AlreadyRunning("process 1".to_string()), 7),
+            (BirdError::Declined, 8),
+        ];
+        for (e, code) in &cases {
+            assert_eq!(e.exit_code(), *code, "{}", e);
+        }
+        let kinds: std::collections::HashSet<&str> = cases.iter().map(|(e, _)| e.kind()).collect();
+        assert_eq!(kinds.len(), cases.len(), "every variant has a kind of its own");
+    }
+
+    #[test]
+    fn tokens_in_response_bodies_are_masked() {
+        let e = BirdError::Auth {
+            status: Some(400),
+            reason: None,
+            message: r#"{"error":"InvalidToken","message":"Bad token eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJ4In0.c2ln"}"#.to_string(),
+        };
+        assert_eq!(e.to_string(), r#"Bluesky authentication failed (response code 400): {"error":"InvalidToken","message":"Bad token ***"}"#);
+    }
+
+    #[test]
+    fn known_auth_failures_say_what_to_do() {
+        assert_eq!(AuthFailure::from_code("AuthFactorTokenRequired"), Some(AuthFailure::TwoFactorRequired));
+        assert_eq!(AuthFailure::from_code("AccountTakedown"), Some(AuthFailure::AccountTakedown));
+        assert_eq!(AuthFailure::from_code("InternalServerError"), None);
+        let e = BirdError::Auth {
+            status: Some(401),
+            reason: Some(AuthFailure::TwoFactorRequired),
+            message: r#"{"error":"AuthFactorTokenRequired"}"#.to_string(),
+        };
+        assert!(e.to_string().ends_with(&format!("}} ({})", AuthFailure::TwoFactorRequired.hint())), "{}", e);
+        assert!(!e.is_retryable());
+    }
+}
@@ -0,0 +1,273 @@
+use std::{collections::HashSet, fmt, fs, io::ErrorKind, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+
+use crate::{BirdCatalog, BirdError};
+
+/// One bird that was successfully posted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub species_code: String,
+    /// RFC 3339 timestamp of the post
+    pub posted_at: String,
+    /// AT URI of the Bluesky post, e.g. `at://did:plc:.../app.bsky.feed.post/3k...`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    /// The post was deleted since, so the bird counts as not posted
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub deleted: bool,
+}
+
+/// How far back the history keeps a bird from being picked again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookback {
+    /// The last this many posts, e.g. `30`
+    Posts(usize),
+    /// The posts made within this many days of the pick, e.g. with `365d` a bird posted on
+    /// 2024-05-04 can come up again from 2025-05-04
+    Days(u32),
+    /// Every post ever made, as `never`
+    Forever,
+}
+
+impl Default for Lookback {
+    fn default() -> Self {
+        Lookback::Posts(30)
+    }
+}
+
+impl FromStr for Lookback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        if s == "never" {
+            return Ok(Lookback::Forever);
+        }
+        match s.strip_suffix('d') {
+            Some(days) => days.trim().parse().map(Lookback::Days),
+            None => s.parse().map(Lookback::Posts),
+        }.map_err(|_| format!("'{}' is not a number of posts, a number of days like 365d, or never", s))
+    }
+}
+
+impl fmt::Display for Lookback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lookback::Posts(n) => write!(f, "{}", n),
+            Lookback::Days(n) => write!(f, "{}d", n),
+            Lookback::Forever => f.write_str("never"),
+        }
+    }
+}
+
+/// Recently posted birds, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Load the history file, treating a file that doesn't exist yet as an empty history
+    pub fn load(path: impl AsRef<Path>) -> Result<History, BirdError> {
+        let path = path.as_ref();
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(History::default()),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_str(&contents)
+            .map_err(|e| BirdError::Parse(format!("'{}' is not a valid history file: {}", path.display(), e)))
+    }
+
+    /// Write the history file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BirdError> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| BirdError::Parse(format!("Error converting history to JSON: {}", e)))?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Add a newly posted bird, keeping only the entries one of `lookbacks` still needs: the last
+    /// so many posts that weren't deleted, the ones posted within so many days of this one, or all
+    /// of them. The most recent post that can still be deleted is always kept, for `delete-last`.
+    pub fn record(&mut self, species_code: &str, posted_at: &str, uri: Option<&str>, lookbacks: &[Lookback]) {
+        self.entries.push(HistoryEntry {
+            species_code: species_code.to_string(),
+            posted_at: posted_at.to_string(),
+            uri: uri.map(str::to_string),
+            deleted: false,
+        });
+        let now = parse_time(posted_at);
+        let deletable = self.entries.iter().rposition(|e| e.uri.is_some() && !e.deleted);
+        // Newest first, counting only the posts that weren't deleted, as `recent` does
+        let mut posted = 0;
+        let mut keep: Vec<bool> = self.entries.iter().enumerate().rev().map(|(i, e)| {
+            posted += usize::from(!e.deleted);
+            Some(i) == deletable || lookbacks.iter().any(|lookback| match *lookback {
+                Lookback::Posts(window) => posted <= window,
+                // An entry whose time can't be read is kept rather than lost
+                Lookback::Days(days) => now.zip(parse_time(&e.posted_at)).is_none_or(|(now, t)| t > now - Duration::days(days.into())),
+                Lookback::Forever => true,
+            })
+        }).collect();
+        keep.reverse();
+        let mut keep = keep.into_iter();
+        self.entries.retain(|_| keep.next().unwrap_or(true));
+    }
+
+    /// Species codes of the posts that weren't deleted and that `lookback` reaches back to from
+    /// the day starting at `today`. An entry whose time can't be read counts as recent.
+    pub fn recent(&self, lookback: Lookback, today: OffsetDateTime) -> HashSet<&str> {
+        let posted = self.entries.iter().rev().filter(|e| !e.deleted);
+        match lookback {
+            Lookback::Posts(window) => posted.take(window).map(|e| e.species_code.as_str()).collect(),
+            Lookback::Days(days) => {
+                let since = today.date() - Duration::days(i64::from(days) - 1);
+                posted.filter(|e| parse_time(&e.posted_at).is_none_or(|t| t.to_offset(today.offset()).date() >= since))
+                    .map(|e| e.species_code.as_str()).collect()
+            }
+            Lookback::Forever => posted.map(|e| e.species_code.as_str()).collect(),
+        }
+    }
+
+    /// Position of the most recent post of `species_code` that wasn't deleted, where a smaller
+    /// number means longer ago
+    pub fn last_posted(&self, species_code: &str) -> Option<usize> {
+        self.entries.iter().rposition(|e| e.species_code == species_code && !e.deleted)
+    }
+
+    /// The most recent post that can still be deleted
+    pub fn last_deletable(&self) -> Option<&HistoryEntry> {
+        self.entries.iter().rev().find(|e| e.uri.is_some() && !e.deleted)
+    }
+
+    /// Drop the posts of birds that aren't in `catalog`, e.g. species lumped into another since,
+    /// returning their species codes
+    pub fn prune(&mut self, catalog: &BirdCatalog) -> Vec<String> {
+        let mut dropped = Vec::new();
+        self.entries.retain(|e| {
+            let known = catalog.by_species_code(&e.species_code).is_some();
+            if !known {
+                dropped.push(e.species_code.clone());
+            }
+            known
+        });
+        dropped
+    }
+
+    /// Mark the post at `uri` as deleted, returning whether it was in the history
+    pub fn mark_deleted(&mut self, uri: &str) -> bool {
+        let entry = self.entries.iter_mut().find(|e| e.uri.as_deref() == Some(uri));
+        entry.map(|e| e.deleted = true).is_some()
+    }
+}
+
+/// The time of a post, as the history has it
+fn parse_time(posted_at: &str) -> Option<OffsetDateTime> {
+    OffsetDateTime::parse(posted_at, &Rfc3339).ok()
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deleted_posts_can_be_picked_again() {
+        let mut history = History::default();
+        history.record("norcar", "2024-05-01T12:00:00Z", Some("at://did:plc:bot/app.bsky.feed.post/1"), &[Lookback::Posts(30)]);
+        history.record("blujay", "2024-05-02T12:00:00Z", None, &[Lookback::Posts(30)]);
+        assert_eq!(history.last_deletable().unwrap().species_code, "norcar");
+
+        assert!(history.mark_deleted("at://did:plc:bot/app.bsky.feed.post/1"));
+        assert!(!history.mark_deleted("at://did:plc:bot/app.bsky.feed.post/2"));
+        assert_eq!(history.recent(Lookback::Posts(30), OffsetDateTime::now_utc()), HashSet::from(["blujay"]));
+        assert_eq!(history.last_posted("norcar"), None);
+        assert!(history.last_deletable().is_none());
+    }
+
+    #[test]
+    fn deleted_posts_do_not_shorten_the_window() {
+        let uri = |n: u32| format!("at://did:plc:bot/app.bsky.feed.post/{}", n);
+        let mut history = History::default();
+        for (n, code) in (1..).zip(["norcar", "blujay", "amerob"]) {
+            history.record(code, "2024-05-01T12:00:00Z", Some(&uri(n)), &[Lookback::Posts(2)]);
+        }
+        assert!(history.mark_deleted(&uri(3)));
+        history.record("houspa", "2024-05-02T12:00:00Z", Some(&uri(4)), &[Lookback::Posts(2)]);
+        assert_eq!(history.recent(Lookback::Posts(2), OffsetDateTime::now_utc()), HashSet::from(["blujay", "houspa"]));
+
+        // With no window at all, the last post is still there for delete-last
+        let mut history = History::default();
+        history.record("norcar", "2024-05-01T12:00:00Z", Some(&uri(1)), &[Lookback::Posts(0)]);
+        history.record("blujay", "2024-05-02T12:00:00Z", None, &[Lookback::Posts(0)]);
+        assert_eq!(history.last_deletable().and_then(|e| e.uri.clone()), Some(uri(1)));
+        assert!(history.recent(Lookback::Posts(0), OffsetDateTime::now_utc()).is_empty());
+    }
+
+    #[test]
+    fn birds_gone_from_the_taxonomy_are_pruned() {
+        let mut history = History::default();
+        for code in ["norcar", "dodo", "blujay"] {
+            history.record(code, "2024-05-01T12:00:00Z", None, &[Lookback::Posts(30)]);
+        }
+        let birds = serde_json::json!(["norcar", "blujay"].map(|code| serde_json::json!({
+            "sciName": code, "comName": code, "speciesCode": code, "category": "species", "taxonOrder": 1.0,
+        })));
+        let catalog = BirdCatalog::new(serde_json::from_value(birds).unwrap());
+
+        assert_eq!(history.prune(&catalog), ["dodo"]);
+        assert_eq!(history.recent(Lookback::Posts(30), OffsetDateTime::now_utc()), HashSet::from(["norcar", "blujay"]));
+        assert!(history.prune(&catalog).is_empty());
+    }
+
+    #[test]
+    fn lookback_is_posts_days_or_never() {
+        for (given, lookback, shown) in [
+            ("30", Lookback::Posts(30), "30"),
+            ("365d", Lookback::Days(365), "365d"),
+            (" 7 D", Lookback::Days(7), "7d"),
+            ("Never", Lookback::Forever, "never"),
+        ] {
+            assert_eq!(given.parse(), Ok(lookback));
+            assert_eq!(lookback.to_string(), shown);
+        }
+        for bad in ["", "-1", "d", "a year"] {
+            assert!(bad.parse::<Lookback>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn days_lookback_counts_from_the_day_of_the_pick() {
+        let mut history = History::default();
+        history.record("norcar", "2024-05-04T23:30:00Z", None, &[Lookback::Days(365)]);
+        history.record("blujay", "2025-05-01T12:00:00Z", None, &[Lookback::Days(365)]);
+        let day = |s: &str| OffsetDateTime::parse(s, &Rfc3339).unwrap();
+
+        assert_eq!(history.recent(Lookback::Days(365), day("2025-05-03T00:00:00Z")), HashSet::from(["norcar", "blujay"]));
+        assert_eq!(history.recent(Lookback::Days(365), day("2025-05-04T00:00:00Z")), HashSet::from(["blujay"]));
+        // Late on the 4th in UTC is already the 5th east of it, so the year is up a day later there
+        assert_eq!(history.recent(Lookback::Days(365), day("2025-05-04T00:00:00+02:00")), HashSet::from(["norcar", "blujay"]));
+        assert_eq!(history.recent(Lookback::Forever, day("2099-01-01T00:00:00Z")), HashSet::from(["norcar", "blujay"]));
+        assert!(history.recent(Lookback::Days(0), day("2025-05-01T00:00:00Z")).is_empty());
+
+        history.record("amerob", "2025-05-05T12:00:00Z", None, &[Lookback::Days(365)]);
+        let codes: Vec<&str> = history.entries.iter().map(|e| e.species_code.as_str()).collect();
+        assert_eq!(codes, ["blujay", "amerob"]);
+        history.record("norcar", "2030-01-01T00:00:00Z", None, &[Lookback::Forever]);
+        assert_eq!(history.entries.len(), 3);
+    }
+
+    #[test]
+    fn history_without_uris_still_loads() {
+        let history: History = serde_json::from_str(r#"{"entries": [{"species_code": "norcar", "posted_at": "2024-05-01T12:00:00Z"}]}"#).unwrap();
+        assert_eq!(history.entries[0].uri, None);
+        assert!(!history.entries[0].deleted);
+        assert!(!serde_json::to_string(&history).unwrap().contains("deleted"));
+    }
+}
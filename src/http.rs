@@ -0,0 +1,180 @@
+//! Requests made the same way everywhere: with the bot's `User-Agent`, a timeout, and any
+//! proxy, rate limits waited out, and responses outside 2xx turned into errors
+
+use std::{
+    collections::HashMap,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+use serde_json::Value;
+use time::PrimitiveDateTime;
+
+use crate::{AuthFailure, BirdError, Config};
+
+/// How many times a rate limited request is sent again before giving up on it
+const RATE_LIMIT_RETRIES: u32 = 3;
+
+/// A GET request for `url` that gives up after `timeout` seconds
+pub(crate) fn get(config: &Config, url: &str, timeout: u64) -> minreq::Request {
+    with_defaults(config, url, minreq::get(url), timeout)
+}
+
+/// A POST request to `url` without a body yet, giving up after `timeout` seconds
+pub(crate) fn post(config: &Config, url: &str, timeout: u64) -> minreq::Request {
+    with_defaults(config, url, minreq::post(url), timeout)
+}
+
+/// `request` for `url` with the bot's `User-Agent`, a timeout, and the configured proxy if there is one
+fn with_defaults(config: &Config, url: &str, request: minreq::Request, timeout: u64) -> minreq::Request {
+    let request = request
+        .with_header("User-Agent", config.user_agent())
+        .with_timeout(timeout);
+    match config.proxies.for_url(url) {
+        Some(proxy) => request.with_proxy(proxy),
+        None => request,
+    }
+}
+
+/// A POST request sending `body` to `url` as JSON, giving up after `timeout` seconds
+pub(crate) fn post_json(config: &Config, url: &str, body: &Value, timeout: u64) -> minreq::Request {
+    post(config, url, timeout)
+        .with_header("Content-Type", "application/json")
+        .with_body(body.to_string())
+}
+
+/// A response with a status code outside 2xx
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HttpError {
+    pub status: i32,
+    pub body: String,
+}
+
+impl HttpError {
+    /// The error for a response from eBird or the Macaulay Library
+    pub fn ebird(self) -> BirdError {
+        BirdError::Ebird { status: self.status }
+    }
+
+    /// The error for a response to logging in to Bluesky, telling apart the failures its `error`
+    /// code names
+    pub fn auth(self) -> BirdError {
+        let reason = serde_json::from_str::<Value>(&self.body).ok()
+            .and_then(|v| v["error"].as_str().and_then(AuthFailure::from_code));
+        BirdError::Auth { status: Some(self.status), reason, message: self.body }
+    }
+
+    /// The error for a response to uploading the photo or creating the post on Bluesky
+    pub fn post(self) -> BirdError {
+        BirdError::Post { status: Some(self.status), message: self.body }
+    }
+
+    /// The error for a response from Mastodon
+    pub fn mastodon(self) -> BirdError {
+        BirdError::Mastodon { status: Some(self.status), message: self.body }
+    }
+}
+
+/// `r` if its status code is 2xx, otherwise its status code and body
+pub(crate) fn check(r: minreq::Response) -> Result<minreq::Response, HttpError> {
+    if (200..300).contains(&r.status_code) {
+        Ok(r)
+    } else {
+        Err(HttpError { status: r.status_code, body: body_text(&r) })
+    }
+}
+
+/// Best-effort response body for error messages
+pub(crate) fn body_text(r: &minreq::Response) -> String {
+    String::from_utf8_lossy(r.as_bytes()).into_owned()
+}
+
+/// Send a request. When rate limited, wait as long as the response asks and send it again, up
+/// to `RATE_LIMIT_RETRIES` times. A wait longer than `config.max_rate_limit_wait` isn't sat out
+/// here but fails with `BirdError::RateLimited`, as does running out of retries.
+pub(crate) fn send<F>(config: &Config, request: F) -> Result<minreq::Response, BirdError>
+where
+    F: Fn() -> minreq::Request,
+{
+    let max_wait = Duration::from_secs(config.max_rate_limit_wait);
+    let mut retries = 0;
+    loop {
+        let r = request().send()?;
+        if r.status_code != 429 {
+            return Ok(r);
+        }
+        let retry_after = rate_limit_wait(&r.headers, SystemTime::now()).unwrap_or(max_wait);
+        if retries == RATE_LIMIT_RETRIES || retry_after > max_wait {
+            return Err(BirdError::RateLimited { retry_after });
+        }
+        info!("Rate limited, waiting {:?}", retry_after);
+        thread::sleep(retry_after);
+        retries += 1;
+    }
+}
+
+/// How long a 429 response asks us to wait, from its `Retry-After` (seconds or an HTTP date)
+/// or `RateLimit-Reset` (Unix timestamp) header
+fn rate_limit_wait(headers: &HashMap<String, String>, now: SystemTime) -> Option<Duration> {
+    let until = |t: SystemTime| t.duration_since(now).unwrap_or(Duration::ZERO);
+    if let Some(v) = headers.get("retry-after") {
+        if let Ok(secs) = v.trim().parse() {
+            return Some(Duration::from_secs(secs));
+        }
+        if let Some(date) = parse_http_date(v) {
+            return Some(until(date));
+        }
+    }
+    let reset = headers.get("ratelimit-reset").and_then(|v| v.trim().parse().ok())?;
+    Some(until(UNIX_EPOCH + Duration::from_secs(reset)))
+}
+
+/// Parse an HTTP date, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let format = time::format_description::parse("[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT").ok()?;
+    PrimitiveDateTime::parse(value.trim(), &format).ok().map(|t| t.assume_utc().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Response, TestServer};
+
+    #[test]
+    fn requests_identify_the_bot() {
+        let server = TestServer::start(|_| Response::new(200, ""));
+        let config = Config { email: "birder@example.com".to_string(), ..Config::default() };
+        get(&config, &server.url, 5).send().unwrap();
+        post_json(&config, &server.url, &serde_json::json!({}), 5).send().unwrap();
+        for r in server.requests() {
+            assert_eq!(r.header("User-Agent"), Some("BirdOfTheDayBot (birder@example.com)"));
+        }
+    }
+
+    #[test]
+    fn non_2xx_responses_are_errors() {
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/accepted" => Response::new(202, ""),
+            _ => Response::new(404, "no such bird"),
+        });
+        let config = Config::default();
+        assert!(check(get(&config, &format!("{}/accepted", server.url), 5).send().unwrap()).is_ok());
+        let e = check(get(&config, &format!("{}/missing", server.url), 5).send().unwrap()).unwrap_err();
+        assert_eq!(e, HttpError { status: 404, body: "no such bird".to_string() });
+    }
+
+    #[test]
+    fn retry_after_can_be_a_date() {
+        let headers = HashMap::from([("retry-after".to_string(), "Wed, 21 Oct 2015 07:28:00 GMT".to_string())]);
+        let now = UNIX_EPOCH + Duration::from_secs(1445412480 - 30);
+        assert_eq!(rate_limit_wait(&headers, now), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn rate_limit_reset_is_relative_to_now() {
+        let headers = HashMap::from([("ratelimit-reset".to_string(), "1000".to_string())]);
+        assert_eq!(rate_limit_wait(&headers, UNIX_EPOCH + Duration::from_secs(940)), Some(Duration::from_secs(60)));
+        assert_eq!(rate_limit_wait(&headers, UNIX_EPOCH + Duration::from_secs(2000)), Some(Duration::ZERO));
+    }
+}
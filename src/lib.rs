@@ -1,357 +1,3210 @@
-use std::{env, fs::File, io::{Read, Write}};
+mod catalog;
+mod config;
+mod credentials;
+mod error;
+mod history;
+mod http;
+mod lock;
+mod mastodon;
+mod overrides;
+mod page_cache;
+mod post_log;
+mod proxy;
+mod publisher;
+mod rate_limit;
+mod redact;
+mod report;
+mod retry;
+mod season;
+mod selection;
+mod session;
+mod shuffle;
+mod species_list;
+mod stats;
+mod template;
+pub mod timing;
+mod verify;
+#[cfg(test)]
+mod test_server;
 
-use rand::Rng;
+use std::{
+    cell::Cell,
+    collections::HashSet,
+    fs::{self, File},
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+pub use catalog::{Bird, BirdCatalog, BirdChoice};
+use catalog::TaxonomyInfo;
+pub use config::{is_locale, load_env_files, Config};
+pub use credentials::Credentials;
+pub use error::{AuthFailure, BirdError};
+pub use history::{History, HistoryEntry, Lookback};
+pub use mastodon::MastodonPublisher;
+pub use overrides::DateOverrides;
+pub use post_log::PostLogEntry;
+pub use proxy::{Proxies, PROXY_VARIABLES};
+pub use publisher::{Published, Publisher};
+pub use rate_limit::RateLimiter;
+pub use redact::{redact, register_secret};
+pub use report::{Report, Stage};
+pub use retry::RetryPolicy;
+pub use season::SeasonalWeights;
+pub use selection::{FamilyRepeat, SelectionStrategy};
+pub use shuffle::ShuffleQueue;
+pub use species_list::SpeciesList;
+pub use stats::{stats, FamilyCount, OrderCount, Stats};
+pub use template::Template;
+pub use verify::{check_config, verify, Check};
+
+use log::{debug, info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use regex::Regex;
 use scraper::{Html, Selector};
+use serde::Serialize;
 use serde_json::{json, Value};
-use time::{OffsetDateTime, format_description::well_known::Rfc3339};
-
-const LOCAL_BIRDS: &str = "birds.json";
-
-#[allow(dead_code)]
-#[derive(Debug, Clone, serde::Deserialize)]
-struct Bird {
-    #[serde(rename(deserialize = "sciName"))]
-    pub scientific_name: String,
-    #[serde(rename(deserialize = "comName"))]
-    pub common_name: String,
-    #[serde(rename(deserialize = "speciesCode"))]
-    pub species_code: String,
-    #[serde(rename(deserialize = "category"))]
-    pub category: String,
-    #[serde(rename(deserialize = "taxonOrder"))]
-    pub taxon_order: f32,
-    #[serde(rename(deserialize = "bandingCodes"))]
-    pub banding_codes: Option<Vec<String>>,
-    #[serde(rename(deserialize = "comNameCodes"))]
-    pub com_name_codes: Option<Vec<String>>,
-    #[serde(rename(deserialize = "sciNameCodes"))]
-    pub sci_name_codes: Option<Vec<String>>,
-    #[serde(rename(deserialize = "order"))]
-    pub order: Option<String>,
-    #[serde(rename(deserialize = "familyComName"))]
-    pub family_com_name: Option<String>,
-    #[serde(rename(deserialize = "familySciName"))]
-    pub family_sci_name: Option<String>,
-    #[serde(rename(deserialize = "reportAs"))]
-    pub report_as: Option<String>,
-    #[serde(rename(deserialize = "extinct"))]
-    pub extinct: Option<bool>,
-    #[serde(rename(deserialize = "extinctYear"))]
-    pub extinct_year: Option<i32>,
-    #[serde(rename(deserialize = "familyCode"))]
-    pub family_code: Option<String>,
-}
-
-struct BirdImage {
-    photo_type: String,
-    url_download: String,
-    url_source: String,
-    alt_text: String,
+use time::{Date, OffsetDateTime, format_description::well_known::Rfc3339};
+use http::{send, HttpError};
+use lock::RunLock;
+use selection::{family_key, Preferences};
+use session::SessionCache;
+use timing::traced;
+
+/// A bird's photo, or its range map, as found on its eBird species page
+#[derive(Debug, Clone)]
+pub struct BirdImage {
+    /// MIME type of the photo, e.g. `image/jpeg`
+    pub photo_type: String,
+    /// Where to download the photo from
+    pub url_download: String,
+    /// Page crediting the photo, on the Macaulay Library
+    pub url_source: String,
+    /// Description of the photo for screen readers
+    pub alt_text: String,
+    /// A recording of the bird's song or calls, if the page has one
+    pub audio_url: Option<String>,
+    /// Who took the photo, if the page credits them
+    pub photographer: Option<String>,
+    /// Macaulay Library catalog number of the photo, e.g. `123456789` for ML123456789, if its
+    /// URLs give it
+    pub asset_id: Option<u64>,
+    /// License the species page gives for the photo, e.g. a Creative Commons URL, if it gives one
+    pub license: Option<String>,
+    /// This is the species' range map rather than a photo, credited to eBird and described as it is
+    pub range_map: bool,
 }
 
-#[derive(Debug)]
+/// The post created on one platform. Either part is `None` if the platform's response left it out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostResult {
+    /// AT URI of a Bluesky post, e.g. `at://did:plc:.../app.bsky.feed.post/...`, or the URL of a Mastodon status
+    pub uri: Option<String>,
+    /// Content hash of a Bluesky post record, or the ID of a Mastodon status
+    pub cid: Option<String>,
+}
+
+/// What to do with the bird picked for a run that asks before posting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Approval {
+    /// Post it
+    Post,
+    /// Pick a different bird and ask again
+    Reroll,
+    /// Give up without posting anything
+    Decline,
+}
+
+#[derive(Debug, Clone)]
 struct Token {
     token: String,
+    refresh_jwt: String,
     did: String,
 }
 
-pub fn run() -> bool {
-    let b = match get_bird() {
-        Some(b) => b,
-        None => return false,
-    };
-
-    let image = match get_bird_photo(&b) {
-        Some(id) => id,
-        None => return false,
-    };
+/// Post a bird of the day to every platform configured in the environment
+pub fn run() -> Result<Report, BirdError> {
+    let config = Config::from_env()?;
+    run_with_config(&config, &mut config.publishers())
+}
 
-    let token = match authenticate() {
-        Some(t) => t,
-        None => return false,
-    };
+/// Post a bird of the day to each of `publishers`, reporting how each one went.
+/// On a dry run nothing is posted and every successful result is `None`.
+pub fn run_with_config(config: &Config, publishers: &mut [Box<dyn Publisher>]) -> Result<Report, BirdError> {
+    run_with_retry(config, &RetryPolicy { max_attempts: 1, ..RetryPolicy::default() }, publishers)
+}
 
-    return post(&b, &image, &token);
+/// Like `run_with_config()`, but retry each step as dictated by `policy`. The bird is picked
+/// once, then every platform gets attempts of its own, so one that keeps failing doesn't hold
+/// up the others or get the bird posted twice on them. Publishers keep what they got done
+/// between attempts, e.g. the Bluesky session and uploaded photo.
+/// Fails with `BirdError::AlreadyRunning` while another run holds `config.lock_path`.
+pub fn run_with_retry(config: &Config, policy: &RetryPolicy, publishers: &mut [Box<dyn Publisher>]) -> Result<Report, BirdError> {
+    run_with_approval(config, policy, publishers, |_, _| Approval::Post)
 }
 
-/// Download a copy of *all* birds and save a copy to the local machine
-/// This should only be run periodically
-pub fn get_all_birds() {
-    // Get all available birds from eBird.org
-    let r = match minreq::get("https://api.ebird.org/v2/ref/taxonomy/ebird?fmt=json")
-        .with_header("X-eBirdApiToken", env::var("EBIRD_API_KEY").unwrap())
-        .with_timeout(30)
-        .send() {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("Error reading response from eBird call: {}", e);
-                return;
-            }
-    };
+/// Like `run_with_retry()`, but ask `approve` about the bird and photos before posting them.
+/// A rerolled bird isn't picked again this run, and the other birds are picked from as usual.
+/// Declining fails with `BirdError::Declined`. Time spent in `approve` doesn't count towards
+/// `config.max_run_duration`.
+pub fn run_with_approval<F>(config: &Config, policy: &RetryPolicy, publishers: &mut [Box<dyn Publisher>], mut approve: F) -> Result<Report, BirdError>
+where
+    F: FnMut(&Bird, &[BirdImage]) -> Approval,
+{
+    let _lock = RunLock::acquire(&config.lock_path, Duration::from_secs(config.stale_lock_age))?;
+    let deadline = Cell::new(Deadline::after(config.max_run_duration));
+    // Don't sleep past the deadline; the next attempt notices it has run out of time
+    let sleep = |d: Duration| thread::sleep(d.min(deadline.get().remaining()));
+    let mut stages = Vec::new();
 
-    if r.status_code != 200 {
-        eprintln!("Bad response code from eBird: {}", r.status_code);
-        return;
+    if config.auto_update_taxonomy {
+        update_stale_taxonomy(config, |config| {
+            stage(&mut stages, "taxonomy", policy, sleep, || download_taxonomy(&deadline.get().limit(config)?))
+        })?;
     }
+    warn_about_locale(config);
 
-    let mut file = match File::create(LOCAL_BIRDS) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("Error creating 'birds.json': {}", e);
-            return;
+    let mut history = History::load(&config.history_path)?;
+    let today = config.today();
+    let mut rerolled = Vec::new();
+    let Pick { bird: b, images, queue } = loop {
+        let pick = stage(&mut stages, "pick", policy, sleep, || {
+            pick_bird_with_photo(&deadline.get().limit(config)?, &history, today, &rerolled)
+        })?;
+        let asked = Instant::now();
+        let approval = approve(&pick.bird, &pick.images);
+        deadline.set(deadline.get().extended(asked.elapsed()));
+        match approval {
+            Approval::Post => break pick,
+            Approval::Reroll => rerolled.push(pick.bird.species_code),
+            Approval::Decline => return Err(BirdError::Declined),
         }
     };
 
-    if let Err(e) = file.write_all(r.as_bytes()) {
-        eprintln!("Error writing data to 'birds.json': {}", e);
+    let mut published = Vec::new();
+    for publisher in publishers.iter_mut() {
+        let platform = publisher.name().to_string();
+        let result = stage(&mut stages, &platform, policy, sleep, || publisher.publish(&deadline.get().limit(config)?, &b, &images));
+        published.push(Published { platform, result });
     }
-}
 
-/// Get one random bird from eBird.org
-fn get_bird() -> Option<Bird> {
-    // Read in the local copy of all data from eBird.org
-    let mut file = match File::open(LOCAL_BIRDS) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("Error opening 'birds.json': {}", e);
-            return None;
+    if !config.dry_run && published.iter().any(|p| p.result.is_ok()) {
+        let now = OffsetDateTime::now_utc().format(&Rfc3339).unwrap();
+        // Only a Bluesky post can be deleted with `delete-last`, so only its URI is kept
+        let uri = published.iter()
+            .find_map(|p| p.result.as_ref().ok()?.as_ref()?.uri.as_deref().filter(|u| u.starts_with("at://")));
+        history.record(&b.species_code, &now, uri, &config.history_lookbacks());
+        // The post is already up, so don't fail the run over these
+        if let Err(e) = history.save(&config.history_path) {
+            warn!("Error saving '{}': {}", config.history_path.display(), e);
         }
-    };
+        if let Some(mut queue) = queue {
+            queue.mark_posted(&b.species_code);
+            if let Err(e) = queue.save(config.shuffle_queue_path()) {
+                warn!("Error saving '{}': {}", config.shuffle_queue_path().display(), e);
+            }
+        }
+        for p in &published {
+            let Ok(Some(post)) = &p.result else { continue };
+            let entry = PostLogEntry {
+                posted_at: now.clone(),
+                platform: p.platform.clone(),
+                species_code: b.species_code.clone(),
+                common_name: b.common_name.clone(),
+                scientific_name: b.scientific_name.clone(),
+                image_source: images[0].url_source.clone(),
+                asset_id: images[0].asset_id,
+                license: images[0].license.clone(),
+                uri: post.uri.clone(),
+                cid: post.cid.clone(),
+            };
+            if let Err(e) = post_log::append(&config.post_log_path, &entry, config.post_log_max_size) {
+                warn!("Error writing to '{}': {}", config.post_log_path.display(), e);
+            }
+        }
+    }
+    Ok(Report { bird: b, images, published, stages })
+}
 
-    let mut contents = String::new();
-    if let Err(e) = file.read_to_string(&mut contents) {
-        eprintln!("Error opening 'birds.json': {}", e);
-        return None;
+/// Run `op` with the retries `policy` allows, noting in `stages` how many attempts it took and how long
+fn stage<T>(stages: &mut Vec<Stage>, name: &str, policy: &RetryPolicy, sleep: impl FnMut(Duration), mut op: impl FnMut() -> Result<T, BirdError>) -> Result<T, BirdError> {
+    let start = Instant::now();
+    let mut attempts = 0;
+    let result = policy.retry(|n| {
+        attempts = n;
+        op()
+    }, sleep);
+    stages.push(Stage { name: name.to_string(), attempts, elapsed: start.elapsed() });
+    result
+}
+
+/// The point by which a run, including all of its retries, has to be done
+#[derive(Clone, Copy)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn after(secs: u64) -> Deadline {
+        Deadline(Instant::now() + Duration::from_secs(secs))
     }
 
-    let mut birds: Vec<Bird> = match serde_json::from_str(&contents) {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("Error converting eBird response into JSON: {}", e);
-            return None;
+    fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    /// The same deadline pushed back by `by`
+    fn extended(self, by: Duration) -> Deadline {
+        Deadline(self.0 + by)
+    }
+
+    /// `config` with its timeouts cut down to what is left of the run's time budget
+    fn limit(&self, config: &Config) -> Result<Config, BirdError> {
+        let left = self.remaining().as_secs();
+        if left == 0 {
+            return Err(BirdError::DeadlineExceeded);
         }
-    };
+        Ok(Config {
+            taxonomy_timeout: config.taxonomy_timeout.min(left),
+            page_timeout: config.page_timeout.min(left),
+            image_timeout: config.image_timeout.min(left),
+            bluesky_timeout: config.bluesky_timeout.min(left),
+            max_rate_limit_wait: config.max_rate_limit_wait.min(left),
+            ..config.clone()
+        })
+    }
 
-    // Filter out all birds that are species and are extinct
-    birds.retain(|b| !b.common_name.contains("sp.") && b.extinct.is_none());
-    
-    // Finally, get a random bird
-    let mut rng = rand::thread_rng();
-    Some(birds[rng.gen_range(0..birds.len())].clone())
+    /// Run `op` until `policy` gives up, as a post's stages are: given `config` cut down to the
+    /// time that is left, and never sleeping past the deadline, however long a rate limit asks for
+    pub fn retry<T, F>(&self, config: &Config, policy: &RetryPolicy, mut op: F) -> Result<T, BirdError>
+    where
+        F: FnMut(&Config) -> Result<T, BirdError>,
+    {
+        policy.retry(|_| op(&self.limit(config)?), |d| thread::sleep(d.min(self.remaining())))
+    }
 }
 
-/// Get a photo of the desired bird
-fn get_bird_photo(bird: &Bird) -> Option<BirdImage> {
-    let r = match minreq::get(format!("https://ebird.org/species/{}", bird.species_code))
-        .with_header("User-Agent", format!("BirdOfTheDayBot ({})", env::var("BOTD_EMAIL").unwrap()))
-        .with_timeout(30)
-        .send() {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("Error reading bird image response: {}", e);
-                return None;
+/// Photos already uploaded to Bluesky, waiting to be attached to a post
+#[derive(Clone)]
+struct UploadedBlobs {
+    species_code: String,
+    blobs: Vec<Value>,
+}
+
+/// Posts to Bluesky as `Config::handle`, keeping the session and the uploaded photos between
+/// attempts, and the session between posts until its access token is about to expire
+#[derive(Default)]
+pub struct BlueskyPublisher {
+    session: SessionCache,
+    blobs: Option<UploadedBlobs>,
+}
+
+impl BlueskyPublisher {
+    pub fn new() -> BlueskyPublisher {
+        BlueskyPublisher::default()
+    }
+}
+
+impl Publisher for BlueskyPublisher {
+    fn name(&self) -> &str {
+        "Bluesky"
+    }
+
+    fn publish(&mut self, config: &Config, bird: &Bird, images: &[BirdImage]) -> Result<Option<PostResult>, BirdError> {
+        let mut token = match self.session.get(SystemTime::now()) {
+            Some((token, true)) => token.clone(),
+            Some((token, false)) => {
+                info!("Bluesky access token is about to expire, refreshing the session");
+                let mut token = token.clone();
+                refresh(config, &mut token)?;
+                token
             }
-    };
+            None => get_session(config)?,
+        };
+        let result = post(config, bird, images, &mut token, &mut self.blobs);
+        // Keep the session even if posting failed, in case it was renewed along the way
+        self.session.set(token, SystemTime::now());
+        result
+    }
+}
 
-    if r.status_code != 200 {
-        eprintln!("Bad response code eBird while getting image: {}", r.status_code);
-        return None;
+/// Download a copy of *all* birds and save a copy to the local machine, returning how many there are.
+/// This should only be run periodically, and not while a run holds `config.lock_path`
+pub fn get_all_birds(config: &Config) -> Result<usize, BirdError> {
+    let _lock = RunLock::acquire(&config.lock_path, Duration::from_secs(config.stale_lock_age))?;
+    download_taxonomy(config)
+}
+
+/// Download the taxonomy with `download` if the local copy is missing or older than
+/// `config.taxonomy_max_age` days. When there is a stale copy to fall back on, a failed
+/// download is only logged.
+fn update_stale_taxonomy<F>(config: &Config, download: F) -> Result<(), BirdError>
+where
+    F: FnOnce(&Config) -> Result<usize, BirdError>,
+{
+    let path = &config.taxonomy_path();
+    let age = fs::metadata(path).and_then(|m| m.modified()).ok()
+        .map(|modified| SystemTime::now().duration_since(modified).unwrap_or(Duration::ZERO));
+    match age {
+        Some(age) if age <= Duration::from_secs(config.taxonomy_max_age * 24 * 60 * 60) => return Ok(()),
+        Some(age) => info!("'{}' is {} days old, downloading the taxonomy again", path.display(), age.as_secs() / (24 * 60 * 60)),
+        None => info!("'{}' is missing, downloading the taxonomy", path.display()),
+    }
+    match download(config) {
+        Ok(n) => info!("Downloaded {} species to '{}'", n, path.display()),
+        Err(e) if age.is_some() => warn!("Error updating '{}', carrying on with the old copy: {}", path.display(), e),
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+/// Download the taxonomy, with common names in `config.locale`, to `config.taxonomy_path()`
+/// and note the locale next to it, returning how many birds are in it
+fn download_taxonomy(config: &Config) -> Result<usize, BirdError> {
+    // Get all available birds from eBird.org
+    config.ebird_rate_limit.acquire();
+    let mut url = format!("{}/v2/ref/taxonomy/ebird?fmt=json", config.ebird_api_url);
+    if let Some(locale) = &config.locale {
+        url.push_str(&format!("&locale={}", locale));
     }
+    let r = send(config, || {
+        http::get(config, &url, config.taxonomy_timeout)
+            .with_header("X-eBirdApiToken", &config.ebird_api_key)
+    })?;
+    let r = http::check(r).map_err(HttpError::ebird)?;
 
-    let page = match r.as_str() {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("Error converting eBird page into string: {}", e);
-            return None;
-        }
+    // Make sure the download is complete before replacing a working copy with it
+    let birds: Vec<Bird> = serde_json::from_slice(r.as_bytes())
+        .map_err(|e| BirdError::Parse(format!("Downloaded taxonomy is not valid JSON (truncated?): {}", e)))?;
+    if birds.is_empty() {
+        return Err(BirdError::Parse("Downloaded taxonomy has no birds in it".to_string()));
+    }
+
+    // Write to a temporary file and move it into place, so a crash part way through
+    // the write can't leave a corrupt copy behind
+    let path = config.taxonomy_path();
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let written = File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(r.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&tmp_path, &path)) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+    let info = TaxonomyInfo { locale: config.locale.clone(), downloaded_at: OffsetDateTime::now_utc().format(&Rfc3339).unwrap() };
+    // Without it the taxonomy is taken to be in English, which only matters for the warning
+    if let Err(e) = info.save(&path) {
+        warn!("Error saving the locale of '{}': {}", path.display(), e);
+    }
+    let catalog = BirdCatalog::new(birds);
+    prune_history(config, &catalog);
+    Ok(catalog.len())
+}
+
+/// Drop the birds that are no longer in `catalog` from the history. The taxonomy is already in
+/// place, so this only logs what goes wrong.
+fn prune_history(config: &Config, catalog: &BirdCatalog) {
+    let path = &config.history_path;
+    let mut history = match History::load(path) {
+        Ok(h) => h,
+        Err(e) => return warn!("Unable to check '{}' against the new taxonomy: {}", path.display(), e),
     };
-    // Now extract all the image properties
-    let doc = Html::parse_document(page);   
-    let s_url_download = Selector::parse(r#"meta[property="og:image"]"#).unwrap();
-    let url_download: &str = match doc.select(&s_url_download).next() {
-        Some(s) => s.value().attr("content").unwrap(),
-        None => {
-            eprintln!("No 'og:image' tag found in html: {}", doc.html());
-            return None;
-        }
+    let dropped = history.prune(catalog);
+    if dropped.is_empty() {
+        return;
+    }
+    info!("Dropping {} from '{}', as they are no longer in the taxonomy", dropped.join(", "), path.display());
+    if let Err(e) = history.save(path) {
+        warn!("Error saving '{}': {}", path.display(), e);
+    }
+}
+
+/// Warn if the common names in the taxonomy aren't in `config.locale`, e.g. because
+/// `BOTD_BIRDS_PATH` points at a copy downloaded for another locale
+fn warn_about_locale(config: &Config) {
+    let path = config.taxonomy_path();
+    let stored = match TaxonomyInfo::load(&path) {
+        Ok(info) => info.and_then(|i| i.locale),
+        Err(e) => return warn!("Unable to tell which locale '{}' is in: {}", path.display(), e),
     };
-    let s_alt_text = Selector::parse(r#"meta[property="og:image:alt"]"#).unwrap();
-    let alt_text: &str = match doc.select(&s_alt_text).next() {
-        Some(s) => s.value().attr("content").unwrap(),
+    if stored != config.locale {
+        let name = |l: &Option<String>| l.clone().unwrap_or_else(|| "the default locale".to_string());
+        warn!("'{}' has common names in {}, not {}; run update-taxonomy to download it again",
+            path.display(), name(&stored), name(&config.locale));
+    }
+}
+
+/// Get today's bird, as [`Config::today`] has it, without posting anything
+pub fn get_bird(config: &Config, history: &History) -> Result<Bird, BirdError> {
+    get_bird_for_date(config, history, config.today())
+}
+
+/// Get one random bird from eBird.org that wasn't posted within `config.history_window`, or the
+/// one `config.date_overrides` has for the date. The same date, taxonomy, and history always give
+/// the same bird, as does the same `config.seed` whatever the date.
+pub fn get_bird_for_date(config: &Config, history: &History, date: Date) -> Result<Bird, BirdError> {
+    // Read in the local copy of all data from eBird.org
+    let catalog = BirdCatalog::load(config.taxonomy_path())?;
+    if let Some(b) = date_override(config, &catalog, date)? {
+        return Ok(b.clone());
+    }
+    let birds = candidates(config, &catalog)?;
+
+    // Finally, get a random bird
+    let mut queue = shuffle_queue(config, &catalog, date)?;
+    let b = pick_next(config, &catalog, history, date, queue.as_mut(), &birds, &mut pick_rng(config, date))?.clone();
+    debug!("Selected {} ({}) out of {} birds", b.common_name, b.species_code, birds.len());
+    Ok(b)
+}
+
+/// Get today's bird and its photos, without posting anything
+pub fn get_bird_with_photo(config: &Config, history: &History) -> Result<(Bird, Vec<BirdImage>), BirdError> {
+    get_bird_with_photo_for_date(config, history, config.today())
+}
+
+/// Like [`get_bird_for_date`], but a bird whose species page has no usable photo is skipped in
+/// favour of another one, up to `config.photo_attempts` birds in all. An override for the date
+/// without a usable photo is skipped for a bird picked as usual.
+pub fn get_bird_with_photo_for_date(config: &Config, history: &History, date: Date) -> Result<(Bird, Vec<BirdImage>), BirdError> {
+    pick_bird_with_photo(config, history, date, &[]).map(|pick| (pick.bird, pick.images))
+}
+
+/// A bird picked to post, with its photos and the shuffle queue it came from, if any
+struct Pick {
+    bird: Bird,
+    images: Vec<BirdImage>,
+    queue: Option<ShuffleQueue>,
+}
+
+/// The saved shuffle queue brought up to date with `catalog`, if `config.selection` walks one
+pub(crate) fn shuffle_queue(config: &Config, catalog: &BirdCatalog, date: Date) -> Result<Option<ShuffleQueue>, BirdError> {
+    if config.selection != SelectionStrategy::Shuffle {
+        return Ok(None);
+    }
+    let mut queue = ShuffleQueue::load(config.shuffle_queue_path())?;
+    queue.sync(catalog, &mut pick_rng(config, date));
+    Ok(Some(queue))
+}
+
+/// The next of `birds` in `queue`, or without one a random bird that wasn't posted recently, as
+/// [`choose_bird`] picks it
+fn pick_next<'a, R: Rng>(
+    config: &Config,
+    catalog: &BirdCatalog,
+    history: &History,
+    date: Date,
+    queue: Option<&mut ShuffleQueue>,
+    birds: &[&'a Bird],
+    rng: &mut R,
+) -> Result<&'a Bird, BirdError> {
+    match queue {
+        Some(queue) => queue.next(birds, rng)
+            .ok_or_else(|| BirdError::NoCandidates("there are no birds to choose from".to_string())),
         None => {
-            eprintln!("No 'og:image:alt' tag found in html: {}", doc.html());
-            return None;
+            let preferences = preferences(config, catalog, history, date);
+            choose_bird(birds, history, config.history_window, start_of_day(config, date), config.selection, &preferences, rng)
         }
-    };
-    let s_url_source = Selector::parse(r#"meta[property="og:url"]"#).unwrap();
-    let url_source: &str = match doc.select(&s_url_source).next() {
-        Some(s) => s.value().attr("content").unwrap(),
-        None => {
-            eprintln!("No 'og:url' tag found in html: {}", doc.html());
-            return None;
+    }
+}
+
+/// What the pick for `date` leans towards or away from: the families of the birds in the last
+/// `config.family_window` posts, looked up in `catalog`, and `config.seasonal_weights` for the month
+fn preferences<'c>(config: &'c Config, catalog: &'c BirdCatalog, history: &History, date: Date) -> Preferences<'c> {
+    let recent_families = history.recent(Lookback::Posts(config.family_window), start_of_day(config, date)).into_iter()
+        .filter_map(|code| catalog.by_species_code(code))
+        .filter_map(family_key)
+        .collect();
+    Preferences { recent_families, repeat: config.family_repeat, season: Some((&config.seasonal_weights, date.month())) }
+}
+
+/// Like [`get_bird_with_photo_for_date`], but never one of the birds in `excluded`, and with the
+/// shuffle queue the bird was picked from, if any, to note the bird in once it has been posted
+fn pick_bird_with_photo(config: &Config, history: &History, date: Date, excluded: &[String]) -> Result<Pick, BirdError> {
+    let catalog = BirdCatalog::load(config.taxonomy_path())?;
+    let mut queue = shuffle_queue(config, &catalog, date)?;
+    // As in get_bird_for_date, the override comes before the filters, which it doesn't go through
+    let mut dropped = None;
+    if let Some(b) = date_override(config, &catalog, date)?.filter(|b| !excluded.contains(&b.species_code)) {
+        match species_photos(config, &catalog, b) {
+            Ok(images) => return Ok(Pick { bird: b.clone(), images, queue }),
+            Err(BirdError::Scrape(m)) => {
+                warn!("Skipping the override for {}, picking a bird as usual instead: {}", date, m);
+                dropped = Some(b);
+            }
+            Err(e) => return Err(e),
         }
+    }
+
+    let mut birds = match (candidates(config, &catalog), dropped) {
+        (Err(BirdError::NoCandidates(_)), Some(_)) => Vec::new(),
+        (birds, _) => birds?,
     };
-    let s_url_source = Selector::parse(r#"link[rel="image_src"]"#).unwrap();
-    let photo_type: &str = match doc.select(&s_url_source).next() {
-        Some(s) => s.value().attr("type").unwrap(),
-        None => {
-            eprintln!("No 'image_src' tag found in html: {}", doc.html());
-            return None;
+    if let Some(b) = dropped {
+        birds.retain(|c| c.species_code != b.species_code);
+        if birds.is_empty() {
+            return Err(BirdError::NoCandidates(format!(
+                "no usable photo of {} ({}), the override for {}, and no other bird to pick instead",
+                b.common_name, b.species_code, date)));
+        }
+    }
+    if !excluded.is_empty() {
+        birds.retain(|b| !excluded.contains(&b.species_code));
+        if birds.is_empty() {
+            return Err(BirdError::NoCandidates("every bird that could be picked was turned down".to_string()));
+        }
+    }
+    let mut rng = pick_rng(config, date);
+
+    let mut skipped = Vec::new();
+    while skipped.len() < config.photo_attempts as usize && !birds.is_empty() {
+        let b = pick_next(config, &catalog, history, date, queue.as_mut(), &birds, &mut rng)?;
+        debug!("Selected {} ({}) out of {} birds", b.common_name, b.species_code, birds.len());
+        match species_photos(config, &catalog, b) {
+            Ok(images) => return Ok(Pick { bird: b.clone(), images, queue }),
+            Err(BirdError::Scrape(m)) => {
+                warn!("Skipping {} ({}): {}", b.common_name, b.species_code, m);
+                let code = b.species_code.clone();
+                birds.retain(|c| c.species_code != code);
+                skipped.push(code);
+            }
+            Err(e) => return Err(e),
         }
+    }
+    // The same date picks the same birds again, so there is no point retrying this
+    Err(BirdError::NoCandidates(format!("no usable photo for any of the birds tried ({})", skipped.join(", "))))
+}
+
+/// The photos of `bird`, from the species page of the species it is reported as: subspecies and
+/// forms, if the categories let them be picked, rarely have photos of their own on eBird
+fn species_photos(config: &Config, catalog: &BirdCatalog, bird: &Bird) -> Result<Vec<BirdImage>, BirdError> {
+    let species = catalog.reported_as(bird);
+    if species.species_code != bird.species_code {
+        debug!("Looking for photos of {} on the species page for {} ({})", bird.common_name, species.common_name, species.species_code);
+    }
+    get_bird_photos(config, species)
+}
+
+/// The bird `config.date_overrides` has for `date`, unless `config.only_bird` names one. An
+/// override for a bird that isn't in `catalog`, or is in the blocklist, is logged and skipped.
+fn date_override<'a>(config: &Config, catalog: &'a BirdCatalog, date: Date) -> Result<Option<&'a Bird>, BirdError> {
+    let Some(code) = config.date_overrides.for_date(date).filter(|_| config.only_bird.is_none()) else {
+        return Ok(None);
+    };
+    let Some(b) = catalog.by_species_code(code) else {
+        warn!("Skipping the override for {}: no bird in the taxonomy has species code '{}'{}", date, code, catalog.suggest(code));
+        return Ok(None);
     };
+    if SpeciesList::load(config.blocklist_path(), catalog)?.is_some_and(|l| l.contains(b)) {
+        warn!("Skipping the override for {}: {} ({}) is in '{}'", date, b.common_name, b.species_code, config.blocklist_path().display());
+        return Ok(None);
+    }
+    info!("Posting {} ({}), the override for {}", b.common_name, b.species_code, date);
+    Ok(Some(b))
+}
 
-    return Some(BirdImage {
-        photo_type: photo_type.to_string(),
-        url_download: url_download.to_string(),
-        url_source: url_source.to_string(),
-        alt_text: alt_text.to_string()
-    });
+/// Every bird in `catalog` that may be posted, or just the one in `config.only_bird`
+fn candidates<'a>(config: &Config, catalog: &'a BirdCatalog) -> Result<Vec<&'a Bird>, BirdError> {
+    if let Some(choice) = &config.only_bird {
+        return catalog.choose(choice).map(|b| vec![b]);
+    }
+
+    let pool = filter_birds(config, catalog)?;
+    if pool.birds.is_empty() {
+        let message = match (&config.region, &config.only_order, &config.only_family) {
+            (Some(region), _, _) if pool.excluded.region > 0 =>
+                format!("none of the birds in the taxonomy are on the species list for '{}'", region),
+            (_, Some(order), _) if pool.excluded.order > 0 =>
+                format!("none of the birds that could be picked are in the order '{}'", order),
+            (_, _, Some(family)) if pool.excluded.family > 0 =>
+                format!("none of the birds that could be picked are in the family '{}'", family),
+            _ if pool.excluded.allowlist > 0 =>
+                format!("none of the birds that could be picked are in '{}'", config.allowlist_path().display()),
+            _ => format!("0 birds remained after filtering; taxonomy file has {} entries", catalog.len()),
+        };
+        return Err(BirdError::NoCandidates(message));
+    }
+    Ok(pool.birds)
 }
 
-/// Authenticate username/password and get the `accessJwt` and `did` values
-fn authenticate() -> Option<Token> {
-    let json = json!({
-        "identifier": format!("{}", env::var("BOTD_HANDLE").unwrap()),
-        "password": format!("{}", env::var("BOTD_PASS").unwrap()),
-    });
-    let r = match minreq::post("https://bsky.social/xrpc/com.atproto.server.createSession")
-        .with_header("Content-Type", "application/json")
-        .with_body(json.to_string())
-        .with_timeout(30)
-        .send() {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("Error during session authentication: {}", e);
-                return None;
-            }
+/// The birds left in a taxonomy once [`filter_birds`] is done with it
+#[derive(Debug)]
+pub struct Pool<'a> {
+    pub birds: Vec<&'a Bird>,
+    pub excluded: Exclusions,
+}
+
+/// How many birds each of the filters took out, each counting only birds the ones before it left in
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Exclusions {
+    /// Spuhs, hybrids, domestic forms and the like: entries not in `config.categories`
+    pub category: usize,
+    /// Entries in `config.excluded_categories`
+    pub denied_category: usize,
+    pub extinct: usize,
+    /// Birds in `blocklist.txt`
+    pub blocklist: usize,
+    /// Birds missing from `allowlist.txt`, when there is one
+    pub allowlist: usize,
+    /// Birds outside `config.only_family`
+    pub family: usize,
+    /// Birds outside `config.only_order`
+    pub order: usize,
+    /// Birds missing from the species list of `config.region`
+    pub region: usize,
+}
+
+/// Apply the filters a random pick is made after: the allowed then the excluded categories,
+/// extinct birds unless `config.include_extinct`, the blocklist and allowlist in the data
+/// directory, read afresh every time, then the families, orders, and region if configured. Stops
+/// early, without fetching the region's species list, if nothing is left. Fails with
+/// `BirdError::Config` if a family or order isn't in the taxonomy.
+pub fn filter_birds<'a>(config: &Config, catalog: &'a BirdCatalog) -> Result<Pool<'a>, BirdError> {
+    let mut birds: Vec<&Bird> = catalog.birds().iter().collect();
+    let mut excluded = Exclusions {
+        category: retain(&mut birds, |b| config.categories.contains(&b.category)),
+        denied_category: retain(&mut birds, |b| !config.excluded_categories.contains(&b.category)),
+        // eBird marks extinct birds `"extinct": true`, and may leave the field out or set it false otherwise
+        extinct: if config.include_extinct { 0 } else { retain(&mut birds, |b| b.extinct != Some(true)) },
+        ..Exclusions::default()
+    };
+    if let Some(blocklist) = SpeciesList::load(config.blocklist_path(), catalog)? {
+        excluded.blocklist = retain(&mut birds, |b| !blocklist.contains(b));
+    }
+    if let Some(allowlist) = SpeciesList::load(config.allowlist_path(), catalog)? {
+        excluded.allowlist = retain(&mut birds, |b| allowlist.contains(b));
+    }
+
+    if let Some(list) = &config.only_family {
+        let families = catalog.parse_families(list)?;
+        let named = |name: &Option<String>| name.as_ref().is_some_and(|n| families.contains(&n.to_lowercase()));
+        excluded.family = retain(&mut birds, |b| named(&b.family_code) || named(&b.family_com_name) || named(&b.family_sci_name));
+    }
+    if let Some(list) = &config.only_order {
+        let orders = catalog.parse_orders(list)?;
+        excluded.order = retain(&mut birds, |b| b.order.as_ref().is_some_and(|o| orders.contains(&o.to_lowercase())));
+    }
+
+    if let Some(region) = config.region.as_ref().filter(|_| !birds.is_empty()) {
+        let codes = get_region_species(config, region)?;
+        excluded.region = retain(&mut birds, |b| codes.contains(&b.species_code));
+    }
+    Ok(Pool { birds, excluded })
+}
+
+/// Keep only the birds `keep` accepts, returning how many were taken out
+fn retain(birds: &mut Vec<&Bird>, keep: impl Fn(&Bird) -> bool) -> usize {
+    let before = birds.len();
+    birds.retain(|b| keep(b));
+    before - birds.len()
+}
+
+/// Species codes of every bird recorded in an eBird region, from the local copy unless it is
+/// missing, empty, or older than `config.region_max_age` days. When the copy is only stale, a
+/// failed download is logged and the copy used anyway.
+fn get_region_species(config: &Config, region: &str) -> Result<HashSet<String>, BirdError> {
+    let path = config.region_path(region);
+    let Some((codes, age)) = read_region_species(&path) else {
+        return download_region_species(config, region);
     };
+    if age <= Duration::from_secs(config.region_max_age * 24 * 60 * 60) {
+        return Ok(codes);
+    }
+    info!("'{}' is {} days old, downloading the species list again", path.display(), age.as_secs() / (24 * 60 * 60));
+    download_region_species(config, region).or_else(|e| {
+        warn!("Error updating '{}', carrying on with the old copy: {}", path.display(), e);
+        Ok(codes)
+    })
+}
 
-    if r.status_code != 200 {
-        eprintln!("Error during authentication: {}", r.as_str().unwrap());
+/// The species list saved at `path` and how old it is, unless it is missing, unreadable, or empty
+fn read_region_species(path: &Path) -> Option<(HashSet<String>, Duration)> {
+    let contents = fs::read_to_string(path).ok()?;
+    let codes: HashSet<String> = serde_json::from_str(&contents)
+        .inspect_err(|e| warn!("Ignoring unreadable '{}': {}", path.display(), e)).ok()?;
+    if codes.is_empty() {
+        warn!("Ignoring '{}', which has no species in it", path.display());
         return None;
     }
-    
-    let json = match r.json::<Value>() {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("Successfully recieved token, but error occurred during conversion to JSON: {}", e);
-            return None;
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some((codes, SystemTime::now().duration_since(modified).unwrap_or(Duration::ZERO)))
+}
+
+/// Download the species list for `region` and save it to `config.region_path(region)`. An empty
+/// list isn't saved, as no bird could be picked from it.
+fn download_region_species(config: &Config, region: &str) -> Result<HashSet<String>, BirdError> {
+    config.ebird_rate_limit.acquire();
+    let url = format!("{}/v2/product/spplist/{}", config.ebird_api_url, region);
+    let r = send(config, || {
+        http::get(config, &url, config.taxonomy_timeout)
+            .with_header("X-eBirdApiToken", &config.ebird_api_key)
+    })?;
+    let r = http::check(r).map_err(|e| match e.status {
+        400 | 404 => BirdError::InvalidRegion(region.to_string()),
+        _ => e.ebird(),
+    })?;
+
+    let codes: HashSet<String> = r.json()
+        .map_err(|e| BirdError::Parse(format!("Error converting species list for '{}' into JSON: {}", region, e)))?;
+    if codes.is_empty() {
+        return Err(BirdError::NoCandidates(format!("eBird has no species recorded in '{}'", region)));
+    }
+    let path = config.region_path(region);
+    if let Err(e) = fs::write(&path, r.as_bytes()) {
+        warn!("Error saving '{}': {}", path.display(), e);
+    }
+    Ok(codes)
+}
+
+/// RNG seeded from the calendar date
+fn date_rng(date: Date) -> StdRng {
+    StdRng::seed_from_u64(date.to_julian_day() as u64)
+}
+
+/// RNG to pick the bird for `date` with, unless `config.seed` overrides it. It only ever draws
+/// from the birds left after filtering, so the pick depends on nothing else.
+fn pick_rng(config: &Config, date: Date) -> StdRng {
+    config.seed.map_or_else(|| date_rng(date), StdRng::seed_from_u64)
+}
+
+/// Pick a random bird that wasn't posted within `lookback` of the day starting at `today`, as
+/// `strategy` has it. If every bird was posted that recently, e.g. when nothing is ever to be
+/// posted twice and every one has been, pick the one that was posted the longest time ago. The
+/// pick leans as `preferences` says as far as it can. Fails with `BirdError::NoCandidates` rather
+/// than panicking if `birds` is empty.
+fn choose_bird<'a, R: Rng>(
+    birds: &[&'a Bird],
+    history: &History,
+    lookback: Lookback,
+    today: OffsetDateTime,
+    strategy: SelectionStrategy,
+    preferences: &Preferences,
+    rng: &mut R,
+) -> Result<&'a Bird, BirdError> {
+    if birds.is_empty() {
+        return Err(BirdError::NoCandidates("there are no birds to choose from".to_string()));
+    }
+    let recent = history.recent(lookback, today);
+    let fresh: Vec<&Bird> = birds.iter().copied().filter(|b| !recent.contains(b.species_code.as_str())).collect();
+    if fresh.is_empty() {
+        info!("Every one of the {} birds was posted within the history window ({}), so the one posted longest ago comes up again",
+            birds.len(), lookback);
+        return Ok(birds.iter().copied().min_by_key(|b| history.last_posted(&b.species_code)).unwrap());
+    }
+    Ok(preferences.pick(strategy, &fresh, rng).unwrap_or_else(|| {
+        warn!("Every one of the {} birds that could be picked is of a family posted within BOTD_FAMILY_WINDOW, so one comes up again anyway",
+            fresh.len());
+        preferences.pick_in_season(strategy, &fresh, rng)
+    }))
+}
+
+/// The start of `date` at `config.utc_offset`, which the history window counts days back from
+fn start_of_day(config: &Config, date: Date) -> OffsetDateTime {
+    date.midnight().assume_offset(config.utc_offset)
+}
+
+/// Get up to `config.max_photos` photos of the desired bird: the one the species page is shared
+/// with, which also carries the recording, then any others the page links to on the Macaulay Library.
+/// With `config.cache_pages`, a page saved less than `config.page_cache_ttl` seconds ago is used
+/// instead of fetching it again, unless the photos can't be found on it.
+pub fn get_bird_photos(config: &Config, bird: &Bird) -> Result<Vec<BirdImage>, BirdError> {
+    let cache_path = config.page_cache_path(&bird.species_code);
+    if config.cache_pages {
+        if let Some(page) = page_cache::load(&cache_path, Duration::from_secs(config.page_cache_ttl)) {
+            match images_from_page(config, bird, &page) {
+                Ok(images) => {
+                    debug!("Using the species page for {} saved in '{}'", bird.species_code, cache_path.display());
+                    return Ok(images);
+                }
+                Err(e) => {
+                    debug!("Saved species page for {} is no use, fetching it again: {}", bird.species_code, e);
+                    let _ = fs::remove_file(&cache_path);
+                }
+            }
         }
-    };
-    
-    let token = match json.get("accessJwt") {
-        Some(t) => t.as_str().unwrap(),
-        None => {
-            eprintln!("Successfully converted response to JSON, but 'accessJwt' parameter was not present");
-            return None;
+    }
+
+    let url = format!("{}/species/{}", config.ebird_url, bird.species_code);
+    config.ebird_rate_limit.acquire();
+    let r = traced("species page", &url, || send(config, || http::get(config, &url, config.page_timeout)))?;
+    let r = http::check(r).map_err(HttpError::ebird)?;
+
+    let page = r.as_str()
+        .map_err(|e| BirdError::Parse(format!("Error converting eBird page into string: {}", e)))?;
+    let images = images_from_page(config, bird, page).inspect_err(|_| {
+        // The whole page is far too noisy for anything but debugging a change to eBird's layout
+        debug!("eBird species page for {}:\n{}", bird.species_code, page);
+    })?;
+    // Only a page the photos could be found on is worth keeping
+    if config.cache_pages {
+        if let Err(e) = page_cache::save(&cache_path, page) {
+            warn!("Error saving the species page to '{}': {}", cache_path.display(), e);
         }
+    }
+    Ok(images)
+}
+
+/// The photos on the species page of `bird`, and its range map after them if
+/// `config.include_range_map` and the page has one, taking the place of the last photo if there
+/// would otherwise be more images than Bluesky allows
+fn images_from_page(config: &Config, bird: &Bird, page: &str) -> Result<Vec<BirdImage>, BirdError> {
+    let mut images = parse_bird_images(page, config.max_photos)?;
+    if !config.include_range_map {
+        return Ok(images);
+    }
+    let Some(url_download) = parse_range_map(page) else {
+        debug!("No range map on the species page for {}, posting only the photos", bird.species_code);
+        return Ok(images);
     };
+    // The first photo carries the recording, so it always stays
+    if images.len() >= MAX_PHOTOS {
+        images.truncate(MAX_PHOTOS - 1);
+    }
+    images.push(BirdImage {
+        photo_type: guess_photo_type(&url_download).to_string(),
+        url_source: format!("{}/map/{}", config.ebird_url, bird.species_code),
+        alt_text: format!("Range map of {}", bird.common_name),
+        url_download,
+        audio_url: None,
+        photographer: None,
+        asset_id: None,
+        license: None,
+        range_map: true,
+    });
+    Ok(images)
+}
 
-    let did = match json.get("did") {
-        Some(t) => t.as_str().unwrap(),
-        None => {
-            eprintln!("Successfully converted response to JSON, but 'did' parameter was not present");
-            return None;
+/// The photos on an eBird species page, up to `max_photos` of them, as for [`get_bird_photos`].
+/// Fails with `BirdError::Scrape` if the page has no main photo, and `BirdError::LayoutChanged`
+/// if it has none of the tags the photo is looked for in.
+pub fn parse_bird_images(page: &str, max_photos: usize) -> Result<Vec<BirdImage>, BirdError> {
+    let doc = Html::parse_document(page);
+    // A page without a single one of these isn't a species without a photo, but a new layout
+    let tags = Selector::parse(r#"meta[property^="og:"], meta[name^="twitter:"], link[rel="image_src"]"#).unwrap();
+    if doc.select(&tags).next().is_none() {
+        let head = head_snippet(&doc);
+        warn!("eBird species page has none of the expected tags, its layout may have changed: {}", head);
+        return Err(BirdError::LayoutChanged(head));
+    }
+
+    // Now extract all the image properties, falling back on the Twitter card and the image_src link
+    let url_download = select_attr(&doc, &[
+        (r#"meta[property="og:image"]"#, "content"),
+        (r#"meta[name="twitter:image"]"#, "content"),
+        (r#"link[rel="image_src"]"#, "href"),
+    ], "og:image")?;
+    let alt_text = select_attr(&doc, &[
+        (r#"meta[property="og:image:alt"]"#, "content"),
+        (r#"meta[name="twitter:image:alt"]"#, "content"),
+    ], "og:image:alt")?;
+    let url_source = select_attr(&doc, &[(r#"meta[property="og:url"]"#, "content")], "og:url")?;
+    let photo_type = select_attr(&doc, &[(r#"link[rel="image_src"]"#, "type")], "image_src")
+        .unwrap_or_else(|_| guess_photo_type(&url_download).to_string());
+    // Plenty of species have no recordings, so this one is optional
+    let audio_url = select_attr(&doc, &[("audio[src], audio source[src]", "src")], "audio").ok();
+    let photographer = scrape_photographer(&doc);
+    let asset_id = parse_asset_id(&url_source).or_else(|| parse_asset_id(&url_download));
+    let license = select_attr(&doc, &[
+        // A license linked anywhere else on the page is most likely the site's own, in the footer
+        (r#"link[rel="license"], [class*="credit" i] a[rel="license"], figcaption a[rel="license"]"#, "href"),
+        (r#"meta[name="license"], meta[name="dcterms.license"], meta[property="og:license"]"#, "content"),
+    ], "license").ok();
+
+    debug!("Scraped photo {} ({}) by {:?} from {}", url_download, photo_type, photographer, url_source);
+    let mut images = vec![BirdImage {
+        photo_type,
+        asset_id,
+        license,
+        url_download,
+        url_source,
+        alt_text,
+        audio_url,
+        photographer,
+        range_map: false,
+    }];
+
+    let gallery = Selector::parse(r#"a[href*="macaulaylibrary.org/asset/"]"#).unwrap();
+    let img = Selector::parse("img[src]").unwrap();
+    for link in doc.select(&gallery) {
+        if images.len() >= max_photos {
+            break;
+        }
+        let (Some(url_source), Some(photo)) = (link.value().attr("href"), link.select(&img).next()) else {
+            continue;
+        };
+        let url_download = photo.value().attr("src").unwrap_or_default();
+        if images.iter().any(|i| i.url_source == url_source || i.url_download == url_download) {
+            continue;
+        }
+        debug!("Scraped photo {} from {}", url_download, url_source);
+        images.push(BirdImage {
+            photo_type: guess_photo_type(url_download).to_string(),
+            url_download: url_download.to_string(),
+            url_source: url_source.to_string(),
+            alt_text: photo.value().attr("alt").unwrap_or_default().to_string(),
+            audio_url: None,
+            photographer: None,
+            asset_id: parse_asset_id(url_source).or_else(|| parse_asset_id(url_download)),
+            license: None,
+            range_map: false,
+        });
+    }
+    Ok(images)
+}
+
+/// The species' range map on an eBird species page, if it has one: the first image described as
+/// a range map, or whose file is named like one
+pub fn parse_range_map(page: &str) -> Option<String> {
+    let doc = Html::parse_document(page);
+    let maps = Selector::parse(r#"img[alt*="range map" i][src], img[src*="rangemap" i], img[src*="range-map" i]"#).unwrap();
+    let src = doc.select(&maps).next()?.value().attr("src")?;
+    (!src.is_empty()).then(|| src.to_string())
+}
+
+/// Name of the photographer the species page credits for its main photo, from a caption like
+/// `© Jane Doe / Macaulay Library ML123456789`
+fn scrape_photographer(doc: &Html) -> Option<String> {
+    let credits = Selector::parse(r#"[class*="credit" i], figcaption"#).unwrap();
+    doc.select(&credits).find_map(|e| parse_credit(&e.text().collect::<Vec<_>>().join(" ")))
+}
+
+/// The name after the `©` in a photo credit, up to the separator or the archive's name
+fn parse_credit(text: &str) -> Option<String> {
+    static CREDIT: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"©\s*([^/;|©]+?)\s*(?:[/;|]|Macaulay Library|ML\d|$)").unwrap()
+    });
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let name = CREDIT.captures(&text)?.get(1)?.as_str().trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// The Macaulay Library catalog number in a photo's URL, whether a page like
+/// `https://macaulaylibrary.org/asset/123` or the older `https://search.macaulaylibrary.org/catalog?assetId=123`,
+/// or a download like `https://cdn.download.ams.birds.cornell.edu/api/v1/asset/123/1200`. URLs on
+/// any other host have none, whatever their path looks like.
+fn parse_asset_id(url: &str) -> Option<u64> {
+    static ASSET_ID: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)^https?://(?:[a-z0-9-]+\.)*(?:macaulaylibrary\.org|birds\.cornell\.edu)(?::\d+)?(?:/.*?)?(?:/asset/(?:ML)?|/photo/|[?&]asset_?id=)(\d+)").unwrap()
+    });
+    ASSET_ID.captures(url)?.get(1)?.as_str().parse().ok()
+}
+
+/// MIME type of a photo from the extension of its URL. The Macaulay Library CDN serves JPEGs
+/// without one.
+fn guess_photo_type(url: &str) -> &'static str {
+    let path = url.split(['?', '#']).next().unwrap_or_default().to_lowercase();
+    if path.ends_with(".png") {
+        "image/png"
+    } else if path.ends_with(".webp") {
+        "image/webp"
+    } else {
+        "image/jpeg"
+    }
+}
+
+/// MIME type of a photo from its first bytes, if it is a JPEG, PNG, WebP, or GIF
+fn sniff_photo_type(photo: &[u8]) -> Option<&'static str> {
+    match photo {
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', ..] => Some("image/png"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("image/gif"),
+        _ => None,
+    }
+}
+
+/// MIME type to upload the downloaded `photo` of `image` as: what its bytes say it is, as
+/// Bluesky rejects a blob that isn't what its `Content-Type` says, or what the species page said
+/// when the bytes don't tell
+pub(crate) fn photo_type<'a>(image: &'a BirdImage, photo: &[u8]) -> &'a str {
+    match sniff_photo_type(photo) {
+        Some(sniffed) if sniffed != image.photo_type => {
+            warn!("Photo {} is {}, not {} as the species page says", image.url_download, sniffed, image.photo_type);
+            sniffed
         }
+        _ => &image.photo_type,
+    }
+}
+
+/// Get the value of `attr` on the first element matching `selector`, trying each pair in turn.
+/// `tag` names the one that is expected in the error if none match.
+fn select_attr(doc: &Html, candidates: &[(&str, &str)], tag: &str) -> Result<String, BirdError> {
+    candidates.iter()
+        .find_map(|(selector, attr)| {
+            let s = Selector::parse(selector).unwrap();
+            doc.select(&s).find_map(|e| e.value().attr(attr)).map(|v| v.to_string())
+        })
+        .ok_or_else(|| BirdError::Scrape(format!("no '{}' tag found in eBird species page", tag)))
+}
+
+/// How much of the `<head>` a `BirdError::LayoutChanged` shows, in characters
+const HEAD_SNIPPET: usize = 300;
+
+/// The start of the page's `<head>` on one line, to show what eBird serves instead
+fn head_snippet(doc: &Html) -> String {
+    let head = Selector::parse("head").unwrap();
+    let html = doc.select(&head).next().map(|h| h.inner_html()).unwrap_or_default();
+    let html = html.split_whitespace().collect::<Vec<_>>().join(" ");
+    match html.char_indices().nth(HEAD_SNIPPET) {
+        Some((i, _)) => format!("{}...", &html[..i]),
+        None => html,
+    }
+}
+
+/// Get a working Bluesky session: the saved one if it is still valid, a refreshed one
+/// if only its access token expired, and a brand new one otherwise
+fn get_session(config: &Config) -> Result<Token, BirdError> {
+    let Some(mut token) = session::load(&config.session_path, &config.handle) else {
+        let token = authenticate(config)?;
+        save_session(config, &token);
+        return Ok(token);
     };
 
-    return Some(Token{ token: token.to_string(), did: did.to_string()});
+    let url = format!("{}/xrpc/com.atproto.server.getSession", config.pds_url);
+    let r = send(config, || {
+        http::get(config, &url, config.bluesky_timeout)
+            .with_header("Authorization", format!("Bearer {}", token.token))
+    })?;
+
+    if r.status_code == 200 {
+        debug!("Reusing saved Bluesky session");
+    } else if is_expired_token(&r) {
+        refresh(config, &mut token)?;
+    } else {
+        info!("Saved Bluesky session was rejected (response code {}), authenticating again", r.status_code);
+        token = authenticate(config)?;
+        save_session(config, &token);
+    }
+    Ok(token)
 }
 
-/// Make a Bluesky post
-fn post(b: &Bird, photo: &BirdImage, token: &Token) -> bool {
-    // Get and upload the image card
-    let r_photo = match minreq::get(photo.url_download.clone())
-        .with_header("User-Agent", format!("BirdOfTheDayBot ({})", env::var("BOTD_EMAIL").unwrap()))
-        .with_timeout(30)
-        .send() {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("Error reading Macaulay Library response: {}", e);
-                return false;
-            }
-        };
+/// Save the session for the next run; a failure only costs a login next time
+fn save_session(config: &Config, token: &Token) {
+    if let Err(e) = session::save(&config.session_path, &config.handle, token) {
+        warn!("Error saving '{}': {}", config.session_path.display(), e);
+    }
+}
 
-    if r_photo.status_code != 200 {
-        eprintln!("Error during photo download (URL: {}): {}", photo.url_download, r_photo.as_str().unwrap());
-        return false;
+/// Authenticate username/password and get the `accessJwt`, `refreshJwt`, and `did` values
+fn authenticate(config: &Config) -> Result<Token, BirdError> {
+    if !looks_like_app_password(&config.password) {
+        warn!("BOTD_PASS doesn't look like an app password (xxxx-xxxx-xxxx-xxxx); logging in with the account password \
+            gives the bot full control of the account, and fails if it has two-factor authentication on");
     }
+    let json = json!({
+        "identifier": config.handle,
+        "password": config.password,
+    });
+    let url = format!("{}/xrpc/com.atproto.server.createSession", config.pds_url);
+    let r = traced("createSession", &url, || send(config, || http::post_json(config, &url, &json, config.bluesky_timeout)))?;
 
-    let blob = match minreq::post("https://bsky.social/xrpc/com.atproto.repo.uploadBlob")
-        .with_header("Content-Type", photo.photo_type.clone())
-        .with_header("Authorization", format!("Bearer {}", token.token))
-        .with_body(r_photo.as_bytes())
-        .with_timeout(30)
-        .send() {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("Error during photo upload: {}", e);
-                return false;
-            }
-        };
-    
-    if blob.status_code != 200 {
-        eprintln!("Error from photo upload (Response code {})", blob.status_code);
-        return false;
+    session_token(r)
+}
+
+/// Whether `password` has the shape of a Bluesky app password: four groups of four lowercase
+/// letters and digits, separated by dashes
+fn looks_like_app_password(password: &str) -> bool {
+    let groups: Vec<&str> = password.split('-').collect();
+    groups.len() == 4
+        && groups.iter().all(|g| g.len() == 4 && g.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit()))
+}
+
+/// Trade the refresh token for a new access token with `com.atproto.server.refreshSession`.
+/// If the refresh token has expired too, log in again with the password.
+fn refresh(config: &Config, token: &mut Token) -> Result<(), BirdError> {
+    let url = format!("{}/xrpc/com.atproto.server.refreshSession", config.pds_url);
+    let r = send(config, || {
+        http::post(config, &url, config.bluesky_timeout)
+            .with_header("Authorization", format!("Bearer {}", token.refresh_jwt))
+    })?;
+
+    *token = if is_expired_token(&r) {
+        info!("Bluesky refresh token expired, authenticating again");
+        authenticate(config)?
+    } else {
+        session_token(r)?
+    };
+    save_session(config, token);
+    Ok(())
+}
+
+/// Get the session tokens out of a `createSession` or `refreshSession` response
+fn session_token(r: minreq::Response) -> Result<Token, BirdError> {
+    let r = http::check(r).map_err(HttpError::auth)?;
+
+    let json = r.json::<Value>()
+        .map_err(|e| BirdError::Parse(format!("Successfully recieved token, but error occurred during conversion to JSON: {}", e)))?;
+
+    let field = |key: &str| json_str(&json, key)
+        .ok_or_else(|| BirdError::Parse(format!("Successfully converted response to JSON, but '{}' parameter was not present", key)));
+
+    Ok(Token {
+        token: field("accessJwt")?,
+        refresh_jwt: field("refreshJwt")?,
+        did: field("did")?,
+    })
+}
+
+/// Send an authenticated Bluesky request. If the access token has expired, refresh
+/// the session, update `token`, and send the request once more.
+fn with_reauth<F>(config: &Config, token: &mut Token, request: F) -> Result<minreq::Response, BirdError>
+where
+    F: Fn(&Token) -> minreq::Request,
+{
+    let r = send(config, || request(token))?;
+    if !is_expired_token(&r) {
+        return Ok(r);
     }
+    info!("Bluesky access token expired, refreshing the session");
+    refresh(config, token)?;
+    send(config, || request(token))
+}
+
+/// Whether Bluesky rejected a request because the token it was sent with expired
+fn is_expired_token(r: &minreq::Response) -> bool {
+    (r.status_code == 400 || r.status_code == 401)
+        && r.json::<Value>().is_ok_and(|j| matches!(j.get("error").and_then(|e| e.as_str()), Some("ExpiredToken" | "InvalidToken")))
+}
 
-    let blob_json = match blob.json::<Value>() {
-        Ok(b) => b,
-        Err(e) => {
-            eprintln!("Error converting photo upload to JSON: {}", e);
-            return false;
+/// Get a string field out of a JSON object
+fn json_str(json: &Value, key: &str) -> Option<String> {
+    json.get(key).and_then(|v| v.as_str()).map(|v| v.to_string())
+}
+
+/// Make a Bluesky post, returning its URI and CID. On a dry run, print what would have been
+/// posted and return `None`. Photos uploaded by an earlier attempt are reused from `uploaded`
+/// rather than uploaded again. A post linking to a recording only shows the first photo, so
+/// only that one is uploaded.
+fn post(config: &Config, b: &Bird, photos: &[BirdImage], token: &mut Token, uploaded: &mut Option<UploadedBlobs>) -> Result<Option<PostResult>, BirdError> {
+    let photos = match audio_embed(config, &photos[0]) {
+        Some(_) => &photos[..1],
+        None => photos,
+    };
+    let blob_refs = match uploaded {
+        Some(u) if u.species_code == b.species_code => {
+            debug!("Reusing photos uploaded by an earlier attempt");
+            u.blobs.clone()
+        }
+        _ => {
+            let Some(blobs) = upload_photos(config, b, photos, token)? else {
+                return Ok(None);
+            };
+            *uploaded = Some(UploadedBlobs { species_code: b.species_code.clone(), blobs: blobs.clone() });
+            blobs
         }
     };
     
     // Image card upload was successful, now make the post
-    let text = format!("{} ({})\n\nImage Credit", b.common_name, b.scientific_name);
-    let post_json = json!({
-        "repo": token.did,
+    let post_json = build_post(&config.template, b, photos, &blob_refs, &token.did, audio_embed(config, &photos[0]));
+
+    let url = format!("{}/xrpc/com.atproto.repo.createRecord", config.pds_url);
+    let post = traced("createRecord", &url, || with_reauth(config, token, |token| {
+        http::post_json(config, &url, &post_json, config.bluesky_timeout)
+            .with_header("Authorization", format!("Bearer {}", token.token))
+    }))?;
+    let post = http::check(post).map_err(HttpError::post)?;
+
+    // The post exists at this point, so a malformed response is only worth a warning
+    let post_json = post.json::<Value>().unwrap_or_else(|e| {
+        warn!("Post was created, but error occurred during conversion to JSON: {}", e);
+        Value::Null
+    });
+    let field = |key: &str| {
+        let value = json_str(&post_json, key);
+        if value.is_none() {
+            warn!("Post was created, but '{}' parameter was not present", key);
+        }
+        value
+    };
+
+    Ok(Some(PostResult { uri: field("uri"), cid: field("cid") }))
+}
+
+/// Delete the Bluesky post at `uri`, e.g. `at://did:plc:.../app.bsky.feed.post/3k...`, with
+/// `com.atproto.repo.deleteRecord`, then mark it deleted in the history so its bird can be picked
+/// again. Returns whether the post was in the history.
+pub fn delete_post(config: &Config, uri: &str) -> Result<bool, BirdError> {
+    let (repo, collection, rkey) = parse_at_uri(uri)
+        .ok_or_else(|| BirdError::Config(format!("'{}' is not the AT URI of a post", uri)))?;
+    let _lock = RunLock::acquire(&config.lock_path, Duration::from_secs(config.stale_lock_age))?;
+    let mut token = get_session(config)?;
+
+    let body = json!({
+        "repo": repo,
+        "collection": collection,
+        "rkey": rkey,
+    });
+    let url = format!("{}/xrpc/com.atproto.repo.deleteRecord", config.pds_url);
+    let r = traced("deleteRecord", &url, || with_reauth(config, &mut token, |token| {
+        http::post_json(config, &url, &body, config.bluesky_timeout)
+            .with_header("Authorization", format!("Bearer {}", token.token))
+    }))?;
+    http::check(r).map_err(HttpError::post)?;
+
+    let mut history = History::load(&config.history_path)?;
+    if !history.mark_deleted(uri) {
+        return Ok(false);
+    }
+    history.save(&config.history_path)?;
+    Ok(true)
+}
+
+/// The repo, collection, and record key of an AT URI
+fn parse_at_uri(uri: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = uri.strip_prefix("at://")?.split('/');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(repo), Some(collection), Some(rkey), None) if ![repo, collection, rkey].contains(&"") => Some((repo, collection, rkey)),
+        _ => None,
+    }
+}
+
+/// Download the photos and upload them to Bluesky, returning the blob references to embed.
+/// On a dry run, print what would have been posted and return `None` instead of uploading.
+fn upload_photos(config: &Config, b: &Bird, photos: &[BirdImage], token: &mut Token) -> Result<Option<Vec<Value>>, BirdError> {
+    // Download every photo before uploading any, so one that is too big doesn't leave the others orphaned
+    let downloads = photos.iter()
+        .map(|photo| download_image(config, photo).map(|bytes| for_bluesky(config, photo, bytes)))
+        .collect::<Result<Vec<_>, _>>()?;
+    validate_post(config, b, photos, &downloads.iter().map(|(bytes, _)| bytes.len()).collect::<Vec<_>>())?;
+
+    if config.dry_run {
+        let blobs = vec![json!("<photo blob>"); photos.len()];
+        let post_json = build_post(&config.template, b, photos, &blobs, &token.did, audio_embed(config, &photos[0]));
+        info!("Dry run, not posting. Would have posted:");
+        info!("  text: {:?}", post_json["record"]["text"]);
+        info!("  facets: {}", post_json["record"]["facets"]);
+        for (photo, (r_photo, photo_type)) in photos.iter().zip(&downloads) {
+            info!("  alt text: {:?}", image_alt_text(b, photo));
+            info!("  image: {} ({}, {} bytes)", photo.url_download, photo_type, r_photo.len());
+        }
+        if let Some(audio) = audio_embed(config, &photos[0]) {
+            info!("  audio: {}", audio);
+        }
+        info!("  createRecord: {}", serde_json::to_string_pretty(&post_json).unwrap());
+        return Ok(None);
+    }
+
+    let url = format!("{}/xrpc/com.atproto.repo.uploadBlob", config.pds_url);
+    let mut blobs = Vec::new();
+    for (r_photo, photo_type) in &downloads {
+        let blob = traced("uploadBlob", &url, || with_reauth(config, token, |token| {
+            http::post(config, &url, config.bluesky_timeout)
+                .with_header("Content-Type", *photo_type)
+                .with_header("Authorization", format!("Bearer {}", token.token))
+                .with_body(r_photo.as_slice())
+        }))?;
+        let blob = http::check(blob).map_err(HttpError::post)?;
+
+        let blob_json = blob.json::<Value>()
+            .map_err(|e| BirdError::Parse(format!("Error converting photo upload to JSON: {}", e)))?;
+        let blob_ref = blob_json.get("blob")
+            .ok_or_else(|| BirdError::Parse("'blob' parameter was not present in photo upload response".to_string()))?;
+        blobs.push(blob_ref.clone());
+    }
+    Ok(Some(blobs))
+}
+
+/// The downloaded `photo` of `image` as it is to be uploaded to Bluesky, with its MIME type.
+/// With the `webp-to-jpeg` feature a WebP is converted to a JPEG, as Bluesky doesn't always show
+/// them well, and with `resize-photos` a photo over `config.max_photo_bytes` is shrunk to fit.
+/// Either way a photo that can't be decoded is uploaded as it is.
+#[cfg_attr(not(feature = "resize-photos"), allow(unused_variables))]
+fn for_bluesky<'a>(config: &Config, image: &'a BirdImage, photo: Vec<u8>) -> (Vec<u8>, &'a str) {
+    let photo_type = photo_type(image, &photo);
+    #[cfg(feature = "webp-to-jpeg")]
+    let (photo, photo_type) = match photo_type {
+        "image/webp" => match webp_to_jpeg(&photo) {
+            Ok(jpeg) => {
+                debug!("Converted {} from WebP to JPEG, {} bytes to {}", image.url_download, photo.len(), jpeg.len());
+                (jpeg, "image/jpeg")
+            }
+            Err(e) => {
+                warn!("Error converting {} to JPEG, uploading it as WebP: {}", image.url_download, e);
+                (photo, photo_type)
+            }
+        },
+        _ => (photo, photo_type),
+    };
+    #[cfg(feature = "resize-photos")]
+    let (photo, photo_type) = match photo.len() > config.max_photo_bytes {
+        true => match shrink_photo(&image.url_download, &photo, config.max_photo_bytes) {
+            Ok(smaller) => (smaller, "image/jpeg"),
+            Err(e) => {
+                warn!("Error shrinking {}, uploading it as it is: {}", image.url_download, e);
+                (photo, photo_type)
+            }
+        },
+        false => (photo, photo_type),
+    };
+    (photo, photo_type)
+}
+
+/// Scale `photo`, keeping its aspect ratio, and encode it as a JPEG of at most `max_bytes`,
+/// trying smaller sizes until it fits
+#[cfg(feature = "resize-photos")]
+fn shrink_photo(url: &str, photo: &[u8], max_bytes: usize) -> Result<Vec<u8>, String> {
+    let decoded = image::load_from_memory(photo).map_err(|e| e.to_string())?;
+    let (width, height) = (decoded.width(), decoded.height());
+    // JPEG size goes roughly with the number of pixels, so start from the ratio of the sizes
+    let mut scale = (max_bytes as f64 / photo.len() as f64).sqrt().min(1.0);
+    let mut smallest = (width, height, photo.len());
+    for _ in 0..8 {
+        let (w, h) = (((width as f64 * scale) as u32).max(1), ((height as f64 * scale) as u32).max(1));
+        let resized = decoded.resize(w, h, image::imageops::FilterType::Triangle).to_rgb8();
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 85).encode_image(&resized).map_err(|e| e.to_string())?;
+        if jpeg.len() <= max_bytes {
+            info!("Shrunk {} from {}x{} ({} bytes) to {}x{} ({} bytes)",
+                url, width, height, photo.len(), resized.width(), resized.height(), jpeg.len());
+            return Ok(jpeg);
+        }
+        smallest = (resized.width(), resized.height(), jpeg.len());
+        scale *= 0.75;
+    }
+    let (w, h, bytes) = smallest;
+    Err(format!("from {}x{} ({} bytes), the smallest tried, {}x{}, was still {} bytes, over {}",
+        width, height, photo.len(), w, h, bytes, max_bytes))
+}
+
+/// Decode a WebP and encode it again as a JPEG, dropping any transparency
+#[cfg(feature = "webp-to-jpeg")]
+fn webp_to_jpeg(webp: &[u8]) -> Result<Vec<u8>, image::ImageError> {
+    let decoded = image::load_from_memory_with_format(webp, image::ImageFormat::WebP)?;
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90).encode_image(&decoded.to_rgb8())?;
+    Ok(jpeg)
+}
+
+/// Download the photo of `image` from the CDN
+pub fn download_image(config: &Config, image: &BirdImage) -> Result<Vec<u8>, BirdError> {
+    config.ebird_rate_limit.acquire();
+    let r = traced("photo download", &image.url_download, || send(config, || http::get(config, &image.url_download, config.image_timeout)))?;
+    Ok(http::check(r).map_err(HttpError::ebird)?.into_bytes())
+}
+
+/// Download the photo of `image`, the `n`th of `bird`'s counting from 0, to the data directory,
+/// returning where it was saved and the photo itself
+pub fn save_image(config: &Config, bird: &Bird, image: &BirdImage, n: usize) -> Result<(PathBuf, Vec<u8>), BirdError> {
+    let photo = download_image(config, image)?;
+    let path = config.photo_path(&bird.species_code, n, photo_extension(photo_type(image, &photo)));
+    fs::write(&path, &photo)?;
+    Ok((path, photo))
+}
+
+/// File extension for a photo of MIME type `photo_type`
+fn photo_extension(photo_type: &str) -> &'static str {
+    match photo_type {
+        "image/png" => "png",
+        "image/webp" => "webp",
+        "image/gif" => "gif",
+        _ => "jpg",
+    }
+}
+
+/// Largest image blob Bluesky accepts, in bytes
+pub const MAX_BLOB_BYTES: usize = 1_000_000;
+
+/// Longest post text Bluesky accepts, in graphemes
+const MAX_POST_GRAPHEMES: usize = 300;
+
+/// Most photos Bluesky allows in one post
+pub const MAX_PHOTOS: usize = 4;
+
+/// Check the post for `b`, with `photos` of `image_sizes` bytes, against Bluesky's limits before
+/// uploading anything, as a post breaking them would be rejected however many times it was retried
+fn validate_post(config: &Config, b: &Bird, photos: &[BirdImage], image_sizes: &[usize]) -> Result<(), BirdError> {
+    let invalid = |message: String| Err(BirdError::Post { status: None, message });
+    if let Some((photo, &size)) = photos.iter().zip(image_sizes).find(|(_, &size)| size > MAX_BLOB_BYTES) {
+        let hint = match cfg!(feature = "resize-photos") {
+            true => "even after trying to shrink it",
+            false => "build with the resize-photos feature to shrink it",
+        };
+        return invalid(format!("photo {} is {} bytes, over Bluesky's limit of {} ({})", photo.url_download, size, MAX_BLOB_BYTES, hint));
+    }
+    // A grapheme is one or more chars, so counting chars errs on the side of too long
+    let len = post_text(&config.template, b, photos).chars().count();
+    if len > MAX_POST_GRAPHEMES {
+        return invalid(format!("post text is {} characters, over the limit of {}", len, MAX_POST_GRAPHEMES));
+    }
+    Ok(())
+}
+
+/// Link text crediting the photographer, when the page doesn't say who that is
+const IMAGE_CREDIT: &str = "Image Credit";
+
+/// Where the photos on eBird species pages are archived, credited after the photographers
+const ARCHIVE: &str = "Macaulay Library";
+
+/// UTF-8 byte offsets of the last occurrence of `needle` in `text`, as Bluesky facets expect
+fn byte_range(text: &str, needle: &str) -> Option<Range<usize>> {
+    text.rfind(needle).map(|start| start..start + needle.len())
+}
+
+/// Scraped alt text shorter than this gets the bird's taxonomy added to it
+const MIN_ALT_TEXT_LEN: usize = 40;
+
+/// Alt text for the photo of `bird`: the text scraped from eBird, with the bird's names and
+/// family added when that is too short to say much on its own
+pub fn build_alt_text(bird: &Bird, scraped: &str) -> String {
+    let scraped = scraped.trim();
+    if scraped.chars().count() >= MIN_ALT_TEXT_LEN {
+        return scraped.to_string();
+    }
+    let mut taxonomy = format!("Photo of a {} ({})", bird.common_name, bird.scientific_name);
+    if let Some(family) = &bird.family_com_name {
+        taxonomy.push_str(&format!(", in the {} family", family));
+    }
+    taxonomy.push('.');
+    if scraped.is_empty() {
+        taxonomy
+    } else {
+        format!("{}. {}", scraped.trim_end_matches('.'), taxonomy)
+    }
+}
+
+/// Alt text for `image` of `bird`: [`build_alt_text`] for a photo, and a range map's as it is
+pub fn image_alt_text(bird: &Bird, image: &BirdImage) -> String {
+    match image.range_map {
+        true => image.alt_text.clone(),
+        false => build_alt_text(bird, &image.alt_text),
+    }
+}
+
+/// Text of the Bluesky post for `bird` with `photos`: `template` filled in, with the image
+/// credits that link to the photos' pages in place of `{credit}`, and a hashtag for its order if
+/// the taxonomy gives one
+pub fn post_text(template: &Template, bird: &Bird, photos: &[BirdImage]) -> String {
+    compose_text(template, bird, photos).0
+}
+
+/// The post text, and the byte range of each credit link in it with the page it links to. When
+/// the photographers of all the photos are known, they are credited by name, as in `Photo © Jane
+/// Doe / Macaulay Library` or `Photos © Jane Doe, John Roe / Macaulay Library`. Otherwise a single
+/// photo is credited as `Image Credit`, several as `Image Credits: 1, 2, 3`. A range map gets a
+/// `Range map: eBird` line of its own.
+fn compose_text<'a>(template: &Template, bird: &Bird, images: &'a [BirdImage]) -> (String, Vec<(Range<usize>, &'a str)>) {
+    let mut text = String::new();
+    let mut credits = Vec::new();
+    template.render(bird, &mut text, |text| push_credits(text, images, &mut credits));
+    if let Some(tag) = hashtag(bird) {
+        text.push_str(&format!("\n\n#{}", tag));
+    }
+    (text, credits)
+}
+
+/// Append the credits for `images` to `text`, as [`compose_text`] words them, noting each link in
+/// `credits`
+fn push_credits<'a>(text: &mut String, images: &'a [BirdImage], credits: &mut Vec<(Range<usize>, &'a str)>) {
+    let mut push_link = |text: &mut String, link: &str, uri: &'a str| {
+        credits.push((text.len()..text.len() + link.len(), uri));
+        text.push_str(link);
+    };
+    let (maps, photos): (Vec<&BirdImage>, Vec<&BirdImage>) = images.iter().partition(|i| i.range_map);
+    let photographers: Option<Vec<&str>> = photos.iter().map(|p| p.photographer.as_deref()).collect();
+    match photographers {
+        Some(names) if !names.is_empty() => {
+            text.push_str(if names.len() > 1 { "Photos © " } else { "Photo © " });
+            for (i, (name, photo)) in names.iter().zip(&photos).enumerate() {
+                if i > 0 {
+                    text.push_str(", ");
+                }
+                push_link(text, name, &photo.url_source);
+            }
+            text.push_str(&format!(" / {}", ARCHIVE));
+        }
+        _ if photos.len() <= 1 => {
+            if let Some(photo) = photos.first() {
+                push_link(text, IMAGE_CREDIT, &photo.url_source);
+            }
+        }
+        _ => {
+            text.push_str(&format!("{}s: ", IMAGE_CREDIT));
+            for (i, photo) in photos.iter().enumerate() {
+                if i > 0 {
+                    text.push_str(", ");
+                }
+                push_link(text, &(i + 1).to_string(), &photo.url_source);
+            }
+        }
+    }
+    for map in maps {
+        text.push_str("\nRange map: ");
+        push_link(text, "eBird", &map.url_source);
+    }
+}
+
+/// Hashtag for the bird's order, e.g. `Passeriformes`, without the `#`
+fn hashtag(bird: &Bird) -> Option<String> {
+    let tag: String = bird.order.as_deref()?.chars().filter(|c| c.is_alphanumeric()).collect();
+    (!tag.is_empty()).then_some(tag)
+}
+
+/// The recording to link to, if audio is wanted and the species page had one
+fn audio_embed<'a>(config: &Config, photo: &'a BirdImage) -> Option<&'a str> {
+    photo.audio_url.as_deref().filter(|_| config.include_audio)
+}
+
+/// The `createRecord` request body for a post of `b` with `photos`, uploaded as `blob_refs`, in
+/// the words of `template`. With a recording to link to in `audio`, the post gets a link card for
+/// the recording instead of the photos: Bluesky has no audio embed and allows only one embed per
+/// post, so the first photo becomes the card's thumbnail, and is the only one credited.
+fn build_post(template: &Template, b: &Bird, photos: &[BirdImage], blob_refs: &[Value], did: &str, audio: Option<&str>) -> Value {
+    let photos = match audio {
+        Some(_) => &photos[..1],
+        None => photos,
+    };
+    let (text, credits) = compose_text(template, b, photos);
+    let mut facets: Vec<Value> = credits.iter().map(|(credit, uri)| json!({
+        "index": {
+            "byteStart": credit.start,
+            "byteEnd": credit.end,
+        },
+        "features": [{
+            "$type": "app.bsky.richtext.facet#link",
+            "uri": uri
+        }]
+    })).collect();
+    if let Some(tag) = hashtag(b) {
+        let range = byte_range(&text, &format!("#{}", tag)).expect("post text contains the hashtag");
+        facets.push(json!({
+            "index": {
+                "byteStart": range.start,
+                "byteEnd": range.end,
+            },
+            "features": [{
+                "$type": "app.bsky.richtext.facet#tag",
+                "tag": tag
+            }]
+        }));
+    }
+    let embed = match audio {
+        Some(audio) => json!({
+            "$type": "app.bsky.embed.external",
+            "external": {
+                "uri": audio,
+                "title": format!("{} song and calls", b.common_name),
+                "description": "Recording from the Macaulay Library",
+                "thumb": blob_refs[0],
+            },
+        }),
+        None => json!({
+            "$type": "app.bsky.embed.images",
+            "images": photos.iter().zip(blob_refs).map(|(photo, blob_ref)| json!({
+                "alt": image_alt_text(b, photo),
+                "image": blob_ref,
+            })).collect::<Vec<_>>(),
+        }),
+    };
+    json!({
+        "repo": did,
         "collection": "app.bsky.feed.post",
         "record": {
             "$type": "app.bsky.feed.post",
             "text": text,
-            "facets": [
-                {
-                "index": {
-                    "byteStart": text.len() - "Image Credit".len(),
-                    "byteEnd": text.len(),
-                },
-                "features": [{
-                    "$type": "app.bsky.richtext.facet#link",
-                    "uri": photo.url_source
-                }]
-                }
-            ],
+            "facets": facets,
             "createdAt": OffsetDateTime::now_utc().format(&Rfc3339).unwrap(),
-            "embed": {
-                "$type": "app.bsky.embed.images",
-                "images": [{
-                        "alt": photo.alt_text,
-                        "image": blob_json.get("blob").unwrap(),
-                    }],  
+            "embed": embed,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::Path, time::Duration};
+
+    use super::*;
+
+    fn bird_json(code: &str) -> Value {
+        json!({
+            "sciName": format!("Avis {}", code),
+            "comName": format!("Bird {}", code),
+            "speciesCode": code,
+            "category": "species",
+            "taxonOrder": 1.0,
+        })
+    }
+
+    fn bird(code: &str) -> Bird {
+        serde_json::from_value(bird_json(code)).unwrap()
+    }
+
+    /// A minimal species page whose photo is served by `host`
+    fn species_page(host: &str) -> String {
+        format!(r#"<html><head>
+            <meta property="og:image" content="http://{host}/photo.jpg">
+            <meta property="og:image:alt" content="A bird">
+            <meta property="og:url" content="https://macaulaylibrary.org/asset/1">
+            <link rel="image_src" type="image/jpeg" href="http://{host}/photo.jpg">
+            </head></html>"#)
+    }
+
+    /// A server standing in for eBird, the photo CDN, and Bluesky, where every species page has
+    /// the photo at `/photo.jpg` and logging in, uploading, and posting all succeed, except for
+    /// the requests `overrides` answers
+    fn bluesky_server<F>(overrides: F) -> test_server::TestServer
+    where
+        F: Fn(&test_server::Request) -> Option<test_server::Response> + Send + Sync + 'static,
+    {
+        use test_server::{Response, TestServer};
+
+        TestServer::start(move |r| overrides(r).unwrap_or_else(|| match r.path.as_str() {
+            p if p.starts_with("/species/") => Response::new(200, species_page(r.header("Host").unwrap())),
+            "/photo.jpg" => Response::new(200, "jpeg bytes"),
+            "/xrpc/com.atproto.server.createSession" => Response::json(200, json!({"accessJwt": "jwt", "refreshJwt": "refresh", "did": "did:plc:test"})),
+            "/xrpc/com.atproto.repo.uploadBlob" => Response::json(200, json!({"blob": {"$type": "blob"}})),
+            "/xrpc/com.atproto.repo.createRecord" => Response::json(200, json!({"uri": "at://post", "cid": "cid"})),
+            _ => Response::new(404, ""),
+        }))
+    }
+
+    /// A species page eBird has no photo for
+    const NO_PHOTO: &str = r#"<html><head><meta property="og:title" content="A bird - eBird"></head></html>"#;
+
+    /// Config keeping every file in `dir` and sending every request to `url`
+    fn test_config(dir: &Path, url: &str) -> Config {
+        Config {
+            handle: "bird.bsky.social".to_string(),
+            birds_path: dir.join("birds.json"),
+            history_path: dir.join("posted_history.json"),
+            session_path: dir.join("session.json"),
+            post_log_path: dir.join("posts.jsonl"),
+            lock_path: dir.join("botd.lock"),
+            auto_update_taxonomy: false,
+            ebird_api_url: url.to_string(),
+            ebird_url: url.to_string(),
+            pds_url: url.to_string(),
+            ..Config::default()
+        }
+    }
+
+    /// A fresh, empty directory for a test's files
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("botd-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn history(codes: &[&str]) -> History {
+        let mut h = History::default();
+        for (i, c) in codes.iter().enumerate() {
+            h.record(c, &format!("2024-01-{:02}T00:00:00Z", i + 1), None, &[Lookback::Posts(30)]);
+        }
+        h
+    }
+
+    fn refs(birds: &[Bird]) -> Vec<&Bird> {
+        birds.iter().collect()
+    }
+
+    #[test]
+    fn recently_posted_birds_are_never_chosen() {
+        let birds: Vec<Bird> = ["a", "b", "c", "d"].iter().map(|c| bird(c)).collect();
+        let h = history(&["a", "b", "c"]);
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            assert_eq!(choose_bird(&refs(&birds), &h, Lookback::Posts(30), OffsetDateTime::now_utc(), SelectionStrategy::Uniform, &Preferences::default(), &mut rng).unwrap().species_code, "d");
+        }
+    }
+
+    #[test]
+    fn posting_every_bird_once_rolls_over_to_the_oldest() {
+        let birds: Vec<Bird> = ["a", "b", "c"].iter().map(|c| bird(c)).collect();
+        let mut h = history(&["b", "a", "c"]);
+        let day = |d: &str| OffsetDateTime::parse(&format!("{}T00:00:00Z", d), &Rfc3339).unwrap();
+        let pick = |h: &History, lookback, today| {
+            choose_bird(&refs(&birds), h, lookback, today, SelectionStrategy::Uniform, &Preferences::default(), &mut rand::thread_rng()).unwrap().species_code.clone()
+        };
+        assert_eq!(pick(&h, Lookback::Forever, day("2030-01-01")), "b");
+        assert_eq!(pick(&h, Lookback::Days(365), day("2024-06-01")), "b");
+
+        h.record("b", "2024-06-01T00:00:00Z", None, &[Lookback::Forever]);
+        assert_eq!(pick(&h, Lookback::Forever, day("2030-01-01")), "a");
+        assert_eq!(h.entries.len(), 4);
+    }
+
+    #[test]
+    fn only_the_window_is_excluded() {
+        let birds: Vec<Bird> = ["a", "b"].iter().map(|c| bird(c)).collect();
+        let h = history(&["a", "b"]);
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            assert_eq!(choose_bird(&refs(&birds), &h, Lookback::Posts(1), OffsetDateTime::now_utc(), SelectionStrategy::Uniform, &Preferences::default(), &mut rng).unwrap().species_code, "a");
+        }
+    }
+
+    #[test]
+    fn same_date_gives_same_bird() {
+        let birds: Vec<Bird> = (0..50).map(|i| bird(&format!("b{}", i))).collect();
+        let h = History::default();
+        let pick = |d: Date| choose_bird(&refs(&birds), &h, Lookback::Posts(30), OffsetDateTime::now_utc(), SelectionStrategy::Uniform, &Preferences::default(), &mut date_rng(d)).unwrap().species_code.clone();
+
+        let date = Date::from_calendar_date(2024, time::Month::May, 4).unwrap();
+        assert_eq!(pick(date), pick(date));
+        let week: HashSet<String> = (0..7).map(|i| pick(date + time::Duration::days(i))).collect();
+        assert!(week.len() > 1);
+    }
+
+    #[test]
+    fn same_date_and_taxonomy_file_give_the_same_species() {
+        let dir = temp_dir("same-date");
+        let birds: Vec<Value> = (0..50).map(|i| bird_json(&format!("b{}", i))).collect();
+        fs::write(dir.join("birds.json"), json!(birds).to_string()).unwrap();
+        let date = Date::from_calendar_date(2024, time::Month::May, 4).unwrap();
+        let config = Config { date: Some(date), ..test_config(&dir, "http://127.0.0.1:9") };
+        let pick = |config: &Config| get_bird(config, &History::default()).unwrap().species_code;
+
+        assert_eq!(pick(&config), pick(&config));
+        assert_eq!(pick(&config), get_bird_for_date(&config, &History::default(), date).unwrap().species_code);
+        let seeded = |date: Date| pick(&Config { date: Some(date), seed: Some(7), ..config.clone() });
+        assert_eq!(seeded(date), seeded(date + time::Duration::days(1)));
+    }
+
+    /// A taxonomy of `families` families of `size` birds each, with codes like `f1b2`
+    fn families_json(families: usize, size: usize) -> Value {
+        (0..families).flat_map(|f| (0..size).map(move |b| {
+            let mut bird = bird_json(&format!("f{}b{}", f, b));
+            bird["familyCode"] = json!(format!("f{}", f));
+            bird
+        })).collect()
+    }
+
+    #[test]
+    fn recently_posted_families_are_left_out_day_after_day() {
+        let dir = temp_dir("family-window");
+        fs::write(dir.join("birds.json"), families_json(4, 3).to_string()).unwrap();
+        let start = Date::from_calendar_date(2024, time::Month::May, 4).unwrap();
+
+        // The history has to keep the last 3 posts even when the history window is shorter
+        for history_window in [Lookback::default(), Lookback::Posts(1), Lookback::Days(1)] {
+            let config = Config { history_window, family_window: 3, family_repeat: FamilyRepeat::Exclude, ..test_config(&dir, "http://127.0.0.1:9") };
+            let mut h = History::default();
+            let mut families = Vec::new();
+            for day in 0..12 {
+                let date = start + time::Duration::days(day);
+                let b = get_bird_for_date(&config, &h, date).unwrap();
+                h.record(&b.species_code, &format!("{}T12:00:00Z", date), None, &config.history_lookbacks());
+                families.push(b.family_code.unwrap());
+            }
+            // With 4 families and the last 3 left out, there is only ever one to pick from
+            for window in families.windows(4) {
+                assert_eq!(window.iter().collect::<HashSet<_>>().len(), 4, "{}: {:?}", history_window, families);
+            }
+        }
+    }
+
+    #[test]
+    fn family_window_is_ignored_when_it_leaves_no_birds() {
+        let dir = temp_dir("family-window-fallback");
+        fs::write(dir.join("birds.json"), families_json(1, 5).to_string()).unwrap();
+        let config = Config { family_window: 3, family_repeat: FamilyRepeat::Exclude, ..test_config(&dir, "http://127.0.0.1:9") };
+        let h = history(&["f0b0"]);
+
+        let b = get_bird(&config, &h).unwrap();
+        assert_ne!(b.species_code, "f0b0");
+        assert_eq!(b.family_code.as_deref(), Some("f0"));
+    }
+
+    #[test]
+    fn falls_back_to_least_recently_posted() {
+        let birds: Vec<Bird> = ["a", "b", "c"].iter().map(|c| bird(c)).collect();
+        let h = history(&["b", "a", "c", "a"]);
+        assert_eq!(choose_bird(&refs(&birds), &h, Lookback::Posts(30), OffsetDateTime::now_utc(), SelectionStrategy::Uniform, &Preferences::default(), &mut rand::thread_rng()).unwrap().species_code, "b");
+    }
+
+    /// What picking a bird from a taxonomy of `birds` comes to
+    fn pick_from(name: &str, birds: Value) -> Result<Bird, BirdError> {
+        let dir = temp_dir(name);
+        fs::write(dir.join("birds.json"), birds.to_string()).unwrap();
+        get_bird(&test_config(&dir, "http://127.0.0.1:9"), &History::default())
+    }
+
+    #[test]
+    fn empty_taxonomy_is_an_error() {
+        match pick_from("empty", json!([])) {
+            Err(BirdError::NoCandidates(m)) => assert_eq!(m, "0 birds remained after filtering; taxonomy file has 0 entries"),
+            other => panic!("expected NoCandidates, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn taxonomy_of_only_spuhs_is_an_error() {
+        let spuhs: Vec<Value> = ["a", "b"].iter().map(|c| {
+            let mut spuh = bird_json(c);
+            spuh["comName"] = json!(format!("Avis sp. {}", c));
+            spuh["category"] = json!("spuh");
+            spuh
+        }).collect();
+        match pick_from("spuhs", Value::Array(spuhs)) {
+            Err(BirdError::NoCandidates(m)) => assert!(m.contains("taxonomy file has 2 entries"), "{}", m),
+            other => panic!("expected NoCandidates, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn choosing_from_no_birds_is_an_error() {
+        let result = choose_bird(&[], &History::default(), Lookback::Posts(30), OffsetDateTime::now_utc(), SelectionStrategy::FamilyBalanced, &Preferences::default(), &mut rand::thread_rng());
+        assert!(matches!(result, Err(BirdError::NoCandidates(_))));
+    }
+
+    #[test]
+    fn only_allowed_categories_are_picked() {
+        let birds: Vec<Value> = ["species", "issf", "domestic", "hybrid", "form", "intergrade", "slash", "spuh"].iter().map(|c| {
+            let mut b = bird_json(c);
+            b["category"] = json!(c);
+            b
+        }).collect();
+        let dir = temp_dir("categories");
+        fs::write(dir.join("birds.json"), Value::Array(birds).to_string()).unwrap();
+        let picked = |categories: Vec<String>| {
+            let config = Config { categories, ..test_config(&dir, "http://127.0.0.1:9") };
+            let catalog = BirdCatalog::load(&config.birds_path).unwrap();
+            candidates(&config, &catalog).unwrap().iter().map(|b| b.species_code.clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(picked(Config::default().categories), ["species"]);
+        assert_eq!(picked(vec!["species".to_string(), "issf".to_string()]), ["species", "issf"]);
+
+        let config = Config {
+            categories: ["species", "issf", "hybrid", "domestic", "form", "intergrade", "slash", "spuh"].map(str::to_string).to_vec(),
+            excluded_categories: vec!["domestic".to_string(), "spuh".to_string()],
+            ..test_config(&dir, "http://127.0.0.1:9")
+        };
+        let catalog = BirdCatalog::load(&config.birds_path).unwrap();
+        let pool = filter_birds(&config, &catalog).unwrap();
+        let codes: Vec<&str> = pool.birds.iter().map(|b| b.species_code.as_str()).collect();
+        assert_eq!(codes, ["species", "issf", "hybrid", "form", "intergrade", "slash"]);
+        assert_eq!((pool.excluded.category, pool.excluded.denied_category), (0, 2));
+    }
+
+    #[test]
+    fn blocklist_and_allowlist_are_read_on_every_pick() {
+        let dir = temp_dir("species-lists");
+        fs::write(dir.join("birds.json"), json!(["a", "b", "c", "d"].map(bird_json)).to_string()).unwrap();
+        let config = test_config(&dir, "http://127.0.0.1:9");
+        let catalog = BirdCatalog::load(&config.birds_path).unwrap();
+        let codes = || {
+            let pool = filter_birds(&config, &catalog).unwrap();
+            let codes: Vec<String> = pool.birds.iter().map(|b| b.species_code.clone()).collect();
+            (codes, pool.excluded.blocklist, pool.excluded.allowlist)
+        };
+        assert_eq!(codes(), (vec!["a".to_string(), "b".into(), "c".into(), "d".into()], 0, 0));
+
+        fs::write(config.blocklist_path(), "a # broken photo page\nBird b\nunknown\n").unwrap();
+        assert_eq!(codes(), (vec!["c".to_string(), "d".into()], 2, 0));
+
+        fs::write(config.allowlist_path(), "# curated\nb\nc\n").unwrap();
+        assert_eq!(codes(), (vec!["c".to_string()], 2, 1));
+
+        fs::write(config.allowlist_path(), "b\n").unwrap();
+        match candidates(&config, &catalog) {
+            Err(BirdError::NoCandidates(m)) => assert!(m.contains("allowlist.txt"), "{}", m),
+            other => panic!("expected NoCandidates, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn taxonomy_of_only_extinct_birds_is_an_error() {
+        let mut dodo = bird_json("dodo");
+        dodo["extinct"] = json!(true);
+        let result = pick_from("extinct", json!([dodo]));
+        assert!(matches!(result, Err(ref e @ BirdError::NoCandidates(_)) if !e.is_retryable()));
+    }
+
+    #[test]
+    fn only_birds_marked_extinct_are_left_out() {
+        let birds: Vec<Value> = [("alive", None), ("living", Some(false)), ("dodo", Some(true))].into_iter()
+            .map(|(code, extinct)| {
+                let mut b = bird_json(code);
+                if let Some(extinct) = extinct {
+                    b["extinct"] = json!(extinct);
                 }
+                b
+            })
+            .collect();
+        let catalog = BirdCatalog::parse(&json!(birds).to_string()).unwrap();
+        let picked = |include_extinct| {
+            let config = Config { include_extinct, ..Config::default() };
+            let pool = filter_birds(&config, &catalog).unwrap();
+            (pool.birds.iter().map(|b| b.species_code.as_str()).collect::<Vec<_>>(), pool.excluded.extinct)
+        };
+
+        assert_eq!(picked(false), (vec!["alive", "living"], 1));
+        assert_eq!(picked(true), (vec!["alive", "living", "dodo"], 0));
+    }
+
+    #[test]
+    fn chosen_bird_is_posted_even_if_it_was_recently() {
+        let dir = temp_dir("chosen");
+        fs::write(dir.join("birds.json"), json!([bird_json("a"), bird_json("b")]).to_string()).unwrap();
+        let config = Config { only_bird: Some(BirdChoice::CommonName("bird B".to_string())), ..test_config(&dir, "http://127.0.0.1:9") };
+        assert_eq!(get_bird(&config, &history(&["b"])).unwrap().species_code, "b");
+    }
+
+    #[test]
+    fn only_birds_of_the_family_are_picked() {
+        let mut hawk = bird_json("hawk");
+        hawk["familyComName"] = json!("Hawks, Eagles, and Kites");
+        hawk["familySciName"] = json!("Accipitridae");
+        let dir = temp_dir("family");
+        fs::write(dir.join("birds.json"), json!([bird_json("a"), hawk, bird_json("b")]).to_string()).unwrap();
+        let config = |family: &str| Config { only_family: Some(family.to_string()), ..test_config(&dir, "http://127.0.0.1:9") };
+
+        for family in ["hawks, eagles, and kites", "ACCIPITRIDAE", "Hawks, Eagles, and Kites,Accipitridae"] {
+            assert_eq!(get_bird(&config(family), &History::default()).unwrap().species_code, "hawk");
+        }
+        match get_bird(&config("Accipitridae,Hawks"), &History::default()) {
+            Err(BirdError::Config(m)) => assert!(m.contains("'Hawks'; did you mean Hawks, Eagles, and Kites"), "{}", m),
+            other => panic!("expected Config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn orders_are_filtered_along_with_families() {
+        let birds: Vec<Value> = [("a", "Passeriformes", "Cardinalidae"), ("hawk", "Accipitriformes", "Accipitridae"), ("owl", "Strigiformes", "Strigidae")]
+            .into_iter()
+            .map(|(code, order, family)| {
+                let mut b = bird_json(code);
+                b["order"] = json!(order);
+                b["familySciName"] = json!(family);
+                b
+            })
+            .collect();
+        let dir = temp_dir("order");
+        fs::write(dir.join("birds.json"), json!(birds).to_string()).unwrap();
+        let config = |order: &str, family: Option<&str>| Config {
+            only_order: Some(order.to_string()),
+            only_family: family.map(str::to_string),
+            ..test_config(&dir, "http://127.0.0.1:9")
+        };
+
+        let catalog = BirdCatalog::load(dir.join("birds.json")).unwrap();
+        let pool = filter_birds(&config("strigiformes, Accipitriformes", None), &catalog).unwrap();
+        assert_eq!(pool.birds.iter().map(|b| b.species_code.as_str()).collect::<Vec<_>>(), ["hawk", "owl"]);
+        assert_eq!(pool.excluded.order, 1);
+        assert_eq!(get_bird(&config("Strigiformes", Some("Strigidae, Cardinalidae")), &History::default()).unwrap().species_code, "owl");
+        match get_bird(&config("Strigiformes", Some("Cardinalidae")), &History::default()) {
+            Err(BirdError::NoCandidates(m)) => assert!(m.contains("order 'Strigiformes'"), "{}", m),
+            other => panic!("expected NoCandidates, got {:?}", other),
+        }
+        assert!(matches!(get_bird(&config("Owls", None), &History::default()), Err(BirdError::Config(m)) if m.contains("Strigiformes")));
+    }
+
+    #[test]
+    fn history_keeps_only_the_window() {
+        let h = {
+            let mut h = History::default();
+            for c in ["a", "b", "c"] {
+                h.record(c, "2024-01-01T00:00:00Z", None, &[Lookback::Posts(2)]);
             }
+            h
+        };
+        assert_eq!(h.entries.iter().map(|e| e.species_code.as_str()).collect::<Vec<_>>(), ["b", "c"]);
+    }
+
+    #[test]
+    fn run_with_config_reads_the_configured_taxonomy_path() {
+        let config = test_config(&temp_dir("missing"), "http://127.0.0.1:9");
+        assert!(matches!(run_with_config(&config, &mut config.publishers()), Err(BirdError::Io(_))));
+    }
+
+    #[test]
+    fn birds_without_a_photo_are_skipped() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/species/c" => Response::new(200, species_page(r.header("Host").unwrap())),
+            p if p.starts_with("/species/") => Response::new(200, NO_PHOTO),
+            _ => Response::new(404, ""),
         });
-    
-    let post = match minreq::post("https://bsky.social/xrpc/com.atproto.repo.createRecord")
-        .with_header("Content-Type", "application/json")
-        .with_header("Authorization", format!("Bearer {}", token.token))
-        .with_body(post_json.to_string())
-        .with_timeout(30)
-        .send() {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("Error during post creation: {}", e);
-                return false;
-            }
+        let dir = temp_dir("photo-repick");
+        fs::write(dir.join("birds.json"), json!([bird_json("a"), bird_json("b"), bird_json("c")]).to_string()).unwrap();
+        let config = test_config(&dir, &server.url);
+
+        let (b, images) = get_bird_with_photo(&config, &History::default()).unwrap();
+        assert_eq!(b.species_code, "c");
+        assert_eq!(images[0].alt_text, "A bird");
+        assert_eq!(server.hits("/species/c"), 1);
+    }
+
+    #[test]
+    fn forms_are_posted_with_the_photos_of_their_species() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/species/rethaw" | "/species/dangle1" => Response::new(200, species_page(r.header("Host").unwrap())),
+            _ => Response::new(200, NO_PHOTO),
+        });
+        let dir = temp_dir("report-as");
+        let mut birds: Value = serde_json::from_str(include_str!("../tests/fixtures/birds.json")).unwrap();
+        let mut dangling = bird_json("dangle1");
+        dangling["category"] = json!("issf");
+        dangling["reportAs"] = json!("dangle");
+        birds.as_array_mut().unwrap().push(dangling);
+        fs::write(dir.join("birds.json"), birds.to_string()).unwrap();
+        let config = Config { categories: vec!["issf".to_string()], ..test_config(&dir, &server.url) };
+
+        fs::write(config.allowlist_path(), "rethaw1\n").unwrap();
+        let (b, images) = get_bird_with_photo(&config, &History::default()).unwrap();
+        assert_eq!(b.species_code, "rethaw1");
+        assert!(post_text(&config.template, &b, &images).starts_with("Red-tailed Hawk (borealis) (Buteo jamaicensis borealis)"));
+        assert_eq!((server.hits("/species/rethaw"), server.hits("/species/rethaw1")), (1, 0));
+
+        // A form reported as a species that isn't in the taxonomy is looked up as itself
+        fs::write(config.allowlist_path(), "dangle1\n").unwrap();
+        assert_eq!(get_bird_with_photo(&config, &History::default()).unwrap().0.species_code, "dangle1");
+        assert_eq!(server.hits("/species/dangle1"), 1);
+    }
+
+    #[test]
+    fn date_overrides_are_posted_when_they_can_be() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/species/a" => Response::new(200, NO_PHOTO),
+            _ => Response::new(200, species_page(r.header("Host").unwrap())),
+        });
+        let dir = temp_dir("date-overrides");
+        fs::write(dir.join("birds.json"), json!(["a", "b", "c", "d"].map(bird_json)).to_string()).unwrap();
+        let config = Config {
+            date_overrides: "05-04=b, 05-05=a, 05-06=c, 05-07=dodo".parse().unwrap(),
+            ..test_config(&dir, &server.url)
         };
-    
-    if post.status_code != 200 {
-        eprintln!("Post creation unsuccessful: {}", post.as_str().unwrap());
-        return false;
+        fs::write(config.blocklist_path(), "c\n").unwrap();
+        let day = |d| Date::from_calendar_date(2024, time::Month::May, d).unwrap();
+        let pick = |d| get_bird_with_photo_for_date(&config, &History::default(), day(d)).unwrap().0.species_code;
+
+        assert_eq!(pick(4), "b");
+        assert_eq!(get_bird_for_date(&config, &History::default(), day(4)).unwrap().species_code, "b");
+        // no photo of a, so a bird is picked as usual instead
+        assert!(["b", "d"].contains(&pick(5).as_str()));
+        assert_eq!(server.hits("/species/a"), 1);
+        // c is blocklisted, and there is no dodo
+        assert_ne!(pick(6), "c");
+        assert_eq!(server.hits("/species/c"), 0);
+        assert!(["b", "d"].contains(&pick(7).as_str()));
+    }
+
+    #[test]
+    fn date_overrides_are_not_filtered() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/species/b" => Response::new(200, NO_PHOTO),
+            _ => Response::new(200, species_page(r.header("Host").unwrap())),
+        });
+        let dir = temp_dir("date-override-filters");
+        let mut form = bird_json("c");
+        form["category"] = json!("issf");
+        form["familySciName"] = json!("Otheridae");
+        fs::write(dir.join("birds.json"), json!([bird_json("a"), bird_json("b"), form]).to_string()).unwrap();
+        // The only bird in the family is a form, which the categories leave out
+        let config = Config {
+            only_family: Some("Otheridae".to_string()),
+            date_overrides: "05-04=a, 05-05=b".parse().unwrap(),
+            ..test_config(&dir, &server.url)
+        };
+        let day = |d| Date::from_calendar_date(2024, time::Month::May, d).unwrap();
+
+        let (b, _) = get_bird_with_photo_for_date(&config, &History::default(), day(4)).unwrap();
+        assert_eq!(b.species_code, "a");
+        assert_eq!(get_bird_for_date(&config, &History::default(), day(4)).unwrap().species_code, "a");
+        match get_bird_with_photo_for_date(&config, &History::default(), day(5)) {
+            Err(BirdError::NoCandidates(m)) => assert!(m.contains("the override for 2024-05-05"), "{}", m),
+            other => panic!("expected NoCandidates, got {:?}", other.map(|(b, _)| b.species_code)),
+        }
+    }
+
+    #[test]
+    fn photo_attempts_are_bounded() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|_| Response::new(200, NO_PHOTO));
+        let dir = temp_dir("photo-attempts");
+        let birds: Vec<Value> = (0..10).map(|i| bird_json(&format!("b{}", i))).collect();
+        fs::write(dir.join("birds.json"), Value::Array(birds).to_string()).unwrap();
+        let config = Config { photo_attempts: 3, ..test_config(&dir, &server.url) };
+
+        let result = get_bird_with_photo(&config, &History::default());
+        assert!(matches!(result, Err(BirdError::NoCandidates(_))));
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn session_is_reused_across_attempts() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use test_server::Response;
+
+        let photo_failures = AtomicUsize::new(2);
+        let server = bluesky_server(move |r| match r.path.as_str() {
+            "/photo.jpg" if photo_failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() =>
+                Some(Response::new(503, "try again")),
+            _ => None,
+        });
+
+        let dir = temp_dir("session-reuse");
+        fs::write(dir.join("birds.json"), json!([bird_json("a")]).to_string()).unwrap();
+        let config = test_config(&dir, &server.url);
+        let policy = RetryPolicy { max_attempts: 3, base_delay: Duration::ZERO, multiplier: 2.0, max_delay: Duration::ZERO, jitter: false };
+
+        let published = run_with_retry(&config, &policy, &mut config.publishers()).unwrap().published;
+        assert_eq!(published[0].result.as_ref().unwrap().as_ref().unwrap().uri.as_deref(), Some("at://post"));
+        assert_eq!(server.hits("/photo.jpg"), 3);
+        assert_eq!(server.hits("/species/a"), 1);
+        assert_eq!(server.hits("/xrpc/com.atproto.server.createSession"), 1);
+    }
+
+    #[test]
+    fn shuffle_posts_every_bird_before_repeating_one() {
+
+        let server = bluesky_server(|_| None);
+        let dir = temp_dir("shuffle");
+        fs::write(dir.join("birds.json"), json!(["a", "b", "c"].map(bird_json)).to_string()).unwrap();
+        let config = Config { selection: SelectionStrategy::Shuffle, ..test_config(&dir, &server.url) };
+        let post = || run_with_config(&config, &mut config.publishers()).unwrap().bird.species_code;
+
+        let mut first: Vec<String> = (0..3).map(|_| post()).collect();
+        let queue = ShuffleQueue::load(config.shuffle_queue_path()).unwrap();
+        assert_eq!(queue.posted, first);
+        assert!(queue.remaining.is_empty());
+        first.sort();
+        assert_eq!(first, ["a", "b", "c"]);
+
+        post();
+        let queue = ShuffleQueue::load(config.shuffle_queue_path()).unwrap();
+        assert_eq!((queue.remaining.len(), queue.posted.len()), (2, 1));
+    }
+
+    #[test]
+    fn consecutive_posts_share_one_session() {
+
+        let server = bluesky_server(|_| None);
+        let config = test_config(&temp_dir("session-cache"), &server.url);
+        let image = BirdImage { url_download: format!("{}/photo.jpg", server.url), ..photo(None) };
+        let mut publisher = BlueskyPublisher::new();
+
+        for code in ["a", "b"] {
+            assert!(publisher.publish(&config, &bird(code), std::slice::from_ref(&image)).unwrap().is_some());
+        }
+        assert_eq!(server.hits("/xrpc/com.atproto.server.createSession"), 1);
+        assert_eq!(server.hits("/xrpc/com.atproto.server.getSession"), 0);
+        assert_eq!(server.hits("/xrpc/com.atproto.server.refreshSession"), 0);
+        assert_eq!(server.hits("/xrpc/com.atproto.repo.createRecord"), 2);
+    }
+
+    #[test]
+    fn expired_session_is_refreshed_before_posting() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match (r.path.as_str(), r.header("Authorization")) {
+            ("/photo.jpg", _) => Response::new(200, "jpeg bytes"),
+            ("/xrpc/com.atproto.server.refreshSession", _) =>
+                Response::json(200, json!({"accessJwt": "fresh", "refreshJwt": "refresh2", "did": "did:plc:test"})),
+            (_, Some("Bearer fresh")) if r.path == "/xrpc/com.atproto.repo.uploadBlob" => Response::json(200, json!({"blob": {"$type": "blob"}})),
+            (_, Some("Bearer fresh")) if r.path == "/xrpc/com.atproto.repo.createRecord" => Response::json(200, json!({"uri": "at://post", "cid": "cid"})),
+            _ => Response::json(400, json!({"error": "ExpiredToken"})),
+        });
+        let config = test_config(&temp_dir("session-expired"), &server.url);
+        let image = BirdImage { url_download: format!("{}/photo.jpg", server.url), ..photo(None) };
+        let mut publisher = BlueskyPublisher::new();
+        // A token whose `exp` claim is long past
+        let stale = Token { token: "e30.eyJleHAiOjF9.c2ln".to_string(), refresh_jwt: "refresh".to_string(), did: "did:plc:test".to_string() };
+        publisher.session.set(stale, SystemTime::now());
+
+        assert!(publisher.publish(&config, &bird("a"), &[image]).unwrap().is_some());
+        assert_eq!(server.hits("/xrpc/com.atproto.server.refreshSession"), 1);
+        assert_eq!(server.hits("/xrpc/com.atproto.server.createSession"), 0);
+        // Nothing was sent with the expired token first
+        assert!(server.requests().iter().all(|r| r.header("Authorization") != Some("Bearer e30.eyJleHAiOjF9.c2ln")));
+    }
+
+    #[test]
+    fn uploaded_photo_is_reused_across_attempts() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use test_server::Response;
+
+        let record_failures = AtomicUsize::new(2);
+        let server = bluesky_server(move |r| match r.path.as_str() {
+            "/xrpc/com.atproto.repo.createRecord" if record_failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() =>
+                Some(Response::json(502, json!({"error": "BadGateway"}))),
+            _ => None,
+        });
+
+        let dir = temp_dir("blob-reuse");
+        fs::write(dir.join("birds.json"), json!([bird_json("a")]).to_string()).unwrap();
+        let config = test_config(&dir, &server.url);
+        let policy = RetryPolicy { max_attempts: 3, base_delay: Duration::ZERO, multiplier: 2.0, max_delay: Duration::ZERO, jitter: false };
+
+        let report = run_with_retry(&config, &policy, &mut config.publishers()).unwrap();
+        assert_eq!(report.uri("Bluesky"), Some("at://post"));
+        assert_eq!((report.bird.species_code.as_str(), report.uri("Mastodon")), ("a", None));
+        assert_eq!(report.stages.iter().map(|s| (s.name.as_str(), s.attempts)).collect::<Vec<_>>(), [("pick", 1), ("Bluesky", 3)]);
+        assert_eq!(server.hits("/xrpc/com.atproto.repo.createRecord"), 3);
+        assert_eq!(server.hits("/photo.jpg"), 1);
+        assert_eq!(server.hits("/xrpc/com.atproto.repo.uploadBlob"), 1);
+    }
+
+    #[test]
+    fn rerolled_bird_is_not_picked_again() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/species/a" | "/species/b" => Response::new(200, species_page(r.header("Host").unwrap())),
+            _ => Response::new(404, ""),
+        });
+        let dir = temp_dir("reroll");
+        fs::write(dir.join("birds.json"), json!([bird_json("a"), bird_json("b")]).to_string()).unwrap();
+        let config = test_config(&dir, &server.url);
+
+        let mut asked = Vec::new();
+        let report = run_with_approval(&config, &RetryPolicy::default(), &mut [], |b, _| {
+            asked.push(b.species_code.clone());
+            if asked.len() == 1 { Approval::Reroll } else { Approval::Post }
+        }).unwrap();
+        assert_eq!(asked.len(), 2);
+        assert_ne!(asked[0], asked[1]);
+        assert_eq!(report.bird.species_code, asked[1]);
+
+        let result = run_with_approval(&config, &RetryPolicy::default(), &mut [], |_, _| Approval::Reroll);
+        assert!(matches!(result, Err(BirdError::NoCandidates(_))));
+        let result = run_with_approval(&config, &RetryPolicy::default(), &mut [], |_, _| Approval::Decline);
+        assert!(matches!(result, Err(BirdError::Declined)));
+        assert!(History::load(&config.history_path).unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn a_failing_platform_does_not_stop_the_others() {
+        use test_server::Response;
+
+        let server = bluesky_server(|r| match r.path.as_str() {
+            "/api/v2/media" => Some(Response::json(422, json!({"error": "Validation failed"}))),
+            _ => None,
+        });
+
+        let dir = temp_dir("publishers");
+        fs::write(dir.join("birds.json"), json!([bird_json("a")]).to_string()).unwrap();
+        let config = Config {
+            mastodon_url: Some(server.url.clone()),
+            mastodon_token: Some("token".to_string()),
+            ..test_config(&dir, &server.url)
+        };
+
+        let published = run_with_config(&config, &mut config.publishers()).unwrap().published;
+        assert_eq!(published.iter().map(|p| p.platform.as_str()).collect::<Vec<_>>(), ["Bluesky", "Mastodon"]);
+        assert!(published[0].result.is_ok());
+        assert!(matches!(published[1].result, Err(BirdError::Mastodon { status: Some(422), .. })));
+        assert_eq!(History::load(&config.history_path).unwrap().entries.len(), 1);
+    }
+
+    #[test]
+    fn post_is_logged_even_without_a_cid() {
+        use test_server::Response;
+
+        let server = bluesky_server(|r| match r.path.as_str() {
+            "/xrpc/com.atproto.repo.createRecord" => Some(Response::json(200, json!({"uri": "at://post"}))),
+            _ => None,
+        });
+
+        let dir = temp_dir("post-log");
+        fs::write(dir.join("birds.json"), json!([bird_json("a")]).to_string()).unwrap();
+        let config = test_config(&dir, &server.url);
+
+        let published = run_with_config(&config, &mut config.publishers()).unwrap().published;
+        let post = published[0].result.as_ref().unwrap().as_ref().unwrap();
+        assert_eq!(post, &PostResult { uri: Some("at://post".to_string()), cid: None });
+        assert_eq!(History::load(&config.history_path).unwrap().entries.len(), 1);
+
+        let log = fs::read_to_string(&config.post_log_path).unwrap();
+        let entry: PostLogEntry = serde_json::from_str(log.trim_end()).unwrap();
+        assert_eq!((entry.platform.as_str(), entry.species_code.as_str()), ("Bluesky", "a"));
+        assert_eq!(entry.image_source, "https://macaulaylibrary.org/asset/1");
+        assert_eq!((entry.uri, entry.cid), (Some("at://post".to_string()), None));
+    }
+
+    #[test]
+    fn posted_bird_can_be_deleted_and_picked_again() {
+        use test_server::Response;
+
+        const URI: &str = "at://did:plc:test/app.bsky.feed.post/3kabc";
+        let server = bluesky_server(|r| match r.path.as_str() {
+            "/xrpc/com.atproto.repo.createRecord" => Some(Response::json(200, json!({"uri": URI, "cid": "cid"}))),
+            "/xrpc/com.atproto.repo.deleteRecord" => Some(Response::json(200, json!({}))),
+            _ => None,
+        });
+
+        let dir = temp_dir("delete");
+        fs::write(dir.join("birds.json"), json!([bird_json("a")]).to_string()).unwrap();
+        let config = test_config(&dir, &server.url);
+
+        run_with_config(&config, &mut config.publishers()).unwrap();
+        let history = History::load(&config.history_path).unwrap();
+        assert_eq!(history.last_deletable().unwrap().uri.as_deref(), Some(URI));
+
+        assert!(delete_post(&config, URI).unwrap());
+        let request = server.requests().into_iter().find(|r| r.path == "/xrpc/com.atproto.repo.deleteRecord").unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&request.body).unwrap(), json!({
+            "repo": "did:plc:test", "collection": "app.bsky.feed.post", "rkey": "3kabc",
+        }));
+        assert!(History::load(&config.history_path).unwrap().recent(Lookback::Posts(30), OffsetDateTime::now_utc()).is_empty());
+
+        assert!(matches!(delete_post(&config, "at://did:plc:test/app.bsky.feed.post"), Err(BirdError::Config(_))));
+    }
+
+    #[test]
+    fn dry_run_stops_before_uploading() {
+
+        let server = bluesky_server(|_| None);
+
+        let dir = temp_dir("dry-run");
+        fs::write(dir.join("birds.json"), json!([bird_json("a")]).to_string()).unwrap();
+        let config = Config { dry_run: true, ..test_config(&dir, &server.url) };
+
+        let published = run_with_config(&config, &mut config.publishers()).unwrap().published;
+        assert!(matches!(published[0].result, Ok(None)));
+        assert_eq!(server.hits("/photo.jpg"), 1);
+        assert_eq!(server.hits("/xrpc/com.atproto.repo.uploadBlob"), 0);
+        assert_eq!(server.hits("/xrpc/com.atproto.repo.createRecord"), 0);
+        assert!(History::load(&config.history_path).unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn oversized_photo_fails_without_uploading() {
+        use test_server::Response;
+
+        let server = bluesky_server(|r| match r.path.as_str() {
+            "/photo.jpg" => Some(Response::new(200, "x".repeat(MAX_BLOB_BYTES + 1))),
+            _ => None,
+        });
+
+        let dir = temp_dir("oversized");
+        fs::write(dir.join("birds.json"), json!([bird_json("a")]).to_string()).unwrap();
+        let config = Config { dry_run: true, ..test_config(&dir, &server.url) };
+
+        let published = run_with_config(&config, &mut config.publishers()).unwrap().published;
+        let e = published[0].result.as_ref().unwrap_err();
+        assert!(matches!(e, BirdError::Post { status: None, .. }) && !e.is_retryable(), "{:?}", e);
+        assert!(e.to_string().contains(&format!("/photo.jpg is {} bytes, over Bluesky's limit", MAX_BLOB_BYTES + 1)), "{}", e);
+        assert_eq!(server.hits("/xrpc/com.atproto.repo.uploadBlob"), 0);
+    }
+
+    #[cfg(feature = "resize-photos")]
+    #[test]
+    fn oversized_photo_is_shrunk_to_fit() {
+        use rand::RngCore;
+
+        // Noise doesn't compress, so this PNG is well over the limit
+        let mut noise = vec![0; 300 * 200 * 3];
+        StdRng::seed_from_u64(1).fill_bytes(&mut noise);
+        let mut png = Vec::new();
+        image::RgbImage::from_raw(300, 200, noise).unwrap()
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let config = Config { max_photo_bytes: 20_000, ..Config::default() };
+        let image = BirdImage { photo_type: "image/png".to_string(), ..photo(None) };
+        assert!(png.len() > 100_000);
+
+        let (jpeg, photo_type) = for_bluesky(&config, &image, png.clone());
+        assert_eq!(photo_type, "image/jpeg");
+        assert!(jpeg.len() <= 20_000, "{} bytes", jpeg.len());
+        let shrunk = image::load_from_memory(&jpeg).unwrap();
+        assert!(shrunk.width() < 300);
+        assert!((shrunk.width() as f64 / shrunk.height() as f64 - 1.5).abs() < 0.02, "{}x{}", shrunk.width(), shrunk.height());
+
+        let e = shrink_photo(&image.url_download, &png, 10).unwrap_err();
+        assert!(e.starts_with(&format!("from 300x200 ({} bytes), the smallest tried, ", png.len())), "{}", e);
+        assert!(e.ends_with("bytes, over 10"), "{}", e);
+
+        // Small enough already, or not a photo at all, and it goes up as it is
+        assert_eq!(for_bluesky(&Config::default(), &image, png.clone()), (png, "image/png"));
+        let garbage = vec![b'x'; 30_000];
+        assert_eq!(for_bluesky(&config, &image, garbage.clone()), (garbage, "image/png"));
+    }
+
+    #[test]
+    fn photo_type_is_sniffed_from_the_bytes() {
+        assert_eq!(sniff_photo_type(b"\xFF\xD8\xFF\xDB..."), Some("image/jpeg"));
+        assert_eq!(sniff_photo_type(b"\x89PNG\r\n\x1A\n..."), Some("image/png"));
+        assert_eq!(sniff_photo_type(b"RIFF\x10\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_photo_type(b"GIF89a..."), Some("image/gif"));
+        assert_eq!(sniff_photo_type(b"RIFF\x10\0\0\0WAVE"), None);
+        assert_eq!(sniff_photo_type(b"<html>"), None);
+
+        let png = BirdImage { photo_type: "image/png".to_string(), ..photo(None) };
+        assert_eq!(photo_type(&png, b"\xFF\xD8\xFF\xE0"), "image/jpeg");
+        assert_eq!(photo_type(&png, b"\x89PNG\r\n\x1A\n"), "image/png");
+        assert_eq!(photo_type(&png, b"not an image"), "image/png");
+    }
+
+    #[cfg(feature = "webp-to-jpeg")]
+    #[test]
+    fn webp_is_uploaded_as_jpeg() {
+        // A 2x2 lossless WebP, half of it see-through
+        let mut webp = Vec::new();
+        let pixels = image::RgbaImage::from_fn(2, 2, |x, _| image::Rgba([200, 30, 30, if x == 0 { 255 } else { 0 }]));
+        image::codecs::webp::WebPEncoder::new_lossless(&mut webp).encode(&pixels, 2, 2, image::ExtendedColorType::Rgba8).unwrap();
+        let image = BirdImage { photo_type: "image/webp".to_string(), ..photo(None) };
+
+        let (jpeg, photo_type) = for_bluesky(&Config::default(), &image, webp.clone());
+        assert_eq!(photo_type, "image/jpeg");
+        assert_eq!(sniff_photo_type(&jpeg), Some("image/jpeg"));
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (2, 2));
+
+        // Not really a WebP after all, so it goes up as it is
+        let broken = b"RIFF\x10\0\0\0WEBPVP8 broken".to_vec();
+        assert_eq!(for_bluesky(&Config::default(), &image, broken.clone()), (broken, "image/webp"));
+    }
+
+    #[test]
+    fn image_is_downloaded_and_saved_by_species() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/photo.png" => Response::new(200, "png bytes"),
+            _ => Response::new(404, ""),
+        });
+        let dir = temp_dir("save-image");
+        let config = test_config(&dir, &server.url);
+        let image = BirdImage { photo_type: "image/png".to_string(), url_download: format!("{}/photo.png", server.url), ..photo(None) };
+
+        assert_eq!(download_image(&config, &image).unwrap(), b"png bytes");
+        let (path, bytes) = save_image(&config, &bird("a"), &image, 1).unwrap();
+        assert_eq!(path, dir.join("a-2.png"));
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        let missing = BirdImage { url_download: format!("{}/gone.jpg", server.url), ..photo(None) };
+        assert!(matches!(download_image(&config, &missing), Err(BirdError::Ebird { status: 404 })));
+    }
+
+    #[test]
+    fn overlong_post_text_is_rejected() {
+        let mut b = bird("a");
+        b.common_name = "Long".repeat(100);
+        assert!(matches!(validate_post(&Config::default(), &b, &[photo(None)], &[10]), Err(BirdError::Post { status: None, .. })));
+        assert!(validate_post(&Config::default(), &bird("a"), &[photo(None)], &[MAX_BLOB_BYTES]).is_ok());
+    }
+
+    #[test]
+    fn expired_token_is_refreshed_without_reuploading() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use test_server::Response;
+
+        let expired = AtomicBool::new(true);
+        let server = bluesky_server(move |r| match r.path.as_str() {
+            "/xrpc/com.atproto.server.refreshSession" if r.header("Authorization") == Some("Bearer refresh") =>
+                Some(Response::json(200, json!({"accessJwt": "new", "refreshJwt": "refresh2", "did": "did:plc:test"}))),
+            "/xrpc/com.atproto.repo.createRecord" if expired.swap(false, Ordering::SeqCst) =>
+                Some(Response::json(401, json!({"error": "ExpiredToken", "message": "Token has expired"}))),
+            _ => None,
+        });
+        let config = test_config(&temp_dir("expired-token"), &server.url);
+        let photo = BirdImage {
+            photo_type: "image/jpeg".to_string(),
+            url_download: format!("{}/photo.jpg", server.url),
+            url_source: "https://macaulaylibrary.org/asset/1".to_string(),
+            alt_text: "A bird".to_string(),
+            audio_url: None,
+            photographer: None,
+            asset_id: Some(1),
+            license: None,
+            range_map: false,
+        };
+        let mut token = Token { token: "old".to_string(), refresh_jwt: "refresh".to_string(), did: "did:plc:test".to_string() };
+
+        let result = post(&config, &bird("a"), &[photo], &mut token, &mut None).unwrap().unwrap();
+        assert_eq!(result.uri.as_deref(), Some("at://post"));
+        assert_eq!(token.token, "new");
+        assert_eq!(token.refresh_jwt, "refresh2");
+        assert_eq!(server.hits("/photo.jpg"), 1);
+        assert_eq!(server.hits("/xrpc/com.atproto.repo.uploadBlob"), 1);
+        assert_eq!(server.hits("/xrpc/com.atproto.server.refreshSession"), 1);
+        assert_eq!(server.hits("/xrpc/com.atproto.server.createSession"), 0);
+        assert_eq!(server.hits("/xrpc/com.atproto.repo.createRecord"), 2);
+    }
+
+    fn photo(audio_url: Option<&str>) -> BirdImage {
+        BirdImage {
+            photo_type: "image/jpeg".to_string(),
+            url_download: "https://cdn.example/photo.jpg".to_string(),
+            url_source: "https://macaulaylibrary.org/asset/1".to_string(),
+            alt_text: "A bird".to_string(),
+            audio_url: audio_url.map(str::to_string),
+            photographer: None,
+            asset_id: Some(1),
+            license: None,
+            range_map: false,
+        }
+    }
+
+    #[test]
+    fn facet_offsets_are_utf8_byte_offsets() {
+        let mut b = bird("a");
+        b.common_name = "Réunion Harrier".to_string();
+        let post = build_post(&Template::default(), &b, &[photo(None)], &[json!("blob")], "did", None);
+        let text = post["record"]["text"].as_str().unwrap();
+        let index = &post["record"]["facets"][0]["index"];
+        let (start, end) = (index["byteStart"].as_u64().unwrap() as usize, index["byteEnd"].as_u64().unwrap() as usize);
+        assert_eq!(&text.as_bytes()[start..end], b"Image Credit");
+        assert_eq!(start, text.len() - "Image Credit".len());
+    }
+
+    #[test]
+    fn credit_facet_follows_the_template() {
+        let mut b = bird("a");
+        b.family_com_name = Some("Hawks, Eagles, and Kites".to_string());
+        let template: Template = r"{common} ({sci}) — {family}\n\n{credit} · ebird.org/species/{species_code}".parse().unwrap();
+        let post = build_post(&template, &b, &[photo(None)], &[json!("blob")], "did", None);
+        let text = post["record"]["text"].as_str().unwrap();
+        assert!(text.starts_with(&format!("{} ({}) — Hawks, Eagles, and Kites\n\nImage Credit · ebird.org/species/a", b.common_name, b.scientific_name)), "{}", text);
+        let index = &post["record"]["facets"][0]["index"];
+        let (start, end) = (index["byteStart"].as_u64().unwrap() as usize, index["byteEnd"].as_u64().unwrap() as usize);
+        assert_eq!(&text.as_bytes()[start..end], b"Image Credit");
+    }
+
+    #[test]
+    fn order_becomes_a_tag_facet() {
+        let mut b = bird("a");
+        b.common_name = "Réunion Harrier".to_string();
+        b.order = Some("Accipitriformes".to_string());
+        let post = build_post(&Template::default(), &b, &[photo(None)], &[json!("blob")], "did", None);
+        let text = post["record"]["text"].as_str().unwrap();
+        assert!(text.ends_with("\n\n#Accipitriformes"), "{}", text);
+
+        let facets = post["record"]["facets"].as_array().unwrap();
+        assert_eq!(facets.len(), 2);
+        let index = &facets[1]["index"];
+        let (start, end) = (index["byteStart"].as_u64().unwrap() as usize, index["byteEnd"].as_u64().unwrap() as usize);
+        assert_eq!(&text.as_bytes()[start..end], b"#Accipitriformes");
+        assert_eq!(end, text.len());
+        assert_eq!(facets[1]["features"][0], json!({"$type": "app.bsky.richtext.facet#tag", "tag": "Accipitriformes"}));
+    }
+
+    #[test]
+    fn tags_leave_out_spaces_and_punctuation() {
+        let mut b = bird("a");
+        assert_eq!(hashtag(&b), None);
+        b.order = Some("Pelecani-formes (sensu lato)".to_string());
+        assert_eq!(hashtag(&b).as_deref(), Some("Pelecaniformessensulato"));
+        b.order = Some(" - ".to_string());
+        assert_eq!(hashtag(&b), None);
+    }
+
+    #[test]
+    fn empty_alt_text_is_built_from_the_taxonomy() {
+        let mut b = bird("a");
+        b.family_com_name = Some("Cardinals and Allies".to_string());
+        assert_eq!(build_alt_text(&b, " "), "Photo of a Bird a (Avis a), in the Cardinals and Allies family.");
+        assert_eq!(build_alt_text(&b, "Bird a"), "Bird a. Photo of a Bird a (Avis a), in the Cardinals and Allies family.");
+        assert_eq!(build_alt_text(&bird("a"), ""), "Photo of a Bird a (Avis a).");
+    }
+
+    #[test]
+    fn long_alt_text_is_kept_as_is() {
+        let scraped = "Bird a - Adult male perched on a snowy branch";
+        assert_eq!(build_alt_text(&bird("a"), scraped), scraped);
+    }
+
+    /// `species_page` with a gallery linking to its own photo and two more
+    fn species_page_with_gallery(host: &str) -> String {
+        species_page(host).replace("</head></html>", &format!(r#"</head><body>
+            <a href="https://macaulaylibrary.org/asset/1"><img src="http://{host}/photo.jpg" alt="A bird"></a>
+            <a href="https://macaulaylibrary.org/asset/2"><img src="http://{host}/photo2.png" alt="A bird in flight"></a>
+            <a href="https://macaulaylibrary.org/asset/3"><img src="http://{host}/photo3" alt=""></a>
+            </body></html>"#))
+    }
+
+    #[test]
+    fn gallery_photos_are_added_up_to_the_limit() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| Response::new(200, species_page_with_gallery(r.header("Host").unwrap())));
+        let config = |max_photos| Config { max_photos, ..test_config(&temp_dir("gallery"), &server.url) };
+
+        assert_eq!(get_bird_photos(&config(1), &bird("a")).unwrap().len(), 1);
+        let images = get_bird_photos(&config(4), &bird("a")).unwrap();
+        let sources: Vec<&str> = images.iter().map(|i| i.url_source.as_str()).collect();
+        assert_eq!(sources, ["https://macaulaylibrary.org/asset/1", "https://macaulaylibrary.org/asset/2", "https://macaulaylibrary.org/asset/3"]);
+        assert_eq!(images[1].alt_text, "A bird in flight");
+        assert_eq!(images[1].photo_type, "image/png");
+        assert_eq!(images[2].photo_type, "image/jpeg");
+        assert_eq!(get_bird_photos(&config(2), &bird("a")).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn range_map_takes_the_place_of_the_last_photo() {
+        let page = species_page_with_gallery("cdn.example").replace("</body>", r#"
+            <a href="https://macaulaylibrary.org/asset/4"><img src="http://cdn.example/photo4.jpg" alt=""></a>
+            <img src="https://cdn.example/rangemap/norcar.png" alt="">
+            </body>"#);
+        let config = |include_range_map, max_photos| Config { include_range_map, max_photos, ..Config::default() };
+        assert_eq!(parse_range_map(&page).as_deref(), Some("https://cdn.example/rangemap/norcar.png"));
+        assert_eq!(parse_range_map(&species_page("cdn.example")), None);
+
+        let images = images_from_page(&config(false, MAX_PHOTOS), &bird("a"), &page).unwrap();
+        assert_eq!(images.len(), MAX_PHOTOS);
+        assert!(images.iter().all(|i| !i.range_map));
+
+        let images = images_from_page(&config(true, MAX_PHOTOS), &bird("a"), &page).unwrap();
+        assert_eq!(images.len(), MAX_PHOTOS);
+        let map = images.last().unwrap();
+        assert!(map.range_map);
+        assert_eq!(map.photo_type, "image/png");
+        assert_eq!(map.url_source, format!("{}/map/a", Config::default().ebird_url));
+        assert_eq!(image_alt_text(&bird("a"), map), format!("Range map of {}", bird("a").common_name));
+
+        assert_eq!(images_from_page(&config(true, 1), &bird("a"), &page).unwrap().len(), 2);
+        assert_eq!(images_from_page(&config(true, 1), &bird("a"), &species_page("cdn.example")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn range_map_is_linked_apart_from_the_photo_credits() {
+        let map = BirdImage { url_source: "https://ebird.org/map/a".to_string(), range_map: true, ..photo(None) };
+        let post = build_post(&Template::default(), &bird("a"), &[photo(None), map], &[json!("blob"), json!("map")], "did", None);
+        let text = post["record"]["text"].as_str().unwrap();
+        assert!(text.contains("Image Credit\nRange map: eBird"), "{}", text);
+
+        let facets = post["record"]["facets"].as_array().unwrap();
+        let index = &facets[1]["index"];
+        let (start, end) = (index["byteStart"].as_u64().unwrap() as usize, index["byteEnd"].as_u64().unwrap() as usize);
+        assert_eq!(&text.as_bytes()[start..end], b"eBird");
+        assert_eq!(facets[1]["features"][0]["uri"], "https://ebird.org/map/a");
+    }
+
+    #[test]
+    fn photographer_is_scraped_from_the_species_page() {
+        let images = parse_bird_images(include_str!("../tests/fixtures/species_page.html"), 1).unwrap();
+        assert_eq!(images[0].photographer.as_deref(), Some("Jane Doe"));
+
+        assert_eq!(parse_credit("© Jane Doe; Macaulay Library"), Some("Jane Doe".to_string()));
+        assert_eq!(parse_credit("©  Jean-Luc Picard  Macaulay Library ML1"), Some("Jean-Luc Picard".to_string()));
+        assert_eq!(parse_credit("© / Macaulay Library"), None);
+        assert_eq!(parse_credit("Macaulay Library ML1"), None);
+        assert!(scrape_photographer(&Html::parse_document(&species_page("cdn.example"))).is_none());
+    }
+
+    #[test]
+    fn saved_species_page_is_parsed_offline() {
+        let images = parse_bird_images(include_str!("../tests/fixtures/species_page.html"), MAX_PHOTOS).unwrap();
+        assert_eq!(images.len(), 1);
+        let image = &images[0];
+        assert_eq!(image.url_download, "https://cdn.download.ams.birds.cornell.edu/api/v1/asset/123456789/1200");
+        assert_eq!(image.url_source, "https://macaulaylibrary.org/asset/123456789");
+        assert_eq!(image.alt_text, "Bright red bird with a crest and a black mask, perched on a snowy branch");
+        assert_eq!(image.photo_type, "image/jpeg");
+        assert_eq!(image.audio_url, None);
+        assert_eq!(image.asset_id, Some(123456789));
+        assert_eq!(image.license, None);
+
+        let page = include_str!("../tests/fixtures/species_page.html").replace("og:url\"", "og:link\"");
+        assert!(matches!(parse_bird_images(&page, 1), Err(BirdError::Scrape(m)) if m.contains("og:url")));
+    }
+
+    #[test]
+    fn asset_ids_are_parsed_from_old_and_new_urls() {
+        assert_eq!(parse_asset_id("https://macaulaylibrary.org/asset/123456789"), Some(123456789));
+        assert_eq!(parse_asset_id("https://macaulaylibrary.org/asset/ML42"), Some(42));
+        assert_eq!(parse_asset_id("https://search.macaulaylibrary.org/catalog?taxonCode=norcar&assetId=42"), Some(42));
+        assert_eq!(parse_asset_id("https://www.macaulaylibrary.org/photo/42"), Some(42));
+        assert_eq!(parse_asset_id("https://cdn.download.ams.birds.cornell.edu/api/v1/asset/42/1200"), Some(42));
+        assert_eq!(parse_asset_id("https://download.ams.birds.cornell.edu/api/v1/asset/42/large"), Some(42));
+        assert_eq!(parse_asset_id("https://cdn.example/photo.jpg"), None);
+        assert_eq!(parse_asset_id("https://ebird.org/map/norcar"), None);
+        assert_eq!(parse_asset_id("https://cdn.example/photo/2024/x.jpg"), None);
+        assert_eq!(parse_asset_id("https://example.org/macaulaylibrary.org/asset/42"), None);
+
+        let page = include_str!("../tests/fixtures/species_page.html")
+            .replace("https://macaulaylibrary.org/asset/123456789\">", "https://example.org/credit\">")
+            .replace("</main>", r#"</main><footer><a rel="license" href="https://ebird.org/terms">Terms</a></footer>"#);
+        let image = &parse_bird_images(&page, 1).unwrap()[0];
+        assert_eq!(image.asset_id, Some(123456789), "taken from the download URL when the page has none");
+        assert_eq!(image.license, None, "the footer's license is the site's, not the photo's");
+
+        let page = page.replace("</div>\n\t\t\t\t</figcaption>",
+            r#"<a rel="license" href="https://creativecommons.org/licenses/by-nc/4.0/">CC BY-NC</a></div></figcaption>"#);
+        let image = &parse_bird_images(&page, 1).unwrap()[0];
+        assert_eq!(image.license.as_deref(), Some("https://creativecommons.org/licenses/by-nc/4.0/"));
     }
 
-    println!("Success!!!!");
-    return true;
+    #[test]
+    fn twitter_card_stands_in_for_missing_open_graph_tags() {
+        let page = r#"<html><head>
+            <meta name="twitter:image" content="https://cdn.example/asset/1.png">
+            <meta name="twitter:image:alt" content="A bird">
+            <meta property="og:url" content="https://macaulaylibrary.org/asset/1">
+            </head></html>"#;
+        let image = &parse_bird_images(page, 1).unwrap()[0];
+        assert_eq!(image.url_download, "https://cdn.example/asset/1.png");
+        assert_eq!(image.alt_text, "A bird");
+        assert_eq!(image.photo_type, "image/png");
+    }
+
+    #[test]
+    fn page_without_any_expected_tags_is_a_layout_change() {
+        assert!(matches!(parse_bird_images(NO_PHOTO, 1), Err(BirdError::Scrape(_))));
+
+        let head = format!("<title>eBird</title>{}", r#"<meta name="description" content="Birds">"#.repeat(20));
+        let e = parse_bird_images(&format!("<html><head>{}</head></html>", head), 1).unwrap_err();
+        let BirdError::LayoutChanged(snippet) = &e else { panic!("{:?}", e) };
+        assert!(snippet.starts_with("<title>eBird</title><meta "), "{}", snippet);
+        assert_eq!(snippet.chars().count(), HEAD_SNIPPET + 3);
+        assert!(!e.is_retryable());
+    }
+
+    #[test]
+    fn photographer_name_is_the_credit_link() {
+        let credited = BirdImage { photographer: Some("Jane Doe".to_string()), ..photo(None) };
+        let post = build_post(&Template::default(), &bird("a"), &[credited], &[json!("blob")], "did", None);
+        let text = post["record"]["text"].as_str().unwrap();
+        assert!(text.ends_with("Photo © Jane Doe / Macaulay Library"), "{}", text);
+        let index = &post["record"]["facets"][0]["index"];
+        let (start, end) = (index["byteStart"].as_u64().unwrap() as usize, index["byteEnd"].as_u64().unwrap() as usize);
+        assert_eq!(&text[start..end], "Jane Doe");
+
+        // Numbers rather than a mix of names and anonymous credits
+        let mixed = [BirdImage { photographer: Some("Jane Doe".to_string()), ..photo(None) }, photo(None)];
+        assert!(post_text(&Template::default(), &bird("a"), &mixed).ends_with("Image Credits: 1, 2"));
+    }
+
+    #[test]
+    fn every_photo_is_credited_and_embedded() {
+        let mut second = photo(None);
+        second.url_source = "https://macaulaylibrary.org/asset/2".to_string();
+        second.alt_text = String::new();
+        let post = build_post(&Template::default(), &bird("a"), &[photo(None), second], &[json!("blob1"), json!("blob2")], "did", None);
+
+        let text = post["record"]["text"].as_str().unwrap();
+        assert!(text.ends_with("Image Credits: 1, 2"), "{}", text);
+        let facets = post["record"]["facets"].as_array().unwrap();
+        for (facet, (n, uri)) in facets.iter().zip([("1", "https://macaulaylibrary.org/asset/1"), ("2", "https://macaulaylibrary.org/asset/2")]) {
+            let (start, end) = (facet["index"]["byteStart"].as_u64().unwrap() as usize, facet["index"]["byteEnd"].as_u64().unwrap() as usize);
+            assert_eq!(&text[start..end], n);
+            assert_eq!(facet["features"][0]["uri"], uri);
+        }
+
+        let images = post["record"]["embed"]["images"].as_array().unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0]["alt"], "A bird. Photo of a Bird a (Avis a).");
+        assert_eq!(images[1]["image"], "blob2");
+        assert_eq!(images[1]["alt"], "Photo of a Bird a (Avis a).");
+    }
+
+    #[test]
+    fn several_photos_are_uploaded_to_one_post() {
+        use test_server::Response;
+
+        let server = bluesky_server(|r| match r.path.as_str() {
+            "/species/a" => Some(Response::new(200, species_page_with_gallery(r.header("Host").unwrap()))),
+            "/photo.jpg" => Some(Response::new(200, "image bytes")),
+            // Really a JPEG, whatever the species page says
+            "/photo2.png" => Some(Response::new(200, b"\xFF\xD8\xFF\xE0 jpeg bytes".as_slice())),
+            _ => None,
+        });
+
+        let dir = temp_dir("several-photos");
+        fs::write(dir.join("birds.json"), json!([bird_json("a")]).to_string()).unwrap();
+        let config = Config { max_photos: 2, ..test_config(&dir, &server.url) };
+
+        let published = run_with_config(&config, &mut config.publishers()).unwrap().published;
+        assert!(published[0].result.is_ok());
+        let uploads: Vec<_> = server.requests().into_iter().filter(|r| r.path == "/xrpc/com.atproto.repo.uploadBlob").collect();
+        assert_eq!(uploads.iter().map(|r| r.header("Content-Type").unwrap()).collect::<Vec<_>>(), ["image/jpeg", "image/jpeg"]);
+        let record = server.requests().into_iter().find(|r| r.path == "/xrpc/com.atproto.repo.createRecord").unwrap();
+        let record: Value = serde_json::from_slice(&record.body).unwrap();
+        assert_eq!(record["record"]["embed"]["images"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn audio_is_linked_with_the_photo_as_thumbnail() {
+        let server = bluesky_server(|_| None);
+        let config = Config { include_audio: true, ..test_config(&temp_dir("audio"), &server.url) };
+        let first = BirdImage { url_download: format!("{}/photo.jpg", server.url), ..photo(Some("https://cdn.example/song.mp3")) };
+        let second = BirdImage { url_source: "https://macaulaylibrary.org/asset/2".to_string(), ..first.clone() };
+        let map = BirdImage { url_source: "https://ebird.org/map/a".to_string(), range_map: true, ..first.clone() };
+        let mut token = Token { token: "jwt".to_string(), refresh_jwt: "refresh".to_string(), did: "did:plc:test".to_string() };
+
+        post(&config, &bird("a"), &[first, second, map], &mut token, &mut None).unwrap().unwrap();
+        // Only the thumbnail shows, so the other photo and the range map are neither uploaded nor credited
+        assert_eq!(server.hits("/xrpc/com.atproto.repo.uploadBlob"), 1);
+        let record = server.requests().into_iter().find(|r| r.path == "/xrpc/com.atproto.repo.createRecord").unwrap();
+        let record: Value = serde_json::from_slice(&record.body).unwrap();
+        let embed = &record["record"]["embed"];
+        assert_eq!(embed["$type"], "app.bsky.embed.external");
+        assert_eq!(embed["external"]["uri"], "https://cdn.example/song.mp3");
+        assert_eq!(embed["external"]["thumb"], json!({"$type": "blob"}));
+        let text = record["record"]["text"].as_str().unwrap();
+        assert!(text.contains("Image Credit") && !text.contains("Credits") && !text.contains("Range map"), "{}", text);
+        assert_eq!(record["record"]["facets"][0]["features"][0]["uri"], "https://macaulaylibrary.org/asset/1");
+    }
+
+    #[test]
+    fn posts_without_a_recording_keep_the_photo() {
+        for (audio, include_audio) in [(None, true), (Some("https://cdn.example/song.mp3"), false)] {
+            let config = Config { include_audio, ..Config::default() };
+            let photo = photo(audio);
+            let post = build_post(&Template::default(), &bird("a"), std::slice::from_ref(&photo), &[json!("blob")], "did", audio_embed(&config, &photo));
+            assert_eq!(post["record"]["embed"]["$type"], "app.bsky.embed.images");
+            assert_eq!(post["record"]["embed"]["images"][0]["alt"], "A bird. Photo of a Bird a (Avis a).");
+        }
+    }
+
+    #[test]
+    fn region_species_are_cached() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/v2/product/spplist/US-NY" => Response::json(200, json!(["a", "b"])),
+            _ => Response::new(400, ""),
+        });
+        let config = test_config(&temp_dir("region"), &server.url);
+
+        for _ in 0..2 {
+            let codes = get_region_species(&config, "US-NY").unwrap();
+            assert_eq!(codes, HashSet::from(["a".to_string(), "b".to_string()]));
+        }
+        assert_eq!(server.hits("/v2/product/spplist/US-NY"), 1);
+        assert!(matches!(get_region_species(&config, "XX-XX"), Err(BirdError::InvalidRegion(_))));
+    }
+
+    #[test]
+    fn stale_or_empty_region_lists_are_downloaded_again() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/v2/product/spplist/US-NY" => Response::json(200, json!(["a", "b"])),
+            "/v2/product/spplist/AQ" => Response::json(200, json!([])),
+            _ => Response::new(503, ""),
+        });
+        let config = Config { region_max_age: 30, ..test_config(&temp_dir("region-stale"), &server.url) };
+        let path = config.region_path("US-NY");
+        let age = |days: u64| File::options().write(true).open(&path).unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60)).unwrap();
+
+        fs::write(&path, "[]").unwrap();
+        assert_eq!(get_region_species(&config, "US-NY").unwrap().len(), 2);
+        age(29);
+        get_region_species(&config, "US-NY").unwrap();
+        assert_eq!(server.hits("/v2/product/spplist/US-NY"), 1);
+        age(31);
+        get_region_species(&config, "US-NY").unwrap();
+        assert_eq!(server.hits("/v2/product/spplist/US-NY"), 2);
+
+        // A stale copy beats none at all, but an empty list is never kept
+        let down = Config { ebird_api_url: "http://127.0.0.1:9".to_string(), ..config.clone() };
+        age(31);
+        assert_eq!(get_region_species(&down, "US-NY").unwrap().len(), 2);
+        assert!(matches!(get_region_species(&config, "AQ"), Err(BirdError::NoCandidates(_))));
+        assert!(!config.region_path("AQ").exists());
+    }
+
+    #[test]
+    fn expired_refresh_token_falls_back_to_login() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/xrpc/com.atproto.server.refreshSession" => Response::json(400, json!({"error": "ExpiredToken"})),
+            "/xrpc/com.atproto.server.createSession" => Response::json(200, json!({"accessJwt": "new", "refreshJwt": "refresh2", "did": "did:plc:test"})),
+            _ => Response::new(404, ""),
+        });
+        let config = test_config(&temp_dir("expired-refresh"), &server.url);
+        let mut token = Token { token: "old".to_string(), refresh_jwt: "refresh".to_string(), did: "did:plc:test".to_string() };
+
+        refresh(&config, &mut token).unwrap();
+        assert_eq!(token.token, "new");
+        assert_eq!(server.hits("/xrpc/com.atproto.server.createSession"), 1);
+    }
+
+    #[test]
+    fn stale_saved_session_is_refreshed_and_saved() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match (r.path.as_str(), r.header("Authorization")) {
+            ("/xrpc/com.atproto.server.getSession", Some("Bearer fresh")) => Response::json(200, json!({"did": "did:plc:test"})),
+            ("/xrpc/com.atproto.server.getSession", _) => Response::json(400, json!({"error": "ExpiredToken"})),
+            ("/xrpc/com.atproto.server.refreshSession", Some("Bearer refresh")) =>
+                Response::json(200, json!({"accessJwt": "fresh", "refreshJwt": "refresh2", "did": "did:plc:test"})),
+            _ => Response::new(401, ""),
+        });
+        let config = test_config(&temp_dir("session"), &server.url);
+        let stale = Token { token: "stale".to_string(), refresh_jwt: "refresh".to_string(), did: "did:plc:test".to_string() };
+        session::save(&config.session_path, &config.handle, &stale).unwrap();
+
+        // Stale session gets refreshed and saved...
+        assert_eq!(get_session(&config).unwrap().token, "fresh");
+        assert_eq!(session::load(&config.session_path, &config.handle).unwrap().refresh_jwt, "refresh2");
+        // ...and is then reused as is
+        assert_eq!(get_session(&config).unwrap().token, "fresh");
+        assert_eq!(server.hits("/xrpc/com.atproto.server.refreshSession"), 1);
+        assert_eq!(server.hits("/xrpc/com.atproto.server.createSession"), 0);
+
+        // A different handle doesn't see the saved session
+        assert!(session::load(&config.session_path, "other.bsky.social").is_none());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&config.session_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn truncated_taxonomy_download_keeps_the_old_file() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|_| Response::new(200, r#"[{"sciName": "Avis a", "comN"#));
+        let config = test_config(&temp_dir("truncated"), &server.url);
+        fs::write(&config.birds_path, json!([bird_json("a")]).to_string()).unwrap();
+
+        assert!(matches!(get_all_birds(&config), Err(BirdError::Parse(_))));
+        assert_eq!(fs::read_to_string(&config.birds_path).unwrap(), json!([bird_json("a")]).to_string());
+    }
+
+    #[test]
+    fn short_rate_limits_are_waited_out() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use test_server::Response;
+
+        let limited = AtomicBool::new(true);
+        let server = bluesky_server(move |r| match r.path.as_str() {
+            "/xrpc/com.atproto.server.createSession" if limited.swap(false, Ordering::SeqCst) =>
+                Some(Response::json(429, json!({"error": "RateLimitExceeded"})).with_header("Retry-After", "1")),
+            _ => None,
+        });
+        let config = test_config(&temp_dir("rate-limit-short"), &server.url);
+
+        let start = std::time::Instant::now();
+        assert_eq!(authenticate(&config).unwrap().token, "jwt");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.hits("/xrpc/com.atproto.server.createSession"), 2);
+    }
+
+    #[test]
+    fn rejected_logins_do_not_leak_credentials() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| {
+            let sent = String::from_utf8_lossy(&r.body).into_owned();
+            Response::json(401, json!({"error": "AuthenticationRequired", "message": sent, "token": "eyJhbGciOiJFUzI1NksifQ.eyJzY29wZSI6ImFwcCJ9.c2ln"}))
+        });
+        let config = Config { password: "correct-horse-battery".to_string(), ..test_config(&temp_dir("redact"), &server.url) };
+        register_secret(&config.password);
+
+        let message = authenticate(&config).unwrap_err().to_string();
+        assert!(!message.contains("correct-horse-battery"), "{}", message);
+        assert!(!message.contains("eyJ"), "{}", message);
+        assert!(message.contains(r#""token":"***""#), "{}", message);
+    }
+
+    #[test]
+    fn two_factor_logins_are_told_apart() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|_| Response::json(401, json!({
+            "error": "AuthFactorTokenRequired",
+            "message": "A sign in code has been sent to your email address",
+        })));
+        let config = test_config(&temp_dir("two-factor"), &server.url);
+
+        match authenticate(&config) {
+            Err(e @ BirdError::Auth { reason: Some(AuthFailure::TwoFactorRequired), .. }) =>
+                assert!(e.to_string().contains("use an app password"), "{}", e),
+            other => panic!("expected a two-factor Auth error, got {:?}", other.map(|_| ())),
+        }
+        assert!(looks_like_app_password("abcd-1234-efgh-5678"));
+        assert!(!looks_like_app_password("correct-horse-battery-staple"));
+        assert!(!looks_like_app_password("hunter2"));
+    }
+
+    #[test]
+    fn long_rate_limits_are_returned() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|_| Response::json(429, json!({"error": "RateLimitExceeded"})).with_header("Retry-After", "3600"));
+        let config = test_config(&temp_dir("rate-limit-long"), &server.url);
+
+        match authenticate(&config) {
+            Err(BirdError::RateLimited { retry_after }) => assert_eq!(retry_after, Duration::from_secs(3600)),
+            other => panic!("expected RateLimited, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(server.hits("/xrpc/com.atproto.server.createSession"), 1);
+    }
+
+    #[test]
+    fn rate_limited_pages_are_fetched_again() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use test_server::{Response, TestServer};
+
+        let limited = AtomicBool::new(true);
+        let server = TestServer::start(move |r| match r.path.as_str() {
+            "/species/a" if limited.swap(false, Ordering::SeqCst) => Response::new(429, "slow down").with_header("Retry-After", "2"),
+            "/species/a" => Response::new(200, species_page(r.header("Host").unwrap())),
+            _ => Response::new(404, ""),
+        });
+        let config = test_config(&temp_dir("rate-limit-get"), &server.url);
+
+        let start = std::time::Instant::now();
+        assert_eq!(get_bird_photos(&config, &bird("a")).unwrap()[0].alt_text, "A bird");
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert_eq!(server.hits("/species/a"), 2);
+    }
+
+    #[test]
+    fn cached_pages_are_used_until_they_stop_parsing() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| Response::new(200, species_page(r.header("Host").unwrap())));
+        let config = Config { cache_pages: true, ..test_config(&temp_dir("page-cache"), &server.url) };
+
+        get_bird_photos(&config, &bird("a")).unwrap();
+        assert_eq!(get_bird_photos(&config, &bird("a")).unwrap()[0].alt_text, "A bird");
+        assert_eq!(server.hits("/species/a"), 1);
+
+        fs::write(config.page_cache_path("a"), "<html><head></head></html>").unwrap();
+        get_bird_photos(&config, &bird("a")).unwrap();
+        assert_eq!(server.hits("/species/a"), 2);
+        assert!(fs::read_to_string(config.page_cache_path("a")).unwrap().contains("og:image"));
+
+        get_bird_photos(&Config { page_cache_ttl: 0, ..config.clone() }, &bird("a")).unwrap();
+        assert_eq!(server.hits("/species/a"), 3);
+    }
+
+    #[test]
+    fn taxonomy_download_replaces_the_old_file() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|_| Response::json(200, json!([bird_json("a"), bird_json("b")])));
+        let config = test_config(&temp_dir("taxonomy"), &server.url);
+        fs::write(&config.birds_path, json!([bird_json("a")]).to_string()).unwrap();
+
+        assert_eq!(get_all_birds(&config).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&config.birds_path).unwrap(), json!([bird_json("a"), bird_json("b")]).to_string());
+        assert!(!config.birds_path.with_file_name("birds.json.tmp").exists());
+    }
+
+    #[test]
+    fn each_locale_is_downloaded_to_its_own_file() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/v2/ref/taxonomy/ebird?fmt=json&locale=es" => Response::json(200, json!([bird_json("a"), bird_json("b")])),
+            _ => Response::json(200, json!([bird_json("a")])),
+        });
+        let config = Config { locale: Some("es".to_string()), ..test_config(&temp_dir("locale"), &server.url) };
+        fs::write(&config.birds_path, json!([bird_json("a")]).to_string()).unwrap();
+
+        assert_eq!(get_all_birds(&config).unwrap(), 2);
+        assert_eq!(config.taxonomy_path(), config.birds_path.with_file_name("birds.es.json"));
+        assert_eq!(BirdCatalog::load(config.taxonomy_path()).unwrap().len(), 2);
+        assert_eq!(BirdCatalog::load(&config.birds_path).unwrap().len(), 1);
+        assert_eq!(TaxonomyInfo::load(&config.taxonomy_path()).unwrap().unwrap().locale.as_deref(), Some("es"));
+
+        // birds.json was there before the locale was kept, so it counts as English
+        assert_eq!(TaxonomyInfo::load(&config.birds_path).unwrap(), None);
+        get_all_birds(&Config { locale: None, ..config.clone() }).unwrap();
+        assert_eq!(TaxonomyInfo::load(&config.birds_path).unwrap().unwrap().locale, None);
+    }
+
+    #[test]
+    fn history_outlives_a_taxonomy_update_without_the_birds_it_lost() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|_| Response::json(200, json!([bird_json("a"), bird_json("c")])));
+        let config = test_config(&temp_dir("taxonomy-prune"), &server.url);
+        history(&["a", "b", "c"]).save(&config.history_path).unwrap();
+
+        get_all_birds(&config).unwrap();
+        let codes = |h: History| h.entries.into_iter().map(|e| e.species_code).collect::<Vec<_>>();
+        assert_eq!(codes(History::load(&config.history_path).unwrap()), ["a", "c"]);
+    }
+
+    /// Config with a taxonomy in `dir` that was last downloaded `days` ago
+    fn taxonomy_aged(dir: &Path, days: u64) -> Config {
+        let config = Config { auto_update_taxonomy: true, ..test_config(dir, "http://127.0.0.1:9") };
+        fs::write(&config.birds_path, json!([bird_json("a")]).to_string()).unwrap();
+        let modified = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+        File::options().write(true).open(&config.birds_path).unwrap().set_modified(modified).unwrap();
+        config
+    }
+
+    #[test]
+    fn fresh_taxonomy_is_not_downloaded_again() {
+        let config = taxonomy_aged(&temp_dir("fresh-taxonomy"), 89);
+        update_stale_taxonomy(&config, |_| panic!("downloaded a fresh taxonomy")).unwrap();
+    }
+
+    #[test]
+    fn stale_taxonomy_is_kept_when_the_download_fails() {
+        let config = taxonomy_aged(&temp_dir("stale-taxonomy"), 91);
+        let mut downloads = 0;
+        update_stale_taxonomy(&config, |_| {
+            downloads += 1;
+            Err(BirdError::Ebird { status: 503 })
+        }).unwrap();
+        assert_eq!(downloads, 1);
+        assert_eq!(BirdCatalog::load(&config.birds_path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn missing_taxonomy_is_downloaded_before_picking() {
+        use test_server::{Response, TestServer};
+
+        let server = TestServer::start(|_| Response::json(503, json!({})));
+        let dir = temp_dir("missing-taxonomy");
+        let config = Config { auto_update_taxonomy: true, ..test_config(&dir, &server.url) };
+        let result = run_with_config(&config, &mut config.publishers());
+        assert!(matches!(result, Err(BirdError::Ebird { status: 503 })), "{:?}", result);
+        assert_eq!(server.hits("/v2/ref/taxonomy/ebird?fmt=json"), 1);
+    }
+
+    #[test]
+    fn deadline_caps_timeouts() {
+        let config = Config { taxonomy_timeout: 120, bluesky_timeout: 30, max_rate_limit_wait: 60, ..Config::default() };
+        let limited = Deadline::after(10).limit(&config).unwrap();
+        assert!(limited.taxonomy_timeout <= 10 && limited.taxonomy_timeout >= 9);
+        assert!(limited.bluesky_timeout <= 10);
+        assert!(limited.max_rate_limit_wait <= 10);
+        let roomy = Deadline::after(300).limit(&config).unwrap();
+        assert_eq!((roomy.taxonomy_timeout, roomy.bluesky_timeout), (120, 30));
+    }
+
+    #[test]
+    fn long_rate_limits_are_not_waited_past_the_deadline() {
+        let start = Instant::now();
+        let mut attempts = 0;
+        let result: Result<(), _> = Deadline::after(2).retry(&Config::default(), &RetryPolicy { max_attempts: 3, ..RetryPolicy::default() }, |_| {
+            attempts += 1;
+            Err(BirdError::RateLimited { retry_after: Duration::from_secs(3600) })
+        });
+        assert!(matches!(result, Err(BirdError::DeadlineExceeded)), "{:?}", result);
+        assert_eq!(attempts, 1);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn passed_deadline_stops_the_run() {
+        let dir = temp_dir("deadline");
+        let config = Config { max_run_duration: 0, ..test_config(&dir, "http://127.0.0.1:9") };
+        fs::write(&config.birds_path, json!([bird_json("a")]).to_string()).unwrap();
+        assert!(matches!(run_with_config(&config, &mut config.publishers()), Err(BirdError::DeadlineExceeded)));
+    }
+
+    #[test]
+    fn overlapping_runs_are_refused() {
+        let dir = temp_dir("overlap");
+        let config = test_config(&dir, "http://127.0.0.1:9");
+        fs::write(&config.birds_path, json!([bird_json("a")]).to_string()).unwrap();
+        let _lock = RunLock::acquire(&config.lock_path, Duration::from_secs(3600)).unwrap();
+
+        assert!(matches!(run_with_config(&config, &mut config.publishers()), Err(BirdError::AlreadyRunning(_))));
+        assert!(matches!(get_all_birds(&config), Err(BirdError::AlreadyRunning(_))));
+        assert_eq!(fs::read_to_string(&config.birds_path).unwrap(), json!([bird_json("a")]).to_string());
+    }
+
+    #[test]
+    fn missing_history_file_is_empty() {
+        let h = History::load("this-file-does-not-exist.json").unwrap();
+        assert!(h.entries.is_empty());
+    }
 }
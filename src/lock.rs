@@ -0,0 +1,106 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+
+use crate::BirdError;
+
+/// An advisory lock keeping two runs of the bot from overlapping, released when dropped
+#[derive(Debug)]
+pub(crate) struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Take the lock at `path`, which holds the PID and start time of whoever has it. A lock
+    /// older than `stale_after` is assumed to have been left behind by a crash and is taken over.
+    pub(crate) fn acquire(path: &Path, stale_after: Duration) -> Result<RunLock, BirdError> {
+        match create(path) {
+            Err(BirdError::AlreadyRunning(holder)) => {
+                let age = lock_age(path).unwrap_or(Duration::ZERO);
+                if age <= stale_after {
+                    return Err(BirdError::AlreadyRunning(holder));
+                }
+                warn!("Taking over lock '{}' left behind {:?} ago by {}", path.display(), age, holder);
+                fs::remove_file(path)?;
+                create(path)
+            }
+            result => result,
+        }
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Error removing lock '{}': {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Create the lock file, failing with `AlreadyRunning` if it exists
+fn create(path: &Path) -> Result<RunLock, BirdError> {
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            let holder = fs::read_to_string(path).ok()
+                .and_then(|c| c.split_whitespace().next().map(|pid| format!("process {}", pid)))
+                .unwrap_or_else(|| "another process".to_string());
+            return Err(BirdError::AlreadyRunning(holder));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let lock = RunLock { path: path.to_path_buf() };
+    writeln!(file, "{} {}", process::id(), unix_now())?;
+    Ok(lock)
+}
+
+/// How long ago the lock at `path` was taken, from its contents or, if those are unreadable, when it was written
+fn lock_age(path: &Path) -> Option<Duration> {
+    let taken = fs::read_to_string(path).ok()
+        .and_then(|c| c.split_whitespace().nth(1).and_then(|t| t.parse().ok()))
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        .or_else(|| fs::metadata(path).and_then(|m| m.modified()).ok())?;
+    SystemTime::now().duration_since(taken).ok()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("botd-lock-{}-{}", process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn second_lock_is_refused_until_the_first_is_dropped() {
+        let path = lock_path("overlap");
+        let lock = RunLock::acquire(&path, Duration::from_secs(60)).unwrap();
+        match RunLock::acquire(&path, Duration::from_secs(60)) {
+            Err(BirdError::AlreadyRunning(holder)) => assert_eq!(holder, format!("process {}", process::id())),
+            other => panic!("expected AlreadyRunning, got {:?}", other),
+        }
+        drop(lock);
+        assert!(!path.exists());
+        assert!(RunLock::acquire(&path, Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn stale_lock_is_taken_over() {
+        let path = lock_path("stale");
+        fs::write(&path, format!("99999 {}\n", unix_now() - 7200)).unwrap();
+        let _lock = RunLock::acquire(&path, Duration::from_secs(3600)).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().starts_with(&process::id().to_string()));
+    }
+}
@@ -1,20 +1,809 @@
+use std::{collections::HashSet, io::Write, path::PathBuf, process::ExitCode, sync::mpsc, thread, time::Duration};
+
 use birdoftheday::*;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use log::{debug, error, info};
+use serde_json::{json, Value};
+use time::Date;
+
+/// Bad command line arguments exit like any other configuration error
+const USAGE_ERROR: u8 = 2;
+
+/// Post a bird of the day to Bluesky
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Directory holding birds.json, the post history and log, the saved session, and the lock file
+    /// (overrides BOTD_DATA_DIR; the default is the platform's data directory)
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+    /// Taxonomy file to pick birds from (overrides BOTD_BIRDS_PATH and --data-dir)
+    #[arg(long, global = true, value_name = "PATH")]
+    birds: Option<PathBuf>,
+    /// Only pick birds recorded in this eBird region, e.g. US-NY or CR (overrides BOTD_REGION)
+    #[arg(long, global = true, value_name = "CODE")]
+    region: Option<String>,
+    /// eBird locale for the common names, e.g. es or pt_BR, with its own copy of the taxonomy
+    /// next to birds.json (overrides BOTD_LOCALE)
+    #[arg(long, global = true, value_name = "LOCALE", value_parser = parse_locale)]
+    locale: Option<String>,
+    /// What the post says about the bird, e.g. "{common} ({sci}) — {family}\n\n{credit}", with
+    /// {common}, {sci}, {family}, {order}, {species_code}, and {credit} for the photo credits
+    /// (overrides BOTD_TEMPLATE)
+    #[arg(long, global = true, value_name = "TEMPLATE")]
+    template: Option<Template>,
+    /// Read settings from this file instead of .env in the working directory and the data
+    /// directory. Variables already set in the environment take precedence over any file, and
+    /// .env in the working directory over the one in the data directory.
+    #[arg(long, global = true, value_name = "PATH")]
+    env_file: Option<PathBuf>,
+    /// How many times to try before giving up (overrides BOTD_MAX_ATTEMPTS)
+    #[arg(long, global = true, value_name = "N", allow_negative_numbers = true, value_parser = clap::value_parser!(u32).range(1..))]
+    attempts: Option<u32>,
+    /// Seconds to wait before the second attempt, doubling from there (overrides BOTD_RETRY_BASE_DELAY)
+    #[arg(long, global = true, value_name = "SECONDS", allow_negative_numbers = true)]
+    retry_delay: Option<u64>,
+    /// Options for posting without naming the `post` subcommand
+    #[command(flatten)]
+    post: PostArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pick today's bird and post it (what happens without a subcommand)
+    Post(PostArgs),
+    /// Download the latest eBird taxonomy to birds.json
+    UpdateTaxonomy,
+    /// Pick today's bird, save its photos to the data directory, and print what would be posted,
+    /// without logging in or posting anything
+    Preview(ChoiceArgs),
+    /// Show the birds posted recently, newest first
+    History,
+    /// Check the eBird API key, the Bluesky login, and the data directory, without posting anything
+    Verify,
+    /// Find birds in the local taxonomy by common or scientific name, e.g. to get a species code
+    Search(SearchArgs),
+    /// List the families in the local taxonomy, e.g. to find a value for --family
+    ListFamilies(ListFamiliesArgs),
+    /// Count the birds in the local taxonomy, how many each filter takes out, and what is left to
+    /// pick from by order and family
+    Stats(StatsArgs),
+    /// Delete the last Bluesky post, marking it deleted in the history so the bird can come up again
+    DeleteLast(ConfirmArgs),
+    /// Delete a particular Bluesky post, e.g. one made before the last
+    Delete(DeleteArgs),
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print every setting with where it came from, and everything wrong with them, without
+    /// making any requests
+    Check,
+}
+
+#[derive(Args)]
+struct ConfirmArgs {
+    /// Don't ask before deleting
+    #[arg(long, short)]
+    yes: bool,
+}
+
+#[derive(Args)]
+struct DeleteArgs {
+    /// AT URI of the post, e.g. at://did:plc:.../app.bsky.feed.post/3k...
+    #[arg(long, value_name = "URI")]
+    uri: String,
+    #[command(flatten)]
+    confirm: ConfirmArgs,
+}
+
+#[derive(Args)]
+struct ListFamiliesArgs {
+    /// List the orders instead
+    #[arg(long)]
+    orders: bool,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    /// Print the counts as text, or as one JSON object
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    output: Option<Output>,
+}
+
+#[derive(Args)]
+struct SearchArgs {
+    /// Part of the bird's common or scientific name, in any case
+    query: String,
+    /// Only birds of this family, by code, common name, or scientific name
+    #[arg(long, value_name = "FAMILY")]
+    family: Option<String>,
+    /// Show at most this many birds
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    limit: usize,
+}
+
+#[derive(Args, Clone)]
+struct PostArgs {
+    /// Do everything except upload the photo and create the post
+    #[arg(long)]
+    dry_run: bool,
+    /// Print how long each call to eBird, the photo CDN, and Bluesky took (needs the `tracing` feature)
+    #[arg(long)]
+    timing: bool,
+    #[command(flatten)]
+    choice: ChoiceArgs,
+    /// How to report the outcome: log lines, or one JSON object on stdout with the logs kept to stderr
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    output: Option<Output>,
+    /// Show the bird and photos that were picked and ask whether to post them, pick another, or stop
+    #[arg(long)]
+    interactive: bool,
+    /// Stop without posting if --interactive gets no answer within this many seconds
+    #[arg(long, value_name = "SECONDS", requires = "interactive")]
+    prompt_timeout: Option<u64>,
+}
+
+/// Which bird to use: a particular one instead of a random one, or the families and orders to
+/// pick from
+#[derive(Args, Clone)]
+struct ChoiceArgs {
+    /// Use the bird with this eBird species code instead of a random one
+    #[arg(long, value_name = "CODE", conflicts_with = "name")]
+    species: Option<String>,
+    /// Use the bird with this common name instead of a random one
+    #[arg(long, value_name = "NAME")]
+    name: Option<String>,
+    /// Pick the bird for this day instead of today, e.g. 2024-05-04
+    #[arg(long, value_name = "YYYY-MM-DD", value_parser = parse_date)]
+    date: Option<Date>,
+    /// Seed the random pick with this number instead of the date
+    #[arg(long, value_name = "N")]
+    seed: Option<u64>,
+    /// Only pick birds of these families, comma separated, by code, common name, or scientific
+    /// name, e.g. "Hawks, Eagles, and Kites,Strigidae" (overrides BOTD_FAMILY)
+    #[arg(long, value_name = "FAMILIES")]
+    family: Option<String>,
+    /// Only pick birds of these orders, comma separated, e.g. Strigiformes,Accipitriformes (overrides BOTD_ORDER)
+    #[arg(long, value_name = "ORDERS")]
+    order: Option<String>,
+}
+
+impl ChoiceArgs {
+    /// These options, with any not given taken from `other`
+    fn or(self, other: ChoiceArgs) -> ChoiceArgs {
+        ChoiceArgs {
+            species: self.species.or(other.species),
+            name: self.name.or(other.name),
+            date: self.date.or(other.date),
+            seed: self.seed.or(other.seed),
+            family: self.family.or(other.family),
+            order: self.order.or(other.order),
+        }
+    }
+
+    /// The bird asked for, if any
+    fn bird(&self) -> Option<BirdChoice> {
+        self.species.clone().map(BirdChoice::SpeciesCode)
+            .or_else(|| self.name.clone().map(BirdChoice::CommonName))
+    }
+
+    /// Pick the bird as these options ask
+    fn apply(&self, config: &mut Config) {
+        config.only_bird = self.bird();
+        config.date = self.date;
+        config.seed = self.seed;
+        if self.family.is_some() {
+            config.only_family = self.family.clone();
+        }
+        if self.order.is_some() {
+            config.only_order = self.order.clone();
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+enum Output {
+    Text,
+    Json,
+}
+
+impl Cli {
+    /// Apply the options that override the environment for every subcommand
+    fn apply(&self, config: &mut Config, policy: &mut RetryPolicy) {
+        if let Some(dir) = &self.data_dir {
+            config.set_data_dir(dir);
+        }
+        if let Some(path) = &self.birds {
+            config.birds_path = path.clone();
+        }
+        if let Some(region) = &self.region {
+            config.region = Some(region.clone());
+        }
+        if let Some(locale) = &self.locale {
+            config.locale = Some(locale.clone());
+        }
+        if let Some(template) = &self.template {
+            config.template = template.clone();
+        }
+        if let Some(n) = self.attempts {
+            policy.max_attempts = n;
+        }
+        if let Some(secs) = self.retry_delay {
+            policy.base_delay = Duration::from_secs(secs);
+            policy.max_delay = policy.max_delay.max(policy.base_delay);
+        }
+    }
+
+    /// The option given that decides the setting of the variable `name`, if any
+    fn flag_for(&self, name: &str) -> Option<&'static str> {
+        match name {
+            "BOTD_DATA_DIR" => self.data_dir.as_ref().map(|_| "--data-dir"),
+            "BOTD_BIRDS_PATH" => self.birds.as_ref().map(|_| "--birds").or(self.data_dir.as_ref().map(|_| "--data-dir")),
+            "BOTD_REGION" => self.region.as_ref().map(|_| "--region"),
+            "BOTD_LOCALE" => self.locale.as_ref().map(|_| "--locale"),
+            "BOTD_TEMPLATE" => self.template.as_ref().map(|_| "--template"),
+            "BOTD_MAX_ATTEMPTS" => self.attempts.map(|_| "--attempts"),
+            "BOTD_RETRY_BASE_DELAY" => self.retry_delay.map(|_| "--retry-delay"),
+            _ => None,
+        }
+    }
+}
+
+/// A `--date` as `YYYY-MM-DD`
+fn parse_date(s: &str) -> Result<Date, String> {
+    let format = time::format_description::parse("[year]-[month]-[day]").unwrap();
+    Date::parse(s, &format).map_err(|e| format!("not a date as YYYY-MM-DD: {}", e))
+}
+
+/// A `--locale` that [`Config::taxonomy_path`] can use
+fn parse_locale(s: &str) -> Result<String, String> {
+    match is_locale(s) {
+        true => Ok(s.to_string()),
+        false => Err("not an eBird locale code, e.g. es or pt_BR".to_string()),
+    }
+}
+
+impl PostArgs {
+    /// These options, with any not given taken from `other`
+    fn or(self, other: PostArgs) -> PostArgs {
+        PostArgs {
+            dry_run: self.dry_run || other.dry_run,
+            timing: self.timing || other.timing,
+            choice: self.choice.or(other.choice),
+            output: self.output.or(other.output),
+            interactive: self.interactive || other.interactive,
+            prompt_timeout: self.prompt_timeout.or(other.prompt_timeout),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // Default to info so the outcome of a run is visible; RUST_LOG overrides this
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .format(|buf, record| {
+            let message = redact(&record.args().to_string());
+            writeln!(buf, "[{} {:<5} {}] {}", buf.timestamp(), record.level(), record.target(), message)
+        })
+        .init();
+
+    let mut cli = Cli::parse();
+    let command = match cli.command.take() {
+        // Options given before `post` count just the same as after it
+        Some(Command::Post(args)) => Command::Post(args.or(cli.post.clone())),
+        Some(command) => command,
+        None => Command::Post(cli.post.clone()),
+    };
+
+    // Before the .env files add to it, to tell where each setting came from
+    let environment: HashSet<String> = std::env::vars_os().filter_map(|(name, _)| name.into_string().ok()).collect();
+    let files = match load_env_files(cli.env_file.as_deref(), cli.data_dir.as_deref()) {
+        Ok(files) => {
+            files.iter().for_each(|f| debug!("Read settings from '{}'", f.display()));
+            files
+        }
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+
+    let config = match command {
+        Command::Config { command: ConfigCommand::Check } => return config_check(&cli, &environment, &files),
+        Command::Preview(_) | Command::History | Command::Search(_) | Command::ListFamilies(_) | Command::Stats(_) =>
+            Config::from_env_without_credentials(),
+        Command::Post(_) | Command::UpdateTaxonomy | Command::Verify | Command::DeleteLast(_) | Command::Delete(_) =>
+            Config::from_env(),
+    };
+    let (mut config, mut policy) = match config.and_then(|c| Ok((c, RetryPolicy::from_env()?))) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    // minreq falls back on the proxy variables by itself for any request sent without a proxy,
+    // which would take NO_PROXY hosts through one anyway; the config has read them already
+    for name in PROXY_VARIABLES {
+        std::env::remove_var(name);
+    }
+    cli.apply(&mut config, &mut policy);
+    // Only the commands that write files need somewhere to put them
+    if matches!(command, Command::Post(_) | Command::UpdateTaxonomy | Command::Preview(_)) {
+        if let Err(e) = config.create_data_dirs() {
+            error!("Unable to create the data directory: {}", e);
+            return ExitCode::from(e.exit_code());
+        }
+    }
+
+    match command {
+        Command::Post(args) => post(config, &policy, args),
+        Command::UpdateTaxonomy => update_taxonomy(&config, &policy),
+        Command::Preview(args) => preview(config, &policy, args),
+        Command::History => history(&config),
+        Command::Verify => verify_setup(&config),
+        Command::Search(args) => search(&config, args),
+        Command::ListFamilies(args) => list_families(&config, args),
+        Command::Stats(args) => print_stats(&config, args),
+        Command::DeleteLast(args) => delete(&config, None, args),
+        Command::Delete(args) => delete(&config, Some(args.uri), args.confirm),
+        Command::Config { .. } => unreachable!("checked before the configuration is read"),
+    }
+}
+
+fn post(mut config: Config, policy: &RetryPolicy, args: PostArgs) -> ExitCode {
+    if args.timing && !cfg!(feature = "tracing") {
+        eprintln!("--timing needs the bot to be built with the 'tracing' feature");
+        return ExitCode::from(USAGE_ERROR);
+    }
+    config.dry_run = args.dry_run;
+    args.choice.apply(&mut config);
+    #[cfg(feature = "tracing")]
+    let timings = args.timing.then(install_timings).flatten();
+    let report = if args.interactive {
+        let timeout = args.prompt_timeout.map(Duration::from_secs);
+        run_with_approval(&config, policy, &mut config.publishers(), |b, images| {
+            eprintln!("{}", proposal(&config.template, b, images));
+            ask(timeout)
+        })
+    } else {
+        run_with_retry(&config, policy, &mut config.publishers())
+    };
+    if args.output == Some(Output::Json) {
+        println!("{}", json_report(&report));
+    }
+    let code = match report {
+        Ok(report) => {
+            let mut code = ExitCode::SUCCESS;
+            for p in report.published {
+                match p.result {
+                    Ok(Some(result)) => info!(
+                        "Success!!!! Posted to {}: {} ({})",
+                        p.platform,
+                        result.uri.as_deref().unwrap_or("no URI returned"),
+                        result.cid.as_deref().unwrap_or("no CID returned"),
+                    ),
+                    Ok(None) => info!("Dry run complete, nothing was posted to {}", p.platform),
+                    Err(e) => {
+                        error!("Unable to post to {}: {}", p.platform, e);
+                        code = ExitCode::from(e.exit_code());
+                    }
+                }
+            }
+            code
+        }
+        Err(e) => {
+            error!("Unable to create post: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    };
+    #[cfg(feature = "tracing")]
+    if let Some(timings) = timings {
+        eprint!("{}", timings.summary());
+    }
+    code
+}
+
+/// The outcome of a run for `--output json`. `ok` is only true if the bird was posted everywhere;
+/// otherwise `kind` is that of the (first) error.
+fn json_report(report: &Result<Report, BirdError>) -> Value {
+    let report = match report {
+        Ok(r) => r,
+        Err(e) => return json!({ "ok": false, "kind": e.kind(), "error": e.to_string() }),
+    };
+    let posts: Vec<Value> = report.published.iter().map(|p| match &p.result {
+        Ok(post) => json!({
+            "platform": p.platform,
+            "ok": true,
+            "uri": post.as_ref().and_then(|r| r.uri.as_ref()),
+            "cid": post.as_ref().and_then(|r| r.cid.as_ref()),
+        }),
+        Err(e) => json!({ "platform": p.platform, "ok": false, "kind": e.kind(), "error": e.to_string() }),
+    }).collect();
+    let stages: Vec<Value> = report.stages.iter().map(|s| json!({
+        "name": s.name,
+        "attempts": s.attempts,
+        "elapsed_ms": s.elapsed.as_millis() as u64,
+    })).collect();
+    let failed: Vec<(&str, &BirdError)> = report.published.iter()
+        .filter_map(|p| p.result.as_ref().err().map(|e| (p.platform.as_str(), e)))
+        .collect();
+    let b = &report.bird;
+    let mut out = json!({
+        "ok": failed.is_empty(),
+        "species_code": b.species_code,
+        "common_name": b.common_name,
+        "scientific_name": b.scientific_name,
+        "category": b.category,
+        "order": b.order,
+        "family": b.family_com_name,
+        "family_sci_name": b.family_sci_name,
+        "image_source": report.images.first().map(|i| &i.url_source),
+        "photographer": report.images.first().and_then(|i| i.photographer.as_ref()),
+        "posts": posts,
+        "stages": stages,
+    });
+    if let Some((_, e)) = failed.first() {
+        out["kind"] = json!(e.kind());
+        let errors: Vec<String> = failed.iter().map(|(platform, e)| format!("{}: {}", platform, e)).collect();
+        out["error"] = json!(errors.join("; "));
+    }
+    out
+}
+
+fn update_taxonomy(config: &Config, policy: &RetryPolicy) -> ExitCode {
+    match Deadline::after(config.max_run_duration).retry(config, policy, get_all_birds) {
+        Ok(n) => {
+            info!("Downloaded {} species to '{}'", n, config.taxonomy_path().display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            error!("Unable to update local copy of birds: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn preview(mut config: Config, policy: &RetryPolicy, args: ChoiceArgs) -> ExitCode {
+    args.apply(&mut config);
+    let deadline = Deadline::after(config.max_run_duration);
+    let bird = History::load(&config.history_path)
+        .and_then(|history| deadline.retry(&config, policy, |config| get_bird_with_photo(config, &history)));
+    let (b, images) = match bird {
+        Ok(b) => b,
+        Err(e) => {
+            error!("Unable to pick a bird: {}", e);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    print!("{}", proposal(&config.template, &b, &images));
+    let today = config.today();
+    let overridden = config.only_bird.is_none() && config.date_overrides.for_date(today) == Some(b.species_code.as_str());
+    if overridden {
+        println!("Date override for {}", today);
+    } else if let Some((family, multiplier)) = config.seasonal_weights.multiplier(&b, today.month())
+        .filter(|_| config.only_bird.is_none() && config.selection != SelectionStrategy::Shuffle)
+    {
+        println!("Seasonal weight: {} for {} in {}", multiplier, family, today.month());
+    }
+    for (n, image) in images.iter().enumerate() {
+        match deadline.retry(&config, policy, |config| save_image(config, &b, image, n)) {
+            Ok((path, photo)) => {
+                let size = imagesize::blob_size(&photo)
+                    .map_or_else(|_| "unknown size".to_string(), |s| format!("{}x{}", s.width, s.height));
+                println!("\nSaved {} ({}, {} bytes)", path.display(), size, photo.len());
+            }
+            Err(e) => {
+                error!("Unable to download {}: {}", image.url_download, e);
+                return ExitCode::from(e.exit_code());
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// What would be posted about `b`: the text, then each photo with its credit and alt text
+fn proposal(template: &Template, b: &Bird, images: &[BirdImage]) -> String {
+    let mut out = format!("{}\n\n", post_text(template, b, images));
+    for image in images {
+        out.push_str(&format!("Image credit: {}\n", image.url_source));
+        if let Some(photographer) = &image.photographer {
+            out.push_str(&format!("Photographer: {}\n", photographer));
+        }
+        out.push_str(&format!("Photo: {} ({})\n", image.url_download, image.photo_type));
+        out.push_str(&format!("Alt text: {}\n", image_alt_text(b, image)));
+    }
+    if let Some(audio) = &images[0].audio_url {
+        out.push_str(&format!("Recording: {}\n", audio));
+    }
+    out
+}
+
+/// Ask on stderr whether to post, reading the answer from stdin so it can be piped in. No
+/// answer within `timeout`, the end of stdin, or anything but `y` or `r` means no.
+fn ask(timeout: Option<Duration>) -> Approval {
+    eprint!("Post this? [y/N/r] ");
+    match read_answer(timeout) {
+        Some(Ok(line)) => match line.trim().to_lowercase().as_str() {
+            "y" | "yes" => Approval::Post,
+            "r" | "reroll" => Approval::Reroll,
+            _ => Approval::Decline,
+        },
+        Some(Err(_)) => Approval::Decline,
+        None => {
+            eprintln!();
+            info!("No answer in time, not posting");
+            Approval::Decline
+        }
+    }
+}
+
+/// Ask `question` on stderr, taking anything but `y` on stdin as no
+fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    matches!(read_answer(None), Some(Ok(line)) if matches!(line.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// A line from stdin, or `None` if none came within `timeout`
+fn read_answer(timeout: Option<Duration>) -> Option<std::io::Result<String>> {
+    let _ = std::io::stderr().flush();
+    let (tx, rx) = mpsc::channel();
+    // A thread, so the wait can be given up on; one still blocked on stdin goes with the process
+    thread::spawn(move || {
+        let mut line = String::new();
+        let _ = tx.send(std::io::stdin().read_line(&mut line).map(|_| line));
+    });
+    match timeout {
+        Some(timeout) => rx.recv_timeout(timeout).ok(),
+        None => rx.recv().ok(),
+    }
+}
+
+fn history(config: &Config) -> ExitCode {
+    let history = match History::load(&config.history_path) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("Unable to read the post history: {}", e);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    if history.entries.is_empty() {
+        println!("No birds have been posted yet");
+        return ExitCode::SUCCESS;
+    }
+    // Names are a nicety; the species codes are enough without a taxonomy
+    let catalog = BirdCatalog::load(config.taxonomy_path()).unwrap_or_default();
+    for entry in history.entries.iter().rev() {
+        let deleted = if entry.deleted { " (deleted)" } else { "" };
+        println!("{}  {}{}", entry.posted_at, describe(&catalog, &entry.species_code), deleted);
+    }
+    ExitCode::SUCCESS
+}
+
+/// The bird's names if the taxonomy has it, otherwise its species code
+fn describe(catalog: &BirdCatalog, species_code: &str) -> String {
+    match catalog.by_species_code(species_code) {
+        Some(b) => format!("{} ({})", b.common_name, b.scientific_name),
+        None => species_code.to_string(),
+    }
+}
+
+/// Delete the post at `uri`, or the last one in the history, after asking unless told not to
+fn delete(config: &Config, uri: Option<String>, args: ConfirmArgs) -> ExitCode {
+    let history = match History::load(&config.history_path) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("Unable to read the post history: {}", e);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    let entry = match &uri {
+        Some(uri) => history.entries.iter().find(|e| e.uri.as_deref() == Some(uri.as_str())),
+        None => history.last_deletable(),
+    };
+    let Some(uri) = uri.or_else(|| entry.and_then(|e| e.uri.clone())) else {
+        error!("No post in the history to delete; give the post's URI with `delete --uri` instead");
+        return ExitCode::FAILURE;
+    };
+
+    let catalog = BirdCatalog::load(config.taxonomy_path()).unwrap_or_default();
+    match entry {
+        Some(e) => eprintln!("{}, posted {}\n{}", describe(&catalog, &e.species_code), e.posted_at, uri),
+        None => eprintln!("{} (not in the post history)", uri),
+    }
+    if !args.yes && !confirm("Delete this post?") {
+        info!("Not deleting anything");
+        return ExitCode::from(BirdError::Declined.exit_code());
+    }
+
+    match delete_post(config, &uri) {
+        Ok(_) => {
+            info!("Deleted {}", uri);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            error!("Unable to delete the post: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn verify_setup(config: &Config) -> ExitCode {
+    let checks = verify(config);
+    for check in &checks {
+        match &check.result {
+            Ok(detail) => println!("PASS  {}: {}", check.name, detail),
+            Err(e) => {
+                println!("FAIL  {}: {}", check.name, e);
+                if let Some(hint) = check.hint() {
+                    println!("      {}", hint);
+                }
+            }
+        }
+    }
+    match checks.iter().find_map(|c| c.result.as_ref().err()) {
+        Some(e) => ExitCode::from(e.exit_code()),
+        None => ExitCode::SUCCESS,
+    }
+}
 
-fn main() {
-    // TODO: Allow command line arguments to periodically update local copy of birds.json
-    // For now, just set to not run unless desired
-    if false {
-        get_all_birds();
+fn search(config: &Config, args: SearchArgs) -> ExitCode {
+    let catalog = match BirdCatalog::load(config.taxonomy_path()) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Unable to read the taxonomy (run `update-taxonomy` to download it): {}", e);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    let found = catalog.search(&args.query, args.family.as_deref());
+    if found.is_empty() {
+        println!("No birds match '{}'", args.query);
     }
-    // Do 3 attempts because it sometimes fails
-    let mut num_attempts = 0;
-    while num_attempts < 3 {
-        num_attempts += 1;
-        if run() {
-            break;
+    for b in found.iter().take(args.limit) {
+        let family = b.family_com_name.as_deref().unwrap_or("no family");
+        match b.category.as_str() {
+            "species" => println!("{:<10} {} ({}), {}", b.species_code, b.common_name, b.scientific_name, family),
+            category => println!("{:<10} {} ({}), {} [{}]", b.species_code, b.common_name, b.scientific_name, family, category),
         }
     }
-    if num_attempts == 3 {
-        eprintln!("After 3 attempts, unable to create post");
+    if found.len() > args.limit {
+        println!("... and {} more; narrow the search or raise --limit", found.len() - args.limit);
+    }
+    ExitCode::SUCCESS
+}
+
+fn list_families(config: &Config, args: ListFamiliesArgs) -> ExitCode {
+    let catalog = match BirdCatalog::load(config.taxonomy_path()) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Unable to read the taxonomy (run `update-taxonomy` to download it): {}", e);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    let names = if args.orders { catalog.orders() } else { catalog.families() };
+    names.iter().for_each(|name| println!("{}", name));
+    ExitCode::SUCCESS
+}
+
+fn print_stats(config: &Config, args: StatsArgs) -> ExitCode {
+    let stats = BirdCatalog::load(config.taxonomy_path()).and_then(|catalog| stats(config, &catalog));
+    let stats = match stats {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Unable to count the birds in '{}': {}", config.taxonomy_path().display(), e);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    if args.output == Some(Output::Json) {
+        println!("{}", json!(stats));
+        return ExitCode::SUCCESS;
+    }
+
+    println!("{} entries in '{}'", stats.total, config.taxonomy_path().display());
+    if let Some(region) = &stats.region {
+        println!("Only birds recorded in {}", region);
+    }
+    for (category, count) in &stats.categories {
+        println!("  {:<12} {:>6}", category, count);
+    }
+    println!("Excluded");
+    println!("  {:<12} {:>6}  (not {})", "category", stats.excluded.category, config.categories.join(", "));
+    if !config.excluded_categories.is_empty() {
+        println!("  {:<12} {:>6}  ({})", "denied", stats.excluded.denied_category, config.excluded_categories.join(", "));
+    }
+    if !config.include_extinct {
+        println!("  {:<12} {:>6}", "extinct", stats.excluded.extinct);
+    }
+    if config.blocklist_path().exists() {
+        println!("  {:<12} {:>6}  (in {})", "blocklist", stats.excluded.blocklist, config.blocklist_path().display());
+    }
+    if config.allowlist_path().exists() {
+        println!("  {:<12} {:>6}  (not in {})", "allowlist", stats.excluded.allowlist, config.allowlist_path().display());
+    }
+    if let Some(family) = &config.only_family {
+        println!("  {:<12} {:>6}  (not in {})", "family", stats.excluded.family, family);
+    }
+    if let Some(order) = &config.only_order {
+        println!("  {:<12} {:>6}  (not in {})", "order", stats.excluded.order, order);
     }
+    if let Some(region) = &config.region {
+        println!("  {:<12} {:>6}  (not seen in {})", "region", stats.excluded.region, region);
+    }
+    println!("{} birds to pick from", stats.remaining);
+    if let Some(queued) = stats.queued {
+        println!("{} of them still to come before the shuffle goes round again", queued);
+    }
+    for order in &stats.orders {
+        println!("  {:<40} {:>6}", order.order, order.count);
+        for family in &order.families {
+            println!("    {:<38} {:>6}", family.family, family.count);
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Print every setting, where its value came from, and every problem with them
+fn config_check(cli: &Cli, environment: &HashSet<String>, files: &[PathBuf]) -> ExitCode {
+    let (mut config, mut problems) = Config::check_env();
+    let (mut policy, policy_problems) = RetryPolicy::check_env();
+    problems.extend(policy_problems);
+    cli.apply(&mut config, &mut policy);
+    problems.extend(check_config(&config));
+
+    for (name, value) in config.settings().into_iter().chain(policy.settings()) {
+        let value = if value.is_empty() { "(not set)".to_string() } else { value };
+        println!("{:<26} {:<24} {}", name, source(cli, name, environment, files), value);
+    }
+    if problems.is_empty() {
+        println!("\nNo problems found");
+        return ExitCode::SUCCESS;
+    }
+    println!();
+    for problem in &problems {
+        println!("PROBLEM  {}", problem);
+    }
+    ExitCode::from(USAGE_ERROR)
+}
+
+/// Where the value of the variable `name` came from: an option, the environment the bot was
+/// started in, the first of the `.env` files that sets it, the credentials file, or nowhere,
+/// leaving the default
+fn source(cli: &Cli, name: &str, environment: &HashSet<String>, files: &[PathBuf]) -> String {
+    if let Some(flag) = cli.flag_for(name) {
+        return flag.to_string();
+    }
+    // The proxy variables are read in lower case too
+    let mut names = vec![name.to_string()];
+    if PROXY_VARIABLES.contains(&name.to_lowercase().as_str()) {
+        names.push(name.to_lowercase());
+    }
+    if names.iter().any(|n| environment.contains(n)) {
+        return "environment".to_string();
+    }
+    let sets = |file: &PathBuf| dotenvy::from_path_iter(file).is_ok_and(|vars| vars.flatten().any(|(k, _)| names.contains(&k)));
+    if let Some(file) = files.iter().find(|f| sets(f)) {
+        return file.display().to_string();
+    }
+    let credentials_file = std::env::var_os("BOTD_CREDENTIALS_FILE").filter(|f| !f.is_empty()).map(PathBuf::from);
+    match credentials_file {
+        Some(file) if Credentials::read(&file).is_ok_and(|c| c.get(name).is_some()) => file.display().to_string(),
+        _ => "default".to_string(),
+    }
+}
+
+/// Collect the external calls of the run to summarize at the end
+#[cfg(feature = "tracing")]
+fn install_timings() -> Option<timing::Timings> {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let timings = timing::Timings::default();
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(timings.clone())).ok()?;
+    Some(timings)
 }
@@ -0,0 +1,242 @@
+use std::{thread, time::Duration};
+
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+
+use crate::{
+    audio_embed, download_image, image_alt_text, photo_type,
+    http::{self, body_text, send, HttpError},
+    json_str,
+    timing::traced,
+    Bird, BirdError, BirdImage, Config, PostResult, Publisher, Template,
+};
+
+/// How many times to check whether Mastodon has finished processing an uploaded photo
+const MEDIA_CHECKS: u32 = 10;
+
+/// Cross-posts to a Mastodon account through the instance's REST API
+pub struct MastodonPublisher {
+    /// Base URL of the instance, e.g. `https://mastodon.social`
+    instance_url: String,
+    access_token: String,
+    /// The photos uploaded by an earlier attempt, as species code and media IDs
+    media: Option<(String, Vec<String>)>,
+}
+
+impl MastodonPublisher {
+    pub fn new(instance_url: &str, access_token: &str) -> MastodonPublisher {
+        MastodonPublisher {
+            instance_url: instance_url.trim_end_matches('/').to_string(),
+            access_token: access_token.to_string(),
+            media: None,
+        }
+    }
+
+    /// Download the photo and upload it with `/api/v2/media`, returning the media ID
+    fn upload_photo(&self, config: &Config, bird: &Bird, image: &BirdImage) -> Result<String, BirdError> {
+        let photo = download_image(config, image)?;
+
+        let boundary = format!("birdoftheday-{}", bird.species_code);
+        let body = multipart(&boundary, &image_alt_text(bird, image), photo_type(image, &photo), &photo);
+        let url = format!("{}/api/v2/media", self.instance_url);
+        let r = traced("mastodon media", &url, || send(config, || {
+            http::post(config, &url, config.bluesky_timeout)
+                .with_header("Authorization", format!("Bearer {}", self.access_token))
+                .with_header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+                .with_body(body.clone())
+        }))?;
+        let r = http::check(r).map_err(HttpError::mastodon)?;
+        let id = r.json::<Value>().ok()
+            .and_then(|j| json_str(&j, "id"))
+            .ok_or_else(|| BirdError::Parse("'id' parameter was not present in Mastodon media upload response".to_string()))?;
+        // 202 means the upload worked but the photo is still being processed
+        if r.status_code == 202 {
+            self.wait_for_media(config, &id)?;
+        }
+        Ok(id)
+    }
+
+    /// Wait until Mastodon has processed the uploaded photo, as a status can't be posted with it before then
+    fn wait_for_media(&self, config: &Config, id: &str) -> Result<(), BirdError> {
+        let url = format!("{}/api/v1/media/{}", self.instance_url, id);
+        for _ in 0..MEDIA_CHECKS {
+            thread::sleep(Duration::from_secs(1));
+            let r = send(config, || {
+                http::get(config, &url, config.bluesky_timeout)
+                    .with_header("Authorization", format!("Bearer {}", self.access_token))
+            })?;
+            match r.status_code {
+                200 => return Ok(()),
+                206 => debug!("Mastodon is still processing the photo"),
+                status => return Err(BirdError::Mastodon { status: Some(status), message: body_text(&r) }),
+            }
+        }
+        Err(BirdError::Mastodon { status: None, message: "photo was still being processed".to_string() })
+    }
+}
+
+impl Publisher for MastodonPublisher {
+    fn name(&self) -> &str {
+        "Mastodon"
+    }
+
+    fn publish(&mut self, config: &Config, bird: &Bird, images: &[BirdImage]) -> Result<Option<PostResult>, BirdError> {
+        let text = status_text(&config.template, bird, images, audio_embed(config, &images[0]));
+        if config.dry_run {
+            info!("Dry run, not posting to Mastodon. Would have posted: {:?}", text);
+            return Ok(None);
+        }
+
+        let media_ids = match &self.media {
+            Some((code, ids)) if *code == bird.species_code => {
+                debug!("Reusing photos uploaded to Mastodon by an earlier attempt");
+                ids.clone()
+            }
+            _ => {
+                let ids = images.iter().map(|image| self.upload_photo(config, bird, image)).collect::<Result<Vec<_>, _>>()?;
+                self.media = Some((bird.species_code.clone(), ids.clone()));
+                ids
+            }
+        };
+
+        let status = json!({
+            "status": text,
+            "media_ids": media_ids,
+        });
+        let url = format!("{}/api/v1/statuses", self.instance_url);
+        // The same key on a retry makes Mastodon return the status it already created
+        let key = format!("{}-{}", bird.species_code, OffsetDateTime::now_utc().date());
+        let r = traced("mastodon status", &url, || send(config, || {
+            http::post_json(config, &url, &status, config.bluesky_timeout)
+                .with_header("Authorization", format!("Bearer {}", self.access_token))
+                .with_header("Idempotency-Key", key.as_str())
+        }))?;
+        let r = http::check(r).map_err(HttpError::mastodon)?;
+
+        // The status exists at this point, so a malformed response is only worth a warning
+        let json = r.json::<Value>().unwrap_or_else(|e| {
+            warn!("Status was posted, but error occurred during conversion to JSON: {}", e);
+            Value::Null
+        });
+        let field = |key: &str| {
+            let value = json_str(&json, key);
+            if value.is_none() {
+                warn!("Status was posted, but '{}' parameter was not present", key);
+            }
+            value
+        };
+        Ok(Some(PostResult { uri: field("url"), cid: field("id") }))
+    }
+}
+
+/// Text of the status for `bird`, in the words of `template`. Mastodon links URLs by itself, so
+/// the credits are spelled out.
+fn status_text(template: &Template, bird: &Bird, images: &[BirdImage], audio: Option<&str>) -> String {
+    let mut text = String::new();
+    template.render(bird, &mut text, |text| {
+        let sources: Vec<&str> = images.iter().filter(|i| !i.range_map).map(|i| i.url_source.as_str()).collect();
+        let label = if sources.len() > 1 { "Image Credits" } else { "Image Credit" };
+        text.push_str(&format!("{}: {}", label, sources.join(" ")));
+        for map in images.iter().filter(|i| i.range_map) {
+            text.push_str(&format!("\nRange map: {}", map.url_source));
+        }
+    });
+    if let Some(audio) = audio {
+        text.push_str(&format!("\nSong and calls: {}", audio));
+    }
+    text
+}
+
+/// A `multipart/form-data` body with the photo as `file` and its alt text as `description`
+fn multipart(boundary: &str, description: &str, content_type: &str, photo: &[u8]) -> Vec<u8> {
+    let mut body = format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"description\"\r\n\r\n{description}\r\n\
+         --{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"photo\"\r\nContent-Type: {content_type}\r\n\r\n"
+    ).into_bytes();
+    body.extend_from_slice(photo);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Response, TestServer};
+
+    fn bird() -> Bird {
+        serde_json::from_value(json!({
+            "sciName": "Cardinalis cardinalis", "comName": "Northern Cardinal", "speciesCode": "norcar",
+            "category": "species", "taxonOrder": 1.0,
+        })).unwrap()
+    }
+
+    fn image(host: &str) -> BirdImage {
+        BirdImage {
+            photo_type: "image/jpeg".to_string(),
+            url_download: format!("{}/photo.jpg", host),
+            url_source: "https://macaulaylibrary.org/asset/1".to_string(),
+            alt_text: "A bird".to_string(),
+            audio_url: None,
+            photographer: None,
+            asset_id: Some(1),
+            license: None,
+            range_map: false,
+        }
+    }
+
+    #[test]
+    fn photo_is_uploaded_then_attached_to_the_status() {
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/photo.jpg" => Response::new(200, "jpeg bytes"),
+            "/api/v2/media" => Response::json(200, json!({"id": "17"})),
+            "/api/v1/statuses" => Response::json(200, json!({"id": "42", "url": "https://mastodon.example/@botd/42"})),
+            _ => Response::new(404, ""),
+        });
+        let mut publisher = MastodonPublisher::new(&format!("{}/", server.url), "secret");
+
+        let result = publisher.publish(&Config::default(), &bird(), &[image(&server.url)]).unwrap().unwrap();
+        assert_eq!(result, PostResult { uri: Some("https://mastodon.example/@botd/42".to_string()), cid: Some("42".to_string()) });
+
+        let requests = server.requests();
+        let media = requests.iter().find(|r| r.path == "/api/v2/media").unwrap();
+        assert_eq!(media.header("Authorization"), Some("Bearer secret"));
+        let body = String::from_utf8_lossy(&media.body);
+        assert!(body.contains("jpeg bytes"));
+        assert!(body.contains("A bird. Photo of a Northern Cardinal (Cardinalis cardinalis)."));
+
+        let status = requests.iter().find(|r| r.path == "/api/v1/statuses").unwrap();
+        let status: Value = serde_json::from_slice(&status.body).unwrap();
+        assert_eq!(status["media_ids"], json!(["17"]));
+        assert!(status["status"].as_str().unwrap().contains("https://macaulaylibrary.org/asset/1"));
+    }
+
+    #[test]
+    fn range_map_is_not_credited_as_a_photo() {
+        let map = BirdImage { url_source: "https://ebird.org/map/norcar".to_string(), range_map: true, ..image("cdn") };
+        let text = status_text(&Template::default(), &bird(), &[image("cdn"), map], None);
+        assert!(text.contains("Image Credit: https://macaulaylibrary.org/asset/1\nRange map: https://ebird.org/map/norcar"), "{}", text);
+    }
+
+    #[test]
+    fn uploaded_photo_is_reused_when_the_status_fails() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let failed = AtomicBool::new(false);
+        let server = TestServer::start(move |r| match r.path.as_str() {
+            "/photo.jpg" => Response::new(200, "jpeg bytes"),
+            "/api/v2/media" => Response::json(200, json!({"id": "17"})),
+            "/api/v1/statuses" if !failed.swap(true, Ordering::SeqCst) => Response::json(503, json!({"error": "Unavailable"})),
+            "/api/v1/statuses" => Response::json(200, json!({"id": "42", "url": "https://mastodon.example/@botd/42"})),
+            _ => Response::new(404, ""),
+        });
+        let mut publisher = MastodonPublisher::new(&server.url, "secret");
+        let config = Config::default();
+
+        let e = publisher.publish(&config, &bird(), &[image(&server.url)]).unwrap_err();
+        assert!(matches!(e, BirdError::Mastodon { status: Some(503), .. }) && e.is_retryable());
+        assert!(publisher.publish(&config, &bird(), &[image(&server.url)]).is_ok());
+        assert_eq!(server.hits("/api/v2/media"), 1);
+        assert_eq!(server.hits("/api/v1/statuses"), 2);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{fmt, str::FromStr};
+
+use time::{Date, Month};
+
+/// Birds to post on particular days instead of picking one, e.g.
+/// `11-26=wiltur, 12-25=eutdov, 2026-11-26=wiltur`: a date, `MM-DD` for every year or
+/// `YYYY-MM-DD` for just the one day, an equals sign, and an eBird species code. A full date
+/// takes precedence over `MM-DD` on the same day. Empty, the default, leaves every day to the pick.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DateOverrides {
+    source: String,
+    entries: Vec<(OverrideDate, String)>,
+}
+
+/// The day an override is for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OverrideDate {
+    Yearly(Month, u8),
+    Once(Date),
+}
+
+impl OverrideDate {
+    fn matches(self, date: Date) -> bool {
+        match self {
+            OverrideDate::Yearly(month, day) => date.month() == month && date.day() == day,
+            OverrideDate::Once(d) => d == date,
+        }
+    }
+}
+
+impl FromStr for OverrideDate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{}' should be a date, either MM-DD or YYYY-MM-DD", s);
+        let parts: Vec<&str> = s.split('-').collect();
+        let number = |part: &str| part.parse::<u16>().map_err(|_| invalid());
+        let (year, month, day) = match parts[..] {
+            [month, day] => (None, number(month)?, number(day)?),
+            [year, month, day] => (Some(number(year)?), number(month)?, number(day)?),
+            _ => return Err(invalid()),
+        };
+        let month = u8::try_from(month).ok().and_then(|m| Month::try_from(m).ok())
+            .ok_or_else(|| format!("there is no month {}", month))?;
+        let day = u8::try_from(day).map_err(|_| format!("{} has no day {}", month, day))?;
+        // A leap year, so that 02-29 is a day to override in the years that have one
+        let date = Date::from_calendar_date(year.map_or(2024, i32::from), month, day)
+            .map_err(|_| format!("'{}' is not a day of the year", s))?;
+        Ok(match year {
+            Some(_) => OverrideDate::Once(date),
+            None => OverrideDate::Yearly(month, day),
+        })
+    }
+}
+
+impl FromStr for DateOverrides {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries: Vec<(OverrideDate, String)> = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (date, code) = entry.split_once('=')
+                .ok_or_else(|| format!("'{}' should be a date, an equals sign, and a species code, e.g. 11-26=wiltur", entry))?;
+            let (date, code) = (date.trim(), code.trim());
+            let day: OverrideDate = date.parse()?;
+            if code.is_empty() || code.contains(char::is_whitespace) {
+                return Err(format!("the override for {} has to be a species code, not '{}'", date, code));
+            }
+            if entries.iter().any(|(d, _)| *d == day) {
+                return Err(format!("{} has more than one override", date));
+            }
+            entries.push((day, code.to_string()));
+        }
+        Ok(DateOverrides { source: s.trim().to_string(), entries })
+    }
+}
+
+impl fmt::Display for DateOverrides {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl DateOverrides {
+    /// The species code of the bird to post on `date`, if there is one
+    pub fn for_date(&self, date: Date) -> Option<&str> {
+        let find = |once: bool| self.entries.iter()
+            .find(|(d, _)| matches!(d, OverrideDate::Once(_)) == once && d.matches(date));
+        find(true).or_else(|| find(false)).map(|(_, code)| code.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: Month, day: u8) -> Date {
+        Date::from_calendar_date(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn full_dates_take_precedence_over_yearly_ones() {
+        let overrides: DateOverrides = "11-26=wiltur, 12-25 = eutdov ,2026-11-26=turvul, 02-29=leapbird".parse().unwrap();
+        assert_eq!(overrides.for_date(date(2025, Month::November, 26)), Some("wiltur"));
+        assert_eq!(overrides.for_date(date(2026, Month::November, 26)), Some("turvul"));
+        assert_eq!(overrides.for_date(date(2026, Month::December, 25)), Some("eutdov"));
+        assert_eq!(overrides.for_date(date(2028, Month::February, 29)), Some("leapbird"));
+        assert_eq!(overrides.for_date(date(2026, Month::December, 24)), None);
+        assert_eq!(overrides.to_string(), "11-26=wiltur, 12-25 = eutdov ,2026-11-26=turvul, 02-29=leapbird");
+        assert!(DateOverrides::default().is_empty() && "".parse::<DateOverrides>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn bad_overrides_are_rejected() {
+        let e = |s: &str| s.parse::<DateOverrides>().unwrap_err();
+        assert!(e("11-26 wiltur").contains("an equals sign"));
+        assert!(e("thanksgiving=wiltur").contains("MM-DD or YYYY-MM-DD"));
+        assert_eq!(e("13-01=wiltur"), "there is no month 13");
+        assert_eq!(e("02-30=wiltur"), "'02-30' is not a day of the year");
+        assert_eq!(e("2025-02-29=wiltur"), "'2025-02-29' is not a day of the year");
+        assert!(e("12-25=").contains("has to be a species code"));
+        assert!(e("12-25=Turtle Dove").contains("has to be a species code"));
+        assert_eq!(e("12-25=eutdov, 12-25=wiltur"), "12-25 has more than one override");
+    }
+}
@@ -0,0 +1,48 @@
+//! Species pages kept on disk between runs, so re-running the bot while working on it doesn't
+//! fetch the same page from eBird every time
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::BirdError;
+
+/// The page saved at `path`, unless there is none or it was saved `ttl` or more ago
+pub(crate) fn load(path: &Path, ttl: Duration) -> Option<String> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let age = SystemTime::now().duration_since(modified).unwrap_or(Duration::ZERO);
+    if age >= ttl {
+        return None;
+    }
+    fs::read_to_string(path).ok()
+}
+
+/// Save `page` at `path`, creating the directory it goes in
+pub(crate) fn save(path: &Path, page: &str) -> Result<(), BirdError> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, page)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn pages_expire_after_the_ttl() {
+        let path = std::env::temp_dir().join(format!("botd-pages-{}", std::process::id())).join("norcar.html");
+        let _ = fs::remove_file(&path);
+        assert_eq!(load(&path, Duration::from_secs(60)), None);
+
+        save(&path, "<html></html>").unwrap();
+        assert_eq!(load(&path, Duration::from_secs(60)).as_deref(), Some("<html></html>"));
+        File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now() - Duration::from_secs(60)).unwrap();
+        assert_eq!(load(&path, Duration::from_secs(60)), None);
+    }
+}
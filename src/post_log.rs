@@ -0,0 +1,115 @@
+use std::{
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::BirdError;
+
+/// One post, as a line of the post log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PostLogEntry {
+    /// RFC 3339 timestamp of the post
+    pub posted_at: String,
+    /// Where it was posted, e.g. `Bluesky`
+    pub platform: String,
+    pub species_code: String,
+    pub common_name: String,
+    pub scientific_name: String,
+    /// Page crediting the (first) photo
+    pub image_source: String,
+    /// Its Macaulay Library catalog number and license, if known, which entries logged before
+    /// they were recorded are without
+    pub asset_id: Option<u64>,
+    pub license: Option<String>,
+    /// As returned by the platform, if it did
+    pub uri: Option<String>,
+    pub cid: Option<String>,
+}
+
+/// Append `entry` to the log at `path`, one JSON object per line. If that would take the file
+/// past `max_size` bytes, it is moved to `<path>.1` first (replacing the one there), so at most
+/// about twice `max_size` is kept. A `max_size` of 0 lets the log grow without limit.
+pub(crate) fn append(path: &Path, entry: &PostLogEntry, max_size: u64) -> Result<(), BirdError> {
+    let mut line = serde_json::to_string(entry)
+        .map_err(|e| BirdError::Parse(format!("Error converting post log entry to JSON: {}", e)))?;
+    line.push('\n');
+
+    let size = match fs::metadata(path) {
+        Ok(m) => m.len(),
+        Err(e) if e.kind() == ErrorKind::NotFound => 0,
+        Err(e) => return Err(e.into()),
+    };
+    if max_size > 0 && size > 0 && size + line.len() as u64 > max_size {
+        fs::rename(path, rotated_path(path))?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Where the log at `path` is moved to when it gets too big
+pub(crate) fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".1");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(code: &str) -> PostLogEntry {
+        PostLogEntry {
+            posted_at: "2024-05-01T12:00:00Z".to_string(),
+            platform: "Bluesky".to_string(),
+            species_code: code.to_string(),
+            common_name: "Northern Cardinal".to_string(),
+            scientific_name: "Cardinalis cardinalis".to_string(),
+            image_source: "https://macaulaylibrary.org/asset/1".to_string(),
+            asset_id: Some(1),
+            license: None,
+            uri: Some("at://post".to_string()),
+            cid: None,
+        }
+    }
+
+    fn log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("botd-posts-{}-{}.jsonl", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(rotated_path(&path));
+        path
+    }
+
+    fn read(path: &Path) -> Vec<PostLogEntry> {
+        fs::read_to_string(path).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn entries_are_appended_one_per_line() {
+        let path = log_path("append");
+        append(&path, &entry("norcar"), 0).unwrap();
+        append(&path, &entry("blujay"), 0).unwrap();
+        assert_eq!(read(&path), [entry("norcar"), entry("blujay")]);
+    }
+
+    #[test]
+    fn full_log_is_rotated() {
+        let path = log_path("rotate");
+        let line = serde_json::to_string(&entry("norcar")).unwrap().len() as u64 + 1;
+        append(&path, &entry("norcar"), line * 2).unwrap();
+        append(&path, &entry("blujay"), line * 2).unwrap();
+        append(&path, &entry("amerob"), line * 2).unwrap();
+        assert_eq!(read(&rotated_path(&path)), [entry("norcar"), entry("blujay")]);
+        assert_eq!(read(&path), [entry("amerob")]);
+    }
+
+    #[test]
+    fn entries_from_before_asset_ids_still_read() {
+        let old = r#"{"posted_at":"2024-05-01T12:00:00Z","platform":"Bluesky","species_code":"norcar","common_name":"Northern Cardinal","scientific_name":"Cardinalis cardinalis","image_source":"https://macaulaylibrary.org/asset/1","uri":"at://post","cid":null}"#;
+        let entry: PostLogEntry = serde_json::from_str(old).unwrap();
+        assert_eq!((entry.asset_id, entry.license), (None, None));
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{Bird, BirdError, BirdImage, Config, PostResult};
+
+/// Somewhere the bird of the day gets posted
+pub trait Publisher {
+    /// Name of the platform, for logs and reports
+    fn name(&self) -> &str;
+
+    /// Post `bird` with its photos, of which there is at least one and at most `MAX_PHOTOS`,
+    /// returning the post that was created. `config` carries the
+    /// run's timeouts, and on a dry run nothing is posted and the result is `None`.
+    /// A failed call may be repeated with the same bird, so anything already done can be reused.
+    fn publish(&mut self, config: &Config, bird: &Bird, images: &[BirdImage]) -> Result<Option<PostResult>, BirdError>;
+}
+
+/// How posting to one platform went
+#[derive(Debug)]
+pub struct Published {
+    /// Name of the platform, e.g. `Bluesky`
+    pub platform: String,
+    /// The post that was created, or `None` on a dry run
+    pub result: Result<Option<PostResult>, BirdError>,
+}
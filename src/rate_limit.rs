@@ -0,0 +1,87 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A token bucket pacing requests to a service. Clones share the same bucket.
+#[derive(Clone)]
+pub struct RateLimiter {
+    per_second: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// Allow `per_second` requests a second on average, with bursts of up to that many (at least one)
+    pub fn new(per_second: f64) -> RateLimiter {
+        RateLimiter {
+            per_second,
+            bucket: Arc::new(Mutex::new(Bucket { tokens: capacity(per_second), refilled: Instant::now() })),
+        }
+    }
+
+    /// Requests allowed a second
+    pub fn per_second(&self) -> f64 {
+        self.per_second
+    }
+
+    /// Take a token from the bucket, sleeping until one is available
+    pub fn acquire(&self) {
+        // Holding the lock while sleeping makes everyone else queue up behind us, which is the point
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(capacity(self.per_second));
+        bucket.refilled = now;
+
+        if bucket.tokens < 1.0 {
+            let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second);
+            thread::sleep(wait);
+            bucket.tokens = 1.0;
+            bucket.refilled = Instant::now();
+        }
+        bucket.tokens -= 1.0;
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RateLimiter({}/s)", self.per_second)
+    }
+}
+
+fn capacity(per_second: f64) -> f64 {
+    per_second.max(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bursts_are_allowed_up_to_the_rate() {
+        let limiter = RateLimiter::new(3.0);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire();
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn requests_past_the_burst_are_paced() {
+        let limiter = RateLimiter::new(10.0);
+        let start = Instant::now();
+        for _ in 0..15 {
+            limiter.clone().acquire();
+        }
+        // 10 straight away, then the other 5 at 100ms intervals
+        assert!(start.elapsed() >= Duration::from_millis(450), "{:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use crate::{Bird, BirdImage, Published};
+
+/// Everything a run did: the bird it picked, how posting it went on each platform, and how
+/// long each step took
+#[derive(Debug)]
+pub struct Report {
+    pub bird: Bird,
+    pub images: Vec<BirdImage>,
+    /// One entry per publisher, in the order they were given
+    pub published: Vec<Published>,
+    /// The steps of the run in the order they happened
+    pub stages: Vec<Stage>,
+}
+
+impl Report {
+    /// The URI of the post made on `platform`, e.g. `Bluesky`, if one was made and the platform
+    /// returned it, for anything to be done with the post afterwards
+    pub fn uri(&self, platform: &str) -> Option<&str> {
+        self.published.iter()
+            .filter(|p| p.platform == platform)
+            .find_map(|p| p.result.as_ref().ok()?.as_ref()?.uri.as_deref())
+    }
+}
+
+/// One step of a run, with its retries
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stage {
+    /// What the step did, e.g. `pick` or the name of a platform
+    pub name: String,
+    /// Attempts made, including the first
+    pub attempts: u32,
+    /// Time taken by all of the attempts and the waits in between
+    pub elapsed: Duration,
+}
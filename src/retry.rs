@@ -0,0 +1,237 @@
+use std::time::Duration;
+
+use log::warn;
+use rand::Rng;
+
+use crate::{config::EnvReader, BirdError};
+
+/// How many times to attempt a run and how long to wait in between
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first
+    pub max_attempts: u32,
+    /// Delay before the second attempt
+    pub base_delay: Duration,
+    /// How much longer each delay is than the one before
+    pub multiplier: f64,
+    /// Upper bound for any single delay
+    pub max_delay: Duration,
+    /// Sleep a random amount between zero and the computed delay
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(5),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(60),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Build a policy from `BOTD_MAX_ATTEMPTS`, `BOTD_RETRY_BASE_DELAY`, `BOTD_RETRY_MAX_DELAY`
+    /// (both in seconds), `BOTD_RETRY_MULTIPLIER`, and `BOTD_RETRY_JITTER`, falling back to the
+    /// defaults for any that are unset
+    pub fn from_env() -> Result<RetryPolicy, BirdError> {
+        let (policy, problems) = RetryPolicy::check_env();
+        match problems.is_empty() {
+            true => Ok(policy),
+            false => Err(BirdError::Config(problems.join("; "))),
+        }
+    }
+
+    /// The policy in the environment as far as it could be read, with every problem
+    /// [`RetryPolicy::from_env`] would fail on rather than just the first
+    pub fn check_env() -> (RetryPolicy, Vec<String>) {
+        RetryPolicy::check_vars(|name| std::env::var(name).ok())
+    }
+
+    fn check_vars(var: impl Fn(&str) -> Option<String>) -> (RetryPolicy, Vec<String>) {
+        let default = RetryPolicy::default();
+        let mut env = EnvReader::new(&var);
+        let policy = RetryPolicy {
+            max_attempts: env.parse("BOTD_MAX_ATTEMPTS", default.max_attempts),
+            base_delay: Duration::from_secs(env.parse("BOTD_RETRY_BASE_DELAY", default.base_delay.as_secs())),
+            max_delay: Duration::from_secs(env.parse("BOTD_RETRY_MAX_DELAY", default.max_delay.as_secs())),
+            multiplier: env.parse("BOTD_RETRY_MULTIPLIER", default.multiplier),
+            jitter: env.parse("BOTD_RETRY_JITTER", default.jitter),
+        };
+        let mut problems = env.problems;
+        if policy.max_attempts == 0 {
+            problems.push("BOTD_MAX_ATTEMPTS must be at least 1".to_string());
+        }
+        if !policy.multiplier.is_finite() || policy.multiplier < 1.0 {
+            problems.push("BOTD_RETRY_MULTIPLIER must be at least 1".to_string());
+        }
+        (policy, problems)
+    }
+
+    /// Every setting by variable, with the value in effect, as for [`Config::settings`](crate::Config::settings)
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("BOTD_MAX_ATTEMPTS", self.max_attempts.to_string()),
+            ("BOTD_RETRY_BASE_DELAY", self.base_delay.as_secs().to_string()),
+            ("BOTD_RETRY_MAX_DELAY", self.max_delay.as_secs().to_string()),
+            ("BOTD_RETRY_MULTIPLIER", self.multiplier.to_string()),
+            ("BOTD_RETRY_JITTER", self.jitter.to_string()),
+        ]
+    }
+
+    /// The delay to wait after the given (1-based) failed attempt, before jitter is applied
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let secs = self.base_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        // Anything past the cap (including an overflow to infinity) is just the cap
+        if secs >= self.max_delay.as_secs_f64() {
+            return self.max_delay;
+        }
+        Duration::from_secs_f64(secs)
+    }
+
+    /// Run `op` until it succeeds, fails with an error that is not worth retrying, or runs out of attempts.
+    /// `op` is given the current (1-based) attempt number and `sleep` is called between attempts.
+    pub fn retry<T, F, S>(&self, mut op: F, mut sleep: S) -> Result<T, BirdError>
+    where
+        F: FnMut(u32) -> Result<T, BirdError>,
+        S: FnMut(Duration),
+    {
+        let mut attempt = 1;
+        loop {
+            match op(attempt) {
+                Ok(t) => return Ok(t),
+                Err(e) if !e.is_retryable() => {
+                    if attempt < self.max_attempts {
+                        warn!("Attempt {} of {} failed with an error that retrying can't fix, giving up", attempt, self.max_attempts);
+                    }
+                    return Err(e);
+                }
+                Err(e) if attempt >= self.max_attempts => {
+                    if self.max_attempts > 1 {
+                        warn!("Attempt {} of {} failed, giving up", attempt, self.max_attempts);
+                    }
+                    return Err(e);
+                }
+                Err(e) => {
+                    let mut delay = self.delay_for(attempt);
+                    if self.jitter && !delay.is_zero() {
+                        delay = rand::thread_rng().gen_range(Duration::ZERO..=delay);
+                    }
+                    // Never go back before the server said we could
+                    if let Some(retry_after) = e.retry_after() {
+                        delay = delay.max(retry_after);
+                    }
+                    warn!("Attempt {} of {} failed, retrying in {:?}: {}", attempt, self.max_attempts, delay, e);
+                    sleep(delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_secs(2),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(10),
+            jitter: false,
+        }
+    }
+
+    fn transient() -> BirdError {
+        BirdError::Ebird { status: 503 }
+    }
+
+    #[test]
+    fn backoff_doubles_and_caps() {
+        let mut sleeps = Vec::new();
+        let mut attempts = 0;
+        let r: Result<(), _> = policy(5).retry(|_| { attempts += 1; Err(transient()) }, |d| sleeps.push(d));
+        assert!(r.is_err());
+        assert_eq!(attempts, 5);
+        assert_eq!(sleeps, [2, 4, 8, 10].map(Duration::from_secs));
+    }
+
+    #[test]
+    fn multiplier_sets_the_growth() {
+        let p = RetryPolicy { multiplier: 3.0, max_delay: Duration::from_secs(100), ..policy(5) };
+        let mut sleeps = Vec::new();
+        let _: Result<(), _> = p.retry(|_| Err(transient()), |d| sleeps.push(d));
+        assert_eq!(sleeps, [2, 6, 18, 54].map(Duration::from_secs));
+        assert_eq!(p.delay_for(u32::MAX), Duration::from_secs(100));
+    }
+
+    #[test]
+    fn stops_after_success() {
+        let mut sleeps = Vec::new();
+        let r = policy(5).retry(|n| if n < 3 { Err(transient()) } else { Ok(n) }, |d| sleeps.push(d));
+        assert_eq!(r.unwrap(), 3);
+        assert_eq!(sleeps.len(), 2);
+    }
+
+    #[test]
+    fn does_not_retry_client_errors() {
+        let mut attempts = 0;
+        let r: Result<(), _> = policy(5).retry(
+            |_| { attempts += 1; Err(BirdError::Auth { status: Some(401), reason: None, message: String::new() }) },
+            |_| panic!("should not sleep"),
+        );
+        assert!(r.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn does_not_retry_missing_env() {
+        let mut attempts = 0;
+        let r: Result<(), _> = policy(5).retry(
+            |_| { attempts += 1; Err(BirdError::MissingEnv(vec!["BOTD_PASS".to_string()])) },
+            |_| panic!("should not sleep"),
+        );
+        assert!(r.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn rate_limits_stretch_the_delay() {
+        let mut sleeps = Vec::new();
+        let _: Result<(), _> = policy(2).retry(
+            |_| Err(BirdError::RateLimited { retry_after: Duration::from_secs(90) }),
+            |d| sleeps.push(d),
+        );
+        assert_eq!(sleeps, [Duration::from_secs(90)]);
+    }
+
+    #[test]
+    fn jitter_stays_within_delay() {
+        let mut p = policy(4);
+        p.jitter = true;
+        let mut sleeps = Vec::new();
+        let _: Result<(), _> = p.retry(|_| Err(transient()), |d| sleeps.push(d));
+        for (i, d) in sleeps.iter().enumerate() {
+            assert!(*d <= p.delay_for(i as u32 + 1));
+        }
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let (policy, problems) = RetryPolicy::check_vars(|name| match name {
+            "BOTD_RETRY_MULTIPLIER" => Some("0.5".to_string()),
+            "BOTD_RETRY_JITTER" => Some("sometimes".to_string()),
+            _ => None,
+        });
+        assert_eq!(problems, [
+            "'sometimes' is not a valid value for BOTD_RETRY_JITTER",
+            "BOTD_RETRY_MULTIPLIER must be at least 1",
+        ]);
+        assert!(policy.jitter);
+    }
+}
@@ -0,0 +1,169 @@
+use std::{fmt, str::FromStr};
+
+use time::Month;
+
+use crate::Bird;
+
+/// The built-in table, for the northern hemisphere: winter finches and waxwings in the winter,
+/// waterfowl and cranes on the move in early spring, warblers and flycatchers in May, shorebirds
+/// and raptors on their way south in the autumn, and owls for Halloween
+const NORTH: &str = "jan: Fringillidae=2, Bombycillidae=2, Alcidae=1.5; \
+    feb: Anatidae=2, Strigidae=1.5; \
+    mar: Anatidae=2, Gruidae=2, Scolopacidae=1.5; \
+    apr: Scolopacidae=2, Charadriidae=2, Hirundinidae=1.5; \
+    may: Parulidae=3, Vireonidae=2, Tyrannidae=2, Cardinalidae=2, Trochilidae=1.5; \
+    jun: Trochilidae=2, Alcidae=2, Turdidae=1.5; \
+    jul: Procellariidae=1.5, Laridae=1.5; \
+    aug: Scolopacidae=2, Charadriidae=2; \
+    sep: Accipitridae=2, Falconidae=2, Parulidae=1.5; \
+    oct: Strigidae=2, Corvidae=2, Anatidae=1.5; \
+    nov: Phasianidae=2, Anatidae=2; \
+    dec: Fringillidae=3, Paridae=2, Bombycillidae=2";
+
+/// The largest multiplier a table may give, which keeps the weights the pick adds up finite
+const MAX_MULTIPLIER: f64 = 1000.0;
+
+/// How much more likely birds of some families are to be picked in each month, e.g.
+/// `may: Parulidae=3, Vireonidae=2; dec: Fringillidae=3`. Families are named by their eBird
+/// code, common name, or scientific name, and months by their first three letters or number.
+/// A multiplier is more than 0 and at most 1000. `north` is a built-in table for the northern
+/// hemisphere, `south` the same table six months on, and `off`, the default, weighs every bird
+/// the same.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeasonalWeights {
+    source: String,
+    /// Lowercased family names and their multipliers, by month from January
+    months: [Vec<(String, f64)>; 12],
+}
+
+impl Default for SeasonalWeights {
+    fn default() -> Self {
+        SeasonalWeights { source: "off".to_string(), months: Default::default() }
+    }
+}
+
+impl FromStr for SeasonalWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut months = match s.trim() {
+            "off" => return Ok(SeasonalWeights::default()),
+            "north" | "south" => parse_table(NORTH).expect("the built-in seasonal weights are valid"),
+            table => parse_table(table)?,
+        };
+        if s.trim() == "south" {
+            months.rotate_right(6);
+        }
+        Ok(SeasonalWeights { source: s.trim().to_string(), months })
+    }
+}
+
+impl fmt::Display for SeasonalWeights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl SeasonalWeights {
+    /// Whether any family is weighted in `month`
+    pub fn applies_in(&self, month: Month) -> bool {
+        !self.months[month as usize - 1].is_empty()
+    }
+
+    /// The family `bird` is weighted for in `month`, as the table names it, and by how much
+    pub fn multiplier(&self, bird: &Bird, month: Month) -> Option<(&str, f64)> {
+        let names = [&bird.family_code, &bird.family_com_name, &bird.family_sci_name];
+        let names: Vec<String> = names.into_iter().flatten().map(|n| n.to_lowercase()).collect();
+        self.months[month as usize - 1].iter()
+            .find(|(family, _)| names.contains(family))
+            .map(|(family, m)| (family.as_str(), *m))
+    }
+
+    /// How much more likely `bird` is to be picked in `month` than a bird weighted the same
+    pub(crate) fn weight(&self, bird: &Bird, month: Month) -> f64 {
+        self.multiplier(bird, month).map_or(1.0, |(_, m)| m)
+    }
+}
+
+/// The family multipliers in `table` by month, as [`SeasonalWeights`] describes them
+fn parse_table(table: &str) -> Result<[Vec<(String, f64)>; 12], String> {
+    let mut months: [Vec<(String, f64)>; 12] = Default::default();
+    for entry in table.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (month, families) = entry.split_once(':')
+            .ok_or_else(|| format!("'{}' should be a month, a colon, and then families, e.g. may: Parulidae=3", entry))?;
+        let month = parse_month(month.trim())?;
+        for family in families.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let (name, multiplier) = family.rsplit_once('=')
+                .ok_or_else(|| format!("'{}' should be a family, an equals sign, and a multiplier, e.g. Parulidae=3", family))?;
+            let multiplier: f64 = multiplier.trim().parse()
+                .ok().filter(|m: &f64| *m > 0.0 && *m <= MAX_MULTIPLIER)
+                .ok_or_else(|| format!("the multiplier for {} has to be a number more than 0 and at most {}, not '{}'", name.trim(), MAX_MULTIPLIER, multiplier.trim()))?;
+            months[month as usize - 1].push((name.trim().to_lowercase(), multiplier));
+        }
+    }
+    Ok(months)
+}
+
+/// A month by its first three letters, its full name, or its number
+fn parse_month(s: &str) -> Result<Month, String> {
+    if let Ok(n) = s.parse::<u8>() {
+        return Month::try_from(n).map_err(|_| format!("there is no month {}", n));
+    }
+    let lower = s.to_lowercase();
+    (1..=12).map(|n| Month::try_from(n).unwrap())
+        .find(|m| {
+            let name = m.to_string().to_lowercase();
+            lower.len() >= 3 && name.starts_with(&lower)
+        })
+        .ok_or_else(|| format!("'{}' is not a month", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bird(family_code: &str, family_sci_name: &str) -> Bird {
+        serde_json::from_value(serde_json::json!({
+            "sciName": "Avis a", "comName": "Bird a", "speciesCode": "a", "category": "species", "taxonOrder": 1.0,
+            "familyCode": family_code, "familySciName": family_sci_name,
+        })).unwrap()
+    }
+
+    #[test]
+    fn families_are_weighted_by_month() {
+        let weights: SeasonalWeights = "May: parula1=3, Vireonidae = 2 ; 12: Fringillidae=1.5".parse().unwrap();
+        let warbler = bird("parula1", "Parulidae");
+        assert_eq!(weights.multiplier(&warbler, Month::May), Some(("parula1", 3.0)));
+        assert_eq!(weights.weight(&bird("vireon1", "Vireonidae"), Month::May), 2.0);
+        assert_eq!(weights.weight(&warbler, Month::June), 1.0);
+        assert_eq!(weights.multiplier(&bird("fringi1", "Fringillidae"), Month::December), Some(("fringillidae", 1.5)));
+        assert!(weights.applies_in(Month::December) && !weights.applies_in(Month::January));
+        assert_eq!(weights.to_string(), "May: parula1=3, Vireonidae = 2 ; 12: Fringillidae=1.5");
+    }
+
+    #[test]
+    fn built_in_table_follows_the_hemisphere() {
+        let warbler = bird("parula1", "Parulidae");
+        let north: SeasonalWeights = "north".parse().unwrap();
+        let south: SeasonalWeights = "south".parse().unwrap();
+        assert_eq!(north.weight(&warbler, Month::May), 3.0);
+        assert_eq!(south.weight(&warbler, Month::November), 3.0);
+        assert_eq!(south.weight(&warbler, Month::May), 1.0);
+        assert!((1..=12).all(|n| north.applies_in(Month::try_from(n).unwrap())));
+        assert_eq!(SeasonalWeights::default(), "off".parse().unwrap());
+        assert!(!SeasonalWeights::default().applies_in(Month::May));
+    }
+
+    #[test]
+    fn bad_tables_are_rejected() {
+        let e = |s: &str| s.parse::<SeasonalWeights>().unwrap_err();
+        assert!(e("may Parulidae=3").contains("a month, a colon"));
+        assert_eq!(e("ma: Parulidae=3"), "'ma' is not a month");
+        assert_eq!(e("13: Parulidae=3"), "there is no month 13");
+        assert!(e("may: Parulidae").contains("an equals sign"));
+        assert!(e("may: Parulidae=0").contains("more than 0 and at most 1000, not '0'"));
+        assert!(e("may: Parulidae=lots").contains("not 'lots'"));
+        assert!(e("may: Parulidae=1e308").contains("at most 1000, not '1e308'"));
+        assert!(e("may: Parulidae=inf").contains("not 'inf'"));
+    }
+}
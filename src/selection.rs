@@ -0,0 +1,359 @@
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use rand::{distributions::{Distribution, WeightedIndex}, Rng};
+use time::Month;
+
+use crate::{Bird, SeasonalWeights};
+
+/// Width of the `taxon_order` ranges that `WeightedByTaxon` picks between
+const TAXON_BUCKET: f32 = 1000.0;
+
+/// How many times a pick from a recently posted family is drawn again with
+/// [`FamilyRepeat::Deprioritize`]
+const FAMILY_REDRAWS: usize = 2;
+
+/// How the bird of the day is picked from the birds that weren't posted recently
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SelectionStrategy {
+    /// Every bird is as likely as any other
+    #[default]
+    Uniform,
+    /// Pick a stretch of the taxonomy, then a bird in it, so the parts of the tree crowded with
+    /// closely related birds don't come up more often than the rest
+    WeightedByTaxon,
+    /// Pick a family, then a bird in it, so small families aren't drowned out by huge ones. Each
+    /// of F families comes up 1/F of the time, and a bird in a family of n 1/(F·n) of it.
+    FamilyBalanced,
+    /// Pick an order, then a bird in it, the same as `FamilyBalanced` a level up the tree: an order
+    /// of one family is as likely as the passerines
+    OrderBalanced,
+    /// Walk through the whole taxonomy in a random order saved between runs, so every bird is
+    /// posted once before any is posted again (see [`ShuffleQueue`](crate::ShuffleQueue))
+    Shuffle,
+}
+
+impl FromStr for SelectionStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" | "random" => Ok(SelectionStrategy::Uniform),
+            "weighted-by-taxon" => Ok(SelectionStrategy::WeightedByTaxon),
+            "family-balanced" | "by_family" => Ok(SelectionStrategy::FamilyBalanced),
+            "order-balanced" | "by_order" => Ok(SelectionStrategy::OrderBalanced),
+            "shuffle" => Ok(SelectionStrategy::Shuffle),
+            _ => Err(format!("unknown selection strategy '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for SelectionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SelectionStrategy::Uniform => "uniform",
+            SelectionStrategy::WeightedByTaxon => "weighted-by-taxon",
+            SelectionStrategy::FamilyBalanced => "family-balanced",
+            SelectionStrategy::OrderBalanced => "order-balanced",
+            SelectionStrategy::Shuffle => "shuffle",
+        })
+    }
+}
+
+impl SelectionStrategy {
+    /// Pick one of `birds`, which must not be empty. `Shuffle` takes the next bird in its queue
+    /// rather than calling this, and is uniform without one.
+    pub(crate) fn pick<'a, R: Rng>(self, birds: &[&'a Bird], rng: &mut R) -> &'a Bird {
+        match self {
+            SelectionStrategy::Uniform | SelectionStrategy::Shuffle => pick_one(birds, rng),
+            _ => pick_grouped(&self.groups(birds), rng),
+        }
+    }
+
+    /// Like [`SelectionStrategy::pick`], but each bird as many times more likely as its `weight`
+    /// says: a group comes up as often as the mean weight of its birds, then a bird in it by weight
+    pub(crate) fn pick_weighted<'a, R: Rng>(self, birds: &[&'a Bird], weight: impl Fn(&Bird) -> f64, rng: &mut R) -> &'a Bird {
+        let groups = self.groups(birds);
+        let means = groups.iter().map(|g| g.iter().map(|b| weight(b)).sum::<f64>() / g.len() as f64);
+        let group = &groups[WeightedIndex::new(means).expect("weights are more than 0").sample(rng)];
+        group[WeightedIndex::new(group.iter().map(|b| weight(b))).expect("weights are more than 0").sample(rng)]
+    }
+
+    /// `birds` split up into what the strategy picks between before picking a bird
+    fn groups<'a>(self, birds: &[&'a Bird]) -> Vec<Vec<&'a Bird>> {
+        match self {
+            SelectionStrategy::Uniform | SelectionStrategy::Shuffle => vec![birds.to_vec()],
+            SelectionStrategy::WeightedByTaxon => group_by(birds, |b| (b.taxon_order / TAXON_BUCKET).floor() as i64),
+            // Birds the taxonomy gives no family make up a family of their own
+            SelectionStrategy::FamilyBalanced => group_by(birds, |b| family_key(b).map(str::to_string)),
+            SelectionStrategy::OrderBalanced => group_by(birds, |b| b.order.clone()),
+        }
+    }
+}
+
+/// What to do about birds of a family posted within `BOTD_FAMILY_WINDOW` posts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FamilyRepeat {
+    /// Draw a bird of such a family again, up to twice, so one still comes up now and then
+    #[default]
+    Deprioritize,
+    /// Leave them out, unless there are no other birds to pick from
+    Exclude,
+}
+
+impl FromStr for FamilyRepeat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deprioritize" => Ok(FamilyRepeat::Deprioritize),
+            "exclude" => Ok(FamilyRepeat::Exclude),
+            _ => Err(format!("'{}' is not deprioritize or exclude", s)),
+        }
+    }
+}
+
+impl fmt::Display for FamilyRepeat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FamilyRepeat::Deprioritize => "deprioritize",
+            FamilyRepeat::Exclude => "exclude",
+        })
+    }
+}
+
+/// What the pick leans towards or away from on top of its strategy: the families of the birds
+/// posted recently, as `repeat` says, and the families `season` weighs for its month
+#[derive(Debug, Default)]
+pub(crate) struct Preferences<'a> {
+    pub(crate) recent_families: HashSet<&'a str>,
+    pub(crate) repeat: FamilyRepeat,
+    pub(crate) season: Option<(&'a SeasonalWeights, Month)>,
+}
+
+impl Preferences<'_> {
+    fn recent(&self, bird: &Bird) -> bool {
+        family_key(bird).is_some_and(|f| self.recent_families.contains(f))
+    }
+
+    /// Pick one of `birds` as `strategy` has it, weighted for the season and staying away from
+    /// the recent families. `None` if they are to be left out and every one of `birds` is of one
+    /// of them.
+    pub(crate) fn pick<'b, R: Rng>(&self, strategy: SelectionStrategy, birds: &[&'b Bird], rng: &mut R) -> Option<&'b Bird> {
+        match self.repeat {
+            FamilyRepeat::Exclude => {
+                let others: Vec<&Bird> = birds.iter().copied().filter(|b| !self.recent(b)).collect();
+                (!others.is_empty()).then(|| self.pick_in_season(strategy, &others, rng))
+            }
+            FamilyRepeat::Deprioritize => {
+                let mut b = self.pick_in_season(strategy, birds, rng);
+                for _ in 0..FAMILY_REDRAWS {
+                    if !self.recent(b) {
+                        break;
+                    }
+                    b = self.pick_in_season(strategy, birds, rng);
+                }
+                Some(b)
+            }
+        }
+    }
+
+    /// Pick one of `birds` as `strategy` has it, weighted by the season if it weighs any family
+    pub(crate) fn pick_in_season<'b, R: Rng>(&self, strategy: SelectionStrategy, birds: &[&'b Bird], rng: &mut R) -> &'b Bird {
+        match self.season {
+            Some((weights, month)) if weights.applies_in(month) => strategy.pick_weighted(birds, |b| weights.weight(b, month), rng),
+            _ => strategy.pick(birds, rng),
+        }
+    }
+}
+
+/// What a bird's family is known by: its code, or without one its scientific name
+pub(crate) fn family_key(bird: &Bird) -> Option<&str> {
+    bird.family_code.as_deref().or(bird.family_sci_name.as_deref())
+}
+
+/// Any one of `items`, uniformly
+fn pick_one<T: Copy, R: Rng>(items: &[T], rng: &mut R) -> T {
+    // Sample a u64 rather than a usize so 32 and 64-bit targets pick the same bird
+    items[rng.gen_range(0..items.len() as u64) as usize]
+}
+
+/// `birds` split up by `key`, in the order each group first appears
+fn group_by<'a, K: PartialEq>(birds: &[&'a Bird], key: impl Fn(&Bird) -> K) -> Vec<Vec<&'a Bird>> {
+    let mut groups: Vec<(K, Vec<&Bird>)> = Vec::new();
+    for &b in birds {
+        let k = key(b);
+        match groups.iter_mut().find(|(g, _)| *g == k) {
+            Some((_, group)) => group.push(b),
+            None => groups.push((k, vec![b])),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// Pick a group, then a bird in it
+fn pick_grouped<'a, R: Rng>(groups: &[Vec<&'a Bird>], rng: &mut R) -> &'a Bird {
+    let group = &groups[rng.gen_range(0..groups.len() as u64) as usize];
+    pick_one(group, rng)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+
+    use super::*;
+
+    const DRAWS: usize = 20_000;
+
+    fn bird(code: &str, taxon_order: f32, family: &str) -> Bird {
+        serde_json::from_value(json!({
+            "sciName": code, "comName": code, "speciesCode": code, "category": "species",
+            "taxonOrder": taxon_order, "familyCode": family, "order": format!("{}formes", family),
+        })).unwrap()
+    }
+
+    /// One family of 1 bird and one of 9, the big one spread over two stretches of the taxonomy
+    fn birds() -> Vec<Bird> {
+        let mut birds = vec![bird("loner", 10.0, "small")];
+        birds.extend((0..9).map(|i| bird(&format!("big{}", i), 1500.0 + i as f32 * 100.0, "big")));
+        birds
+    }
+
+    /// How often each bird came up in `DRAWS` picks, as a fraction
+    fn frequencies(strategy: SelectionStrategy) -> HashMap<String, f64> {
+        frequencies_in(&birds(), strategy, |b| b.species_code.clone())
+    }
+
+    /// How often each `key` of `birds` came up in `DRAWS` picks, as a fraction
+    fn frequencies_in(birds: &[Bird], strategy: SelectionStrategy, key: impl Fn(&Bird) -> String) -> HashMap<String, f64> {
+        let refs: Vec<&Bird> = birds.iter().collect();
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut counts = HashMap::new();
+        for _ in 0..DRAWS {
+            *counts.entry(key(strategy.pick(&refs, &mut rng))).or_insert(0.0) += 1.0 / DRAWS as f64;
+        }
+        counts
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.02, "{} is not close to {}", actual, expected);
+    }
+
+    #[test]
+    fn uniform_picks_every_bird_as_often() {
+        let f = frequencies(SelectionStrategy::Uniform);
+        assert_near(f["loner"], 0.1);
+        assert_near(f["big4"], 0.1);
+    }
+
+    #[test]
+    fn family_balanced_picks_every_family_as_often() {
+        let f = frequencies(SelectionStrategy::FamilyBalanced);
+        assert_near(f["loner"], 0.5);
+        assert_near(f["big0"], 0.5 / 9.0);
+    }
+
+    #[test]
+    fn family_balanced_evens_out_families_of_any_size() {
+        // Families of 1 to 40 birds, the way a few huge families dwarf the rest of the taxonomy
+        let sizes = [1, 2, 3, 5, 8, 40];
+        let birds: Vec<Bird> = sizes.iter()
+            .flat_map(|&n| (0..n).map(move |i| bird(&format!("f{}b{}", n, i), 0.0, &format!("f{}", n))))
+            .collect();
+        let f = frequencies_in(&birds, SelectionStrategy::FamilyBalanced, |b| b.family_code.clone().unwrap());
+        for n in sizes {
+            assert_near(f[&format!("f{}", n)], 1.0 / sizes.len() as f64);
+        }
+        let f = frequencies_in(&birds, SelectionStrategy::Uniform, |b| b.family_code.clone().unwrap());
+        assert_near(f["f40"], 40.0 / 59.0);
+    }
+
+    #[test]
+    fn order_balanced_picks_every_order_as_often() {
+        let mut birds = birds();
+        birds.push(bird("other", 20.0, "other"));
+        birds[1].order = Some("smallformes".to_string());
+        // smallformes now has loner and big0, bigformes the other 8 big birds
+        let f = frequencies_in(&birds, SelectionStrategy::OrderBalanced, |b| b.order.clone().unwrap());
+        assert_near(f["otherformes"], 1.0 / 3.0);
+        assert_near(f["smallformes"], 1.0 / 3.0);
+        assert_near(f["bigformes"], 1.0 / 3.0);
+    }
+
+    #[test]
+    fn recent_families_are_drawn_again_or_left_out() {
+        let birds = birds();
+        let refs: Vec<&Bird> = birds.iter().collect();
+        let mut recent = Preferences { recent_families: HashSet::from(["big"]), ..Preferences::default() };
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut loner = 0.0;
+        for _ in 0..DRAWS {
+            if recent.pick(SelectionStrategy::Uniform, &refs, &mut rng).unwrap().species_code == "loner" {
+                loner += 1.0 / DRAWS as f64;
+            }
+        }
+        // A big one has to come up three times running to stick
+        assert_near(loner, 1.0 - 0.9f64.powi(3));
+
+        recent.repeat = FamilyRepeat::Exclude;
+        for _ in 0..100 {
+            assert_eq!(recent.pick(SelectionStrategy::FamilyBalanced, &refs, &mut rng).unwrap().species_code, "loner");
+        }
+        recent.recent_families.insert("small");
+        assert!(recent.pick(SelectionStrategy::Uniform, &refs, &mut rng).is_none());
+    }
+
+    #[test]
+    fn seasonal_weights_multiply_how_often_a_bird_comes_up() {
+        let weights: SeasonalWeights = "may: small=9; jun: big=3".parse().unwrap();
+        let in_month = |month, strategy| {
+            let birds = birds();
+            let refs: Vec<&Bird> = birds.iter().collect();
+            let preferences = Preferences { season: Some((&weights, month)), ..Preferences::default() };
+            let mut rng = StdRng::seed_from_u64(7);
+            let mut loner = 0.0;
+            for _ in 0..DRAWS {
+                if preferences.pick(strategy, &refs, &mut rng).unwrap().species_code == "loner" {
+                    loner += 1.0 / DRAWS as f64;
+                }
+            }
+            loner
+        };
+        // Uniform: the loner counts as 9 birds against the big family's 9
+        assert_near(in_month(Month::May, SelectionStrategy::Uniform), 0.5);
+        // Family-balanced: the big family counts as 3 families against the small one's 1
+        assert_near(in_month(Month::June, SelectionStrategy::FamilyBalanced), 0.25);
+        assert_near(in_month(Month::July, SelectionStrategy::FamilyBalanced), 0.5);
+        assert_near(in_month(Month::May, SelectionStrategy::OrderBalanced), 0.9);
+    }
+
+    #[test]
+    fn parses_family_repeat() {
+        assert_eq!("exclude".parse(), Ok(FamilyRepeat::Exclude));
+        assert_eq!(FamilyRepeat::default().to_string().parse(), Ok(FamilyRepeat::Deprioritize));
+        assert!("avoid".parse::<FamilyRepeat>().is_err());
+    }
+
+    #[test]
+    fn weighted_by_taxon_picks_every_stretch_as_often() {
+        // 10 falls in 0..1000, 1500..=1900 in 1000..2000, and 2000..=2300 in 2000..3000
+        let f = frequencies(SelectionStrategy::WeightedByTaxon);
+        assert_near(f["loner"], 1.0 / 3.0);
+        assert_near(f["big0"], 1.0 / 3.0 / 5.0);
+        assert_near(f["big8"], 1.0 / 3.0 / 4.0);
+    }
+
+    #[test]
+    fn parses_strategy_names() {
+        assert_eq!("family-balanced".parse(), Ok(SelectionStrategy::FamilyBalanced));
+        assert_eq!("random".parse(), Ok(SelectionStrategy::Uniform));
+        assert_eq!("by_family".parse(), Ok(SelectionStrategy::FamilyBalanced));
+        assert_eq!("by_order".parse(), Ok(SelectionStrategy::OrderBalanced));
+        assert_eq!(SelectionStrategy::OrderBalanced.to_string().parse(), Ok(SelectionStrategy::OrderBalanced));
+        assert_eq!(SelectionStrategy::Shuffle.to_string().parse(), Ok(SelectionStrategy::Shuffle));
+        assert!("popular".parse::<SelectionStrategy>().is_err());
+        assert_eq!(SelectionStrategy::WeightedByTaxon.to_string().parse(), Ok(SelectionStrategy::WeightedByTaxon));
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{BirdError, Token};
+
+/// How long a Bluesky access token is taken to last when it doesn't say itself
+const ACCESS_TOKEN_LIFETIME: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// How long before it expires an access token is renewed, so it doesn't run out halfway through a post
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// The Bluesky session of a process, reused for every post it makes until its access token is
+/// about to expire
+#[derive(Default)]
+pub(crate) struct SessionCache {
+    session: Option<(Token, SystemTime)>,
+}
+
+impl SessionCache {
+    /// The session, if there is one yet, and whether its access token is still good at `now`
+    pub fn get(&self, now: SystemTime) -> Option<(&Token, bool)> {
+        let (token, expires) = self.session.as_ref()?;
+        Some((token, now + EXPIRY_MARGIN < *expires))
+    }
+
+    /// Keep `token` for the next post. Its access token expires when its `exp` claim says, or
+    /// [`ACCESS_TOKEN_LIFETIME`] after `now` if it can't be read, unless it is the one kept already.
+    pub fn set(&mut self, token: Token, now: SystemTime) {
+        let expires = match &self.session {
+            Some((kept, expires)) if kept.token == token.token => *expires,
+            _ => jwt_expiry(&token.token).unwrap_or(now + ACCESS_TOKEN_LIFETIME),
+        };
+        self.session = Some((token, expires));
+    }
+}
+
+/// When a JWT expires, from the `exp` claim in its payload
+fn jwt_expiry(jwt: &str) -> Option<SystemTime> {
+    let payload = jwt.split('.').nth(1)?;
+    let claims: Value = serde_json::from_slice(&base64url_decode(payload)?).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(claims.get("exp")?.as_u64()?))
+}
+
+/// Decode base64url, as the parts of a JWT are encoded, with or without padding
+fn base64url_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+/// A Bluesky session saved between runs
+#[derive(Serialize, Deserialize)]
+struct SavedSession {
+    /// Handle the session was created for, so a change of account invalidates it
+    handle: String,
+    #[serde(rename = "accessJwt")]
+    access_jwt: String,
+    #[serde(rename = "refreshJwt")]
+    refresh_jwt: String,
+    did: String,
+}
+
+/// Load the saved session for `handle`, if there is one
+pub(crate) fn load(path: &Path, handle: &str) -> Option<Token> {
+    let contents = fs::read_to_string(path).ok()?;
+    let saved: SavedSession = serde_json::from_str(&contents).ok()?;
+    if saved.handle != handle {
+        return None;
+    }
+    Some(Token { token: saved.access_jwt, refresh_jwt: saved.refresh_jwt, did: saved.did })
+}
+
+/// Save the session for `handle`, readable only by the current user
+pub(crate) fn save(path: &Path, handle: &str, token: &Token) -> Result<(), BirdError> {
+    let saved = SavedSession {
+        handle: handle.to_string(),
+        access_jwt: token.token.clone(),
+        refresh_jwt: token.refresh_jwt.clone(),
+        did: token.did.clone(),
+    };
+    let json = serde_json::to_string(&saved)
+        .map_err(|e| BirdError::Parse(format!("Error converting session to JSON: {}", e)))?;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // `mode` only applies to new files, so tighten up an existing one too
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(access: &str) -> Token {
+        Token { token: access.to_string(), refresh_jwt: "refresh".to_string(), did: "did:plc:test".to_string() }
+    }
+
+    #[test]
+    fn expiry_is_read_from_the_access_token() {
+        // {"alg":"ES256K"}.{"scope":"com.atproto.access","sub":"did:plc:test","exp":1714824000}
+        let jwt = "eyJhbGciOiJFUzI1NksifQ.eyJzY29wZSI6ImNvbS5hdHByb3RvLmFjY2VzcyIsInN1YiI6ImRpZDpwbGM6dGVzdCIsImV4cCI6MTcxNDgyNDAwMH0.c2ln";
+        assert_eq!(jwt_expiry(jwt), Some(UNIX_EPOCH + Duration::from_secs(1_714_824_000)));
+        assert_eq!(base64url_decode("aGk_-w").unwrap(), b"hi?\xfb");
+        assert_eq!(base64url_decode("aGk=").unwrap(), b"hi");
+        assert_eq!(jwt_expiry("jwt"), None);
+        assert_eq!(jwt_expiry("a.b*c.d"), None);
+
+        let mut cache = SessionCache::default();
+        assert!(cache.get(UNIX_EPOCH).is_none());
+        cache.set(token(jwt), UNIX_EPOCH);
+        let expires = UNIX_EPOCH + Duration::from_secs(1_714_824_000);
+        assert!(cache.get(expires - EXPIRY_MARGIN * 2).unwrap().1);
+        assert!(!cache.get(expires - EXPIRY_MARGIN).unwrap().1);
+    }
+
+    #[test]
+    fn unreadable_token_is_given_the_usual_lifetime_once() {
+        let mut cache = SessionCache::default();
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        cache.set(token("opaque"), start);
+        assert!(cache.get(start + ACCESS_TOKEN_LIFETIME / 2).unwrap().1);
+
+        // Keeping the same token again after a post doesn't push its expiry back
+        cache.set(token("opaque"), start + ACCESS_TOKEN_LIFETIME / 2);
+        assert!(!cache.get(start + ACCESS_TOKEN_LIFETIME).unwrap().1);
+
+        cache.set(token("renewed"), start + ACCESS_TOKEN_LIFETIME);
+        let (kept, valid) = cache.get(start + ACCESS_TOKEN_LIFETIME).unwrap();
+        assert_eq!((kept.token.as_str(), valid), ("renewed", true));
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::ErrorKind,
+    path::Path,
+};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{Bird, BirdCatalog, BirdError};
+
+/// The order [`SelectionStrategy::Shuffle`](crate::SelectionStrategy::Shuffle) walks the taxonomy
+/// in, so that every bird is posted once before any is posted again, saved between runs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShuffleQueue {
+    /// Species codes of the birds still to come this time through, in the order they come up
+    pub remaining: Vec<String>,
+    /// Species codes of the birds posted this time through, in the order they were posted
+    pub posted: Vec<String>,
+}
+
+impl ShuffleQueue {
+    /// Load the queue file, treating a file that doesn't exist yet as an empty queue
+    pub fn load(path: impl AsRef<Path>) -> Result<ShuffleQueue, BirdError> {
+        let path = path.as_ref();
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ShuffleQueue::default()),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_str(&contents)
+            .map_err(|e| BirdError::Parse(format!("'{}' is not a valid shuffle queue: {}", path.display(), e)))
+    }
+
+    /// Write the queue file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BirdError> {
+        let json = serde_json::to_string(self)
+            .map_err(|e| BirdError::Parse(format!("Error converting shuffle queue to JSON: {}", e)))?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Bring the queue in line with `catalog`, e.g. after the taxonomy was updated: birds no
+    /// longer in it are dropped, and new ones go in at random places among those still to come,
+    /// leaving the rest where they were. An empty queue gets every bird in a random order.
+    pub fn sync<R: Rng>(&mut self, catalog: &BirdCatalog, rng: &mut R) {
+        let codes: HashSet<&str> = catalog.birds().iter().map(|b| b.species_code.as_str()).collect();
+        self.remaining.retain(|c| codes.contains(c.as_str()));
+        self.posted.retain(|c| codes.contains(c.as_str()));
+
+        let queued: HashSet<&str> = self.remaining.iter().chain(&self.posted).map(String::as_str).collect();
+        let mut new: Vec<String> = catalog.birds().iter()
+            .map(|b| b.species_code.as_str())
+            .filter(|c| !queued.contains(c))
+            .map(str::to_string)
+            .collect();
+        insert_randomly(&mut self.remaining, &mut new, rng);
+    }
+
+    /// The first bird still to come that is one of `birds`. If every one of `birds` was posted
+    /// this time through, they are put back among the birds still to come first, at random
+    /// places, and go round again. `None` only if `birds` is empty.
+    pub fn next<'a, R: Rng>(&mut self, birds: &[&'a Bird], rng: &mut R) -> Option<&'a Bird> {
+        let by_code: HashMap<&str, &'a Bird> = birds.iter().map(|&b| (b.species_code.as_str(), b)).collect();
+        let find = |remaining: &[String]| remaining.iter().find_map(|c| by_code.get(c.as_str()).copied());
+        if let Some(b) = find(&self.remaining) {
+            return Some(b);
+        }
+        let (mut again, posted) = self.posted.drain(..).partition(|c| by_code.contains_key(c.as_str()));
+        self.posted = posted;
+        insert_randomly(&mut self.remaining, &mut again, rng);
+        find(&self.remaining)
+    }
+
+    /// How many of `birds` are still to come this time through
+    pub fn remaining_of(&self, birds: &[&Bird]) -> usize {
+        let codes: HashSet<&str> = birds.iter().map(|b| b.species_code.as_str()).collect();
+        self.remaining.iter().filter(|c| codes.contains(c.as_str())).count()
+    }
+
+    /// Note that `species_code` was posted, moving it from the birds still to come to the end of
+    /// the birds posted
+    pub fn mark_posted(&mut self, species_code: &str) {
+        self.remaining.retain(|c| c != species_code);
+        self.posted.retain(|c| c != species_code);
+        self.posted.push(species_code.to_string());
+    }
+}
+
+/// Put `new` into `queue` in a random order and at random places, every arrangement as likely
+fn insert_randomly<R: Rng>(queue: &mut Vec<String>, new: &mut [String], rng: &mut R) {
+    if new.is_empty() {
+        return;
+    }
+    new.shuffle(rng);
+    // Where each new bird goes, as the number of old ones before it
+    let mut places: Vec<usize> = new.iter().map(|_| rng.gen_range(0..=queue.len() as u64) as usize).collect();
+    places.sort_unstable();
+
+    let old = std::mem::take(queue);
+    let mut new = new.iter().cloned().zip(places).peekable();
+    for (i, code) in old.into_iter().enumerate() {
+        while let Some((b, _)) = new.next_if(|(_, place)| *place == i) {
+            queue.push(b);
+        }
+        queue.push(code);
+    }
+    queue.extend(new.map(|(b, _)| b));
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+
+    use super::*;
+
+    fn catalog(codes: &[&str]) -> BirdCatalog {
+        let birds = codes.iter().map(|c| json!({
+            "sciName": c, "comName": c, "speciesCode": c, "category": "species", "taxonOrder": 1.0,
+        })).collect();
+        BirdCatalog::new(serde_json::from_value(serde_json::Value::Array(birds)).unwrap())
+    }
+
+    fn sorted(codes: &[String]) -> Vec<&str> {
+        let mut codes: Vec<&str> = codes.iter().map(String::as_str).collect();
+        codes.sort_unstable();
+        codes
+    }
+
+    #[test]
+    fn every_bird_comes_up_once_before_any_comes_up_again() {
+        let catalog = catalog(&["a", "b", "c", "d", "e"]);
+        let birds: Vec<&Bird> = catalog.birds().iter().collect();
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut queue = ShuffleQueue::default();
+        queue.sync(&catalog, &mut rng);
+        assert_eq!(sorted(&queue.remaining), ["a", "b", "c", "d", "e"]);
+
+        let mut first = Vec::new();
+        for _ in 0..5 {
+            let b = queue.next(&birds, &mut rng).unwrap();
+            first.push(b.species_code.clone());
+            queue.mark_posted(&b.species_code);
+        }
+        assert_eq!(first, queue.posted);
+        assert_eq!(sorted(&first), ["a", "b", "c", "d", "e"]);
+        assert_eq!(queue.remaining_of(&birds), 0);
+
+        // Round again, with the birds back in the queue
+        let b = queue.next(&birds, &mut rng).unwrap();
+        assert_eq!(queue.remaining.len(), 5);
+        assert!(queue.posted.is_empty());
+        queue.mark_posted(&b.species_code);
+        assert_eq!(queue.remaining_of(&birds), 4);
+    }
+
+    #[test]
+    fn filtered_birds_go_round_without_the_others() {
+        let catalog = catalog(&["a", "b", "c", "d"]);
+        let mut queue = ShuffleQueue { remaining: vec!["c".into(), "d".into()], posted: vec!["a".into(), "b".into()] };
+        let only_a: Vec<&Bird> = catalog.birds().iter().filter(|b| b.species_code == "a").collect();
+        let mut rng = StdRng::seed_from_u64(2);
+
+        assert_eq!(queue.next(&only_a, &mut rng).unwrap().species_code, "a");
+        assert_eq!(queue.posted, ["b"]);
+        assert_eq!(sorted(&queue.remaining), ["a", "c", "d"]);
+        assert!(queue.next(&[], &mut rng).is_none());
+    }
+
+    #[test]
+    fn taxonomy_updates_keep_the_place_in_the_queue() {
+        let mut queue = ShuffleQueue {
+            remaining: ["c", "gone", "d", "e"].map(String::from).to_vec(),
+            posted: ["a", "lumped", "b"].map(String::from).to_vec(),
+        };
+        queue.sync(&catalog(&["a", "b", "c", "d", "e", "new1", "new2"]), &mut StdRng::seed_from_u64(3));
+
+        assert_eq!(queue.posted, ["a", "b"]);
+        assert_eq!(sorted(&queue.remaining), ["c", "d", "e", "new1", "new2"]);
+        let old: Vec<&str> = queue.remaining.iter().map(String::as_str).filter(|c| !c.starts_with("new")).collect();
+        assert_eq!(old, ["c", "d", "e"]);
+    }
+
+    #[test]
+    fn new_birds_land_anywhere_in_the_queue() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut firsts = HashSet::new();
+        for _ in 0..200 {
+            let mut queue = vec!["a".to_string(), "b".to_string()];
+            insert_randomly(&mut queue, &mut ["x".to_string(), "y".to_string()], &mut rng);
+            assert_eq!(queue.len(), 4);
+            assert!(queue.iter().position(|c| c == "a") < queue.iter().position(|c| c == "b"));
+            firsts.insert(queue[0].clone());
+        }
+        assert_eq!(firsts, HashSet::from(["a", "x", "y"].map(String::from)));
+    }
+
+    #[test]
+    fn queue_survives_a_round_trip() {
+        let path = std::env::temp_dir().join(format!("botd-queue-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(ShuffleQueue::load(&path).unwrap(), ShuffleQueue::default());
+        let queue = ShuffleQueue { remaining: vec!["b".into()], posted: vec!["a".into()] };
+        queue.save(&path).unwrap();
+        assert_eq!(ShuffleQueue::load(&path).unwrap(), queue);
+        fs::write(&path, "[").unwrap();
+        assert!(matches!(ShuffleQueue::load(&path), Err(BirdError::Parse(_))));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{collections::HashSet, fs, io::ErrorKind, path::Path};
+
+use log::warn;
+
+use crate::{Bird, BirdCatalog, BirdError};
+
+/// Birds listed in a file in the data directory, like `blocklist.txt` and `allowlist.txt`: one
+/// species code or common name per line, with blank lines and anything after a `#` ignored
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpeciesList {
+    codes: HashSet<String>,
+}
+
+impl SpeciesList {
+    /// Read the list at `path`, or `None` if there is no such file. Lines that name no bird in
+    /// `catalog` are logged as warnings and otherwise left out.
+    pub fn load(path: impl AsRef<Path>, catalog: &BirdCatalog) -> Result<Option<SpeciesList>, BirdError> {
+        let path = path.as_ref();
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let (list, unknown) = SpeciesList::parse(&contents, catalog);
+        for (line, entry) in unknown {
+            warn!("'{}' line {}: '{}' is not a species code or common name in the taxonomy", path.display(), line, entry);
+        }
+        Ok(Some(list))
+    }
+
+    /// The list in `contents`, with the line numbers and entries of those that name no bird in
+    /// `catalog`
+    pub fn parse<'a>(contents: &'a str, catalog: &BirdCatalog) -> (SpeciesList, Vec<(usize, &'a str)>) {
+        let mut codes = HashSet::new();
+        let mut unknown = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            match catalog.by_species_code(entry).or_else(|| catalog.by_common_name(entry)) {
+                Some(b) => {
+                    codes.insert(b.species_code.clone());
+                }
+                None => unknown.push((i + 1, entry)),
+            }
+        }
+        (SpeciesList { codes }, unknown)
+    }
+
+    pub fn contains(&self, bird: &Bird) -> bool {
+        self.codes.contains(&bird.species_code)
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> BirdCatalog {
+        BirdCatalog::parse(include_str!("../tests/fixtures/birds.json")).unwrap()
+    }
+
+    #[test]
+    fn codes_and_common_names_are_listed() {
+        let catalog = catalog();
+        let b = &catalog.birds()[0];
+        let contents = format!("# sensitive species\n{}\n\n  {}  # broken photo page\nnotabird\n",
+            b.species_code, catalog.birds()[1].common_name.to_uppercase());
+
+        let (list, unknown) = SpeciesList::parse(&contents, &catalog);
+        assert_eq!(list.len(), 2);
+        assert!(list.contains(b) && list.contains(&catalog.birds()[1]));
+        assert!(!list.contains(&catalog.birds()[2]));
+        assert_eq!(unknown, [(5, "notabird")]);
+    }
+
+    #[test]
+    fn missing_file_is_no_list() {
+        let path = std::env::temp_dir().join(format!("botd-list-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(SpeciesList::load(&path, &catalog()).unwrap(), None);
+        fs::write(&path, "# nothing yet\n").unwrap();
+        assert!(SpeciesList::load(&path, &catalog()).unwrap().unwrap().is_empty());
+        let _ = fs::remove_file(&path);
+    }
+}
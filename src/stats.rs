@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{filter_birds, shuffle_queue, BirdCatalog, BirdError, Config, Exclusions};
+
+/// What a random pick is drawn from: the whole taxonomy, what the filters took out of it, and
+/// what is left, by order and family
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    /// Entries in the taxonomy
+    pub total: usize,
+    /// Entries in the taxonomy by category, e.g. `species` or `hybrid`
+    pub categories: BTreeMap<String, usize>,
+    /// eBird region whose species list the birds have to be on, if any
+    pub region: Option<String>,
+    pub excluded: Exclusions,
+    /// Birds left to pick from
+    pub remaining: usize,
+    /// With the `shuffle` selection, how many of the birds left to pick from are still to come
+    /// before the queue goes round again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queued: Option<usize>,
+    /// The birds left, by order in taxonomic order
+    pub orders: Vec<OrderCount>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct OrderCount {
+    pub order: String,
+    pub count: usize,
+    pub families: Vec<FamilyCount>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FamilyCount {
+    pub family: String,
+    pub count: usize,
+}
+
+/// Count up `catalog` and the birds the filters in `config` leave of it
+pub fn stats(config: &Config, catalog: &BirdCatalog) -> Result<Stats, BirdError> {
+    let mut categories = BTreeMap::new();
+    for b in catalog.birds() {
+        *categories.entry(b.category.clone()).or_insert(0) += 1;
+    }
+
+    let pool = filter_birds(config, catalog)?;
+    let queued = shuffle_queue(config, catalog, config.today())?.map(|queue| queue.remaining_of(&pool.birds));
+    let mut orders: Vec<OrderCount> = Vec::new();
+    for b in &pool.birds {
+        let order = b.order.as_deref().unwrap_or("no order");
+        let family = b.family_com_name.as_deref().or(b.family_sci_name.as_deref()).unwrap_or("no family");
+        // The taxonomy is sorted, so an order's birds come one after another, but stray ones are counted all the same
+        let o = match orders.iter().position(|o| o.order == order) {
+            Some(i) => &mut orders[i],
+            None => {
+                orders.push(OrderCount { order: order.to_string(), count: 0, families: Vec::new() });
+                orders.last_mut().unwrap()
+            }
+        };
+        o.count += 1;
+        match o.families.iter_mut().find(|f| f.family == family) {
+            Some(f) => f.count += 1,
+            None => o.families.push(FamilyCount { family: family.to_string(), count: 1 }),
+        }
+    }
+
+    Ok(Stats {
+        total: catalog.len(),
+        categories,
+        region: config.region.clone(),
+        excluded: pool.excluded,
+        remaining: pool.birds.len(),
+        queued,
+        orders,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_taxonomy_is_counted() {
+        let catalog = BirdCatalog::parse(include_str!("../tests/fixtures/birds.json")).unwrap();
+        let stats = stats(&Config::default(), &catalog).unwrap();
+
+        assert_eq!(stats.total, 8);
+        assert_eq!(stats.categories["species"], 4);
+        assert_eq!(stats.categories["hybrid"], 1);
+        assert_eq!(stats.excluded, Exclusions { category: 4, denied_category: 0, extinct: 1, blocklist: 0, allowlist: 0, family: 0, order: 0, region: 0 });
+        assert_eq!(stats.remaining, 3);
+        assert_eq!(stats.orders.iter().map(|o| o.count).sum::<usize>(), 3);
+
+        let config = Config { only_family: Some("Cardinalidae".to_string()), ..Config::default() };
+        let stats = super::stats(&config, &catalog).unwrap();
+        assert_eq!(stats.excluded.family, 2);
+        assert_eq!(stats.orders, [OrderCount {
+            order: "Passeriformes".to_string(),
+            count: 1,
+            families: vec![FamilyCount { family: "Cardinals and Allies".to_string(), count: 1 }],
+        }]);
+        assert_eq!(stats.queued, None);
+    }
+
+    #[test]
+    fn shuffle_queue_is_counted() {
+        let catalog = BirdCatalog::parse(include_str!("../tests/fixtures/birds.json")).unwrap();
+        let dir = std::env::temp_dir().join(format!("botd-stats-queue-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = Config { selection: crate::SelectionStrategy::Shuffle, ..Config::default() };
+        config.set_data_dir(&dir);
+        let _ = std::fs::remove_file(config.shuffle_queue_path());
+        assert_eq!(stats(&config, &catalog).unwrap().queued, Some(3));
+
+        let posted = filter_birds(&config, &catalog).unwrap().birds[0].species_code.clone();
+        let mut queue = crate::ShuffleQueue::load(config.shuffle_queue_path()).unwrap();
+        queue.mark_posted(&posted);
+        queue.save(config.shuffle_queue_path()).unwrap();
+        assert_eq!(stats(&config, &catalog).unwrap().queued, Some(2));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
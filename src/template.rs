@@ -0,0 +1,152 @@
+use std::{fmt, str::FromStr};
+
+use crate::Bird;
+
+/// The placeholders a [`Template`] can have, as written between the braces
+const PLACEHOLDERS: [&str; 6] = ["common", "sci", "family", "order", "species_code", "credit"];
+
+/// What the post text says about the bird, with placeholders for its names, e.g.
+/// `{common} ({sci}) — {family}\n\n{credit}`. `{credit}` is where the photos are credited and has
+/// to be there exactly once; `{family}` and `{order}` are left empty for a bird the taxonomy
+/// doesn't give one. `\n` starts a new line, and `{{` and `}}` are literal braces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(&'static str),
+}
+
+impl Default for Template {
+    /// The names, then the credits a paragraph below
+    fn default() -> Self {
+        r"{common} ({sci})\n\n{credit}".parse().expect("the default template is valid")
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    text.push(c);
+                    chars.next();
+                }
+                ('\\', Some('n')) => {
+                    text.push('\n');
+                    chars.next();
+                }
+                ('{', _) => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed {{{} in the post template, write {{{{ for a literal {{", name)),
+                        }
+                    }
+                    let Some(&placeholder) = PLACEHOLDERS.iter().find(|&&p| p == name) else {
+                        return Err(format!("unknown placeholder {{{}}} in the post template, expected one of {}",
+                            name, known_placeholders()));
+                    };
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                    parts.push(Part::Placeholder(placeholder));
+                }
+                ('}', _) => return Err("unmatched } in the post template, write }} for a literal one".to_string()),
+                _ => text.push(c),
+            }
+        }
+        parts.push(Part::Text(text));
+        parts.retain(|p| *p != Part::Text(String::new()));
+        match parts.iter().filter(|&p| *p == Part::Placeholder("credit")).count() {
+            1 => Ok(Template { source: s.to_string(), parts }),
+            0 => Err("the post template has no {credit}, where the photos are credited".to_string()),
+            _ => Err("the post template has {credit} more than once".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Template {
+    /// Append the text for `bird` to `text`, with `credit` appending the credits in their place
+    pub(crate) fn render(&self, bird: &Bird, text: &mut String, mut credit: impl FnMut(&mut String)) {
+        for part in &self.parts {
+            match part {
+                Part::Text(s) => text.push_str(s),
+                Part::Placeholder("common") => text.push_str(&bird.common_name),
+                Part::Placeholder("sci") => text.push_str(&bird.scientific_name),
+                Part::Placeholder("family") => text.push_str(bird.family_com_name.as_deref().unwrap_or_default()),
+                Part::Placeholder("order") => text.push_str(bird.order.as_deref().unwrap_or_default()),
+                Part::Placeholder("species_code") => text.push_str(&bird.species_code),
+                Part::Placeholder(_) => credit(text),
+            }
+        }
+    }
+}
+
+/// The placeholders as they would be written in a template, e.g. `{common}, {sci}`
+fn known_placeholders() -> String {
+    PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bird() -> Bird {
+        serde_json::from_value(serde_json::json!({
+            "sciName": "Cardinalis cardinalis", "comName": "Northern Cardinal", "speciesCode": "norcar",
+            "category": "species", "taxonOrder": 1.0, "order": "Passeriformes",
+            "familyComName": "Cardinals and Allies",
+        })).unwrap()
+    }
+
+    fn render(template: &str, bird: &Bird) -> String {
+        let mut text = String::new();
+        template.parse::<Template>().unwrap().render(bird, &mut text, |t| t.push_str("Image Credit"));
+        text
+    }
+
+    #[test]
+    fn placeholders_are_filled_in_from_the_bird() {
+        assert_eq!(render(r"{common} ({sci}) — {family}\n\n{credit}", &bird()),
+            "Northern Cardinal (Cardinalis cardinalis) — Cardinals and Allies\n\nImage Credit");
+        assert_eq!(render("{{{species_code}}} {order}: {credit}", &bird()), "{norcar} Passeriformes: Image Credit");
+
+        let mut b = bird();
+        b.family_com_name = None;
+        assert_eq!(render("{common} ({family}) {credit}", &b), "Northern Cardinal () Image Credit");
+    }
+
+    #[test]
+    fn default_is_the_names_then_the_credit() {
+        let mut text = String::new();
+        Template::default().render(&bird(), &mut text, |t| t.push_str("Image Credit"));
+        assert_eq!(text, "Northern Cardinal (Cardinalis cardinalis)\n\nImage Credit");
+    }
+
+    #[test]
+    fn bad_templates_are_rejected() {
+        let e = "{common} {genus} {credit}".parse::<Template>().unwrap_err();
+        assert!(e.starts_with("unknown placeholder {genus}"), "{}", e);
+        assert!(e.contains("{species_code}"), "{}", e);
+        assert!("{common} {credit".parse::<Template>().unwrap_err().starts_with("unclosed {credit"));
+        assert!("{common}} {credit}".parse::<Template>().unwrap_err().starts_with("unmatched }"));
+        assert!("{common}".parse::<Template>().unwrap_err().contains("no {credit}"));
+        assert!("{credit} {credit}".parse::<Template>().unwrap_err().contains("more than once"));
+    }
+}
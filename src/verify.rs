@@ -0,0 +1,179 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    authenticate,
+    http::{self, send, HttpError},
+    BirdError, Config,
+};
+
+/// The outcome of one of the checks made by [`verify`]
+#[derive(Debug)]
+pub struct Check {
+    /// What was checked, e.g. `eBird API key`
+    pub name: &'static str,
+    /// What was found out if the check passed
+    pub result: Result<String, BirdError>,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// What to do about a failed check, if there is anything more useful to say than the error
+    pub fn hint(&self) -> Option<&'static str> {
+        let e = self.result.as_ref().err()?;
+        match (self.name, e) {
+            (_, BirdError::Network(_)) => Some("Check the network connection, and any proxy or firewall in the way"),
+            (EBIRD, BirdError::Ebird { status: 401 | 403 }) =>
+                Some("Check EBIRD_API_KEY; a key can be requested at https://ebird.org/api/keygen"),
+            // the error already says what to do about the failures Bluesky names
+            (BLUESKY, BirdError::Auth { reason: Some(_), .. }) => None,
+            (BLUESKY, BirdError::Auth { status: Some(400 | 401), .. }) =>
+                Some("Check BOTD_HANDLE and BOTD_PASS; use an app password from Settings > Privacy and security > App passwords"),
+            (BLUESKY, BirdError::Auth { status: Some(_), .. }) => Some("Bluesky may be having problems; try again later"),
+            (DATA_DIR, BirdError::Io(_)) =>
+                Some("Create the directory or fix its permissions, or point --data-dir or BOTD_DATA_DIR somewhere writable"),
+            _ => None,
+        }
+    }
+}
+
+const EBIRD: &str = "eBird API key";
+const BLUESKY: &str = "Bluesky login";
+const DATA_DIR: &str = "Data directory";
+
+/// Check, without posting anything, that the eBird API key and Bluesky credentials work and
+/// that the bot can write its files
+pub fn verify(config: &Config) -> Vec<Check> {
+    vec![
+        Check { name: EBIRD, result: check_ebird(config) },
+        Check { name: BLUESKY, result: check_bluesky(config) },
+        Check { name: DATA_DIR, result: check_data_dirs(config) },
+    ]
+}
+
+/// Problems with `config` that reading it from the environment doesn't catch: a handle that
+/// isn't shaped like one, and files that can't be written. Makes no requests.
+pub fn check_config(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Some(problem) = handle_problem(&config.handle) {
+        problems.push(format!("BOTD_HANDLE '{}' {}", config.handle, problem));
+    }
+    if let Err(e) = check_data_dirs(config) {
+        problems.push(e.to_string());
+    }
+    problems
+}
+
+/// What is wrong with a Bluesky handle, e.g. `bird.bsky.social`, or a DID given instead of one
+fn handle_problem(handle: &str) -> Option<&'static str> {
+    if handle.is_empty() || handle.starts_with("did:") {
+        return None;
+    }
+    if handle.starts_with('@') {
+        return Some("should be given without the '@'");
+    }
+    let labels: Vec<&str> = handle.split('.').collect();
+    let valid_label = |l: &&str| {
+        !l.is_empty() && !l.starts_with('-') && !l.ends_with('-') && l.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    if labels.len() < 2 || !labels.iter().all(valid_label) {
+        return Some("is not a handle, which looks like a domain name, e.g. bird.bsky.social");
+    }
+    None
+}
+
+/// Ask eBird for a single taxonomy entry, which needs a valid API key
+fn check_ebird(config: &Config) -> Result<String, BirdError> {
+    config.ebird_rate_limit.acquire();
+    let url = format!("{}/v2/ref/taxonomy/ebird?fmt=json&species=norcar", config.ebird_api_url);
+    let r = send(config, || {
+        http::get(config, &url, config.taxonomy_timeout)
+            .with_header("X-eBirdApiToken", &config.ebird_api_key)
+    })?;
+    http::check(r).map_err(HttpError::ebird)?;
+    Ok("accepted by eBird".to_string())
+}
+
+/// Log in to Bluesky with the handle and password, without saving the session
+fn check_bluesky(config: &Config) -> Result<String, BirdError> {
+    let token = authenticate(config)?;
+    Ok(format!("logged in as {} ({})", config.handle, token.did))
+}
+
+/// Write and remove a file in every directory the bot keeps files in, creating them as a run would
+fn check_data_dirs(config: &Config) -> Result<String, BirdError> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for path in [&config.birds_path, &config.history_path, &config.session_path, &config.post_log_path, &config.lock_path] {
+        let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf();
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    for dir in &dirs {
+        let probe = dir.join(format!(".botd-verify-{}", process::id()));
+        fs::create_dir_all(dir).and_then(|_| fs::write(&probe, b"")).and_then(|_| fs::remove_file(&probe)).map_err(|e| {
+            BirdError::Io(std::io::Error::new(e.kind(), format!("unable to write to '{}': {}", dir.display(), e)))
+        })?;
+    }
+    let dirs: Vec<String> = dirs.iter().map(|d| format!("'{}'", d.display())).collect();
+    Ok(format!("{} writable", dirs.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_server::{Response, TestServer};
+
+    #[test]
+    fn each_check_passes_or_fails_on_its_own() {
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/v2/ref/taxonomy/ebird?fmt=json&species=norcar" if r.header("X-eBirdApiToken") == Some("good") =>
+                Response::json(200, json!([{"speciesCode": "norcar"}])),
+            "/v2/ref/taxonomy/ebird?fmt=json&species=norcar" => Response::new(403, ""),
+            "/xrpc/com.atproto.server.createSession" =>
+                Response::json(200, json!({"accessJwt": "jwt", "refreshJwt": "refresh", "did": "did:plc:test"})),
+            _ => Response::new(404, ""),
+        });
+        let dir = std::env::temp_dir().join(format!("botd-verify-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut config = Config {
+            ebird_api_key: "good".to_string(),
+            handle: "bird.bsky.social".to_string(),
+            ebird_api_url: server.url.clone(),
+            pds_url: server.url.clone(),
+            ..Config::default()
+        };
+        config.set_data_dir(&dir);
+
+        let checks = verify(&config);
+        assert!(checks.iter().all(Check::passed), "{:?}", checks);
+        assert_eq!(checks[1].result.as_ref().unwrap(), "logged in as bird.bsky.social (did:plc:test)");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        // A file where the data directory should be
+        fs::write(dir.join("file"), "").unwrap();
+        config.ebird_api_key = "bad".to_string();
+        config.set_data_dir(&dir.join("file"));
+        let checks = verify(&config);
+        assert_eq!(checks.iter().map(Check::passed).collect::<Vec<_>>(), [false, true, false]);
+        assert!(checks[0].hint().unwrap().contains("EBIRD_API_KEY"));
+        assert!(checks[2].hint().unwrap().contains("--data-dir"));
+    }
+
+    #[test]
+    fn handles_look_like_domain_names() {
+        assert_eq!(handle_problem("bird.bsky.social"), None);
+        assert_eq!(handle_problem("did:plc:abc123"), None);
+        assert!(handle_problem("@bird.bsky.social").unwrap().contains("'@'"));
+        assert!(handle_problem("bird").is_some());
+        assert!(handle_problem("birder@example.com").is_some());
+    }
+}